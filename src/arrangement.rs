@@ -0,0 +1,572 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::bvh::Aabb;
+use crate::cut_triangle::CutTriangle;
+use crate::intersect::{dominant_axis, project};
+use crate::mesh::Mesh;
+use crate::predicates::{orient2d, orient3d, Exact};
+use crate::sdf::WindingNumbers;
+use crate::vec3::Vec3;
+
+pub(crate) type Edge = (u32, u32);
+
+pub(crate) fn edge(a: u32, b: u32) -> Edge {
+	(a.min(b), a.max(b))
+}
+
+/// A point where the meshes meet, named after the features it lies on rather
+/// than by its coordinates, so that every triangle that finds it agrees on it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum Key {
+	Vertex(u32),
+	/// An edge through the inside of a triangle
+	EdgeFace(Edge, u32),
+	/// Two edges crossing, the first mesh's first
+	EdgeEdge(Edge, Edge),
+}
+
+/// A piece of a cut up triangle, as triangles.
+pub(crate) type Face = Vec<[Key; 3]>;
+
+/// A piece of a segment where the meshes meet, between two points on it.
+pub(crate) type Cut = (Key, Key);
+
+/// Where a point lies on a triangle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Location {
+	Corner(usize),
+	/// On the edge from corner k to corner k + 1
+	Edge(usize),
+	Inside,
+}
+
+/// Where one triangle relative to the other mesh.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Status {
+	Inside,
+	Outside,
+	/// On a face of the other mesh facing the same way
+	Same,
+	/// On a face of the other mesh facing the other way
+	Opposite,
+}
+
+fn one_side(s: &[f64; 3]) -> bool {
+	(s[0] > 0.0 && s[1] > 0.0 && s[2] > 0.0) || (s[0] < 0.0 && s[1] < 0.0 && s[2] < 0.0)
+}
+
+fn same_sign(s: &[f64; 3]) -> bool {
+	(s[0] >= 0.0 && s[1] >= 0.0 && s[2] >= 0.0) || (s[0] <= 0.0 && s[1] <= 0.0 && s[2] <= 0.0)
+}
+
+/// The largest coordinate of `p` in magnitude.
+fn magnitude(p: Vec3) -> f64 {
+	p.x.abs().max(p.y.abs()).max(p.z.abs())
+}
+
+/// The coordinates `project` keeps when it drops axis `drop`.
+fn kept(drop: usize) -> (usize, usize) {
+	((drop + 1) % 3, (drop + 2) % 3)
+}
+
+/// A point where the meshes meet, held exactly as (x, y, z) / w with w > 0.
+/// Rounding it could move it off the edge or across the segment it's on as
+/// seen from one of the triangles it's on, and leave the pieces on either
+/// side of the cut disagreeing.
+#[derive(Clone, Debug)]
+pub(crate) struct Point {
+	/// x, y, z and w
+	coords: [Exact; 4],
+	/// The point rounded, for the output and to try the predicates on
+	pub approx: Vec3,
+	/// How far `approx` can be off in each coordinate
+	error: f64,
+	/// Set for vertices of the meshes, which the faster predicates handle
+	vertex: Option<Vec3>,
+}
+
+impl Point {
+	pub fn vertex(p: Vec3) -> Point {
+		Point {
+			coords: [Exact::new(p.x), Exact::new(p.y), Exact::new(p.z), Exact::new(1.0)],
+			approx: p,
+			error: 0.0,
+			vertex: Some(p),
+		}
+	}
+
+	/// p + (q - p) * s / d, for d other than zero.
+	fn along(p: Vec3, q: Vec3, s: Exact, d: Exact) -> Point {
+		let (s, d) = if d.sign() < 0.0 { (-&s, -&d) } else { (s, d) };
+		let approx = p.lerp(q, s.approx() / d.approx());
+		// The point is on the segment, and the quotient is within a few
+		// roundings, so this is generous
+		let error = 32.0 * f64::EPSILON * (magnitude(p) + magnitude(q));
+		let coord = |i: usize| &(&Exact::new(p[i]) * &d) + &(&Exact::diff(q[i], p[i]) * &s);
+		Point {
+			coords: [coord(0), coord(1), coord(2), d],
+			approx,
+			error,
+			vertex: None,
+		}
+	}
+
+	/// A point inside the triangle a, b, c.
+	fn centroid(a: &Point, b: &Point, c: &Point) -> Point {
+		let [wa, wb, wc] = [a, b, c].map(|p| &p.coords[3]);
+		let (wbc, wac, wab) = (wb * wc, wa * wc, wa * wb);
+		let coord = |i: usize| &(&(&a.coords[i] * &wbc) + &(&b.coords[i] * &wac)) + &(&c.coords[i] * &wab);
+		let approx = (a.approx + b.approx + c.approx) / 3.0;
+		Point {
+			coords: [coord(0), coord(1), coord(2), &(&Exact::new(3.0) * wa) * &wbc],
+			approx,
+			error: a.error + b.error + c.error + 4.0 * f64::EPSILON * magnitude(approx),
+			vertex: None,
+		}
+	}
+
+	/// Orders points by x, then y, then z, which along any line is the order
+	/// from one end to the other.
+	pub fn compare(&self, other: &Point) -> Ordering {
+		for i in 0..3 {
+			let order = match (self.vertex, other.vertex) {
+				(Some(p), Some(q)) => p[i].total_cmp(&q[i]),
+				_ if (self.approx[i] - other.approx[i]).abs() > 2.0 * (self.error + other.error) => {
+					self.approx[i].total_cmp(&other.approx[i])
+				}
+				_ => {
+					let d = &(&self.coords[i] * &other.coords[3]) - &(&other.coords[i] * &self.coords[3]);
+					d.sign().total_cmp(&0.0)
+				}
+			};
+			if order != Ordering::Equal {
+				return order;
+			}
+		}
+		Ordering::Equal
+	}
+}
+
+/// Positive if a, b and c are counterclockwise as `project` sees them, like
+/// `orient2d`.
+pub(crate) fn orient(a: &Point, b: &Point, c: &Point, drop: usize) -> f64 {
+	let (i, j) = kept(drop);
+	if let (Some(a), Some(b), Some(c)) = (a.vertex, b.vertex, c.vertex) {
+		return orient2d(a[i], a[j], b[i], b[j], c[i], c[j]);
+	}
+	// Try the rounded points first, allowing for how far off they can be
+	let (ax, ay, bx, by) = (
+		a.approx[i] - c.approx[i],
+		a.approx[j] - c.approx[j],
+		b.approx[i] - c.approx[i],
+		b.approx[j] - c.approx[j],
+	);
+	let (left, right) = (ax * by, ay * bx);
+	let (ea, eb) = (a.error + c.error, b.error + c.error);
+	let bound = ea * (bx.abs() + by.abs()) + eb * (ax.abs() + ay.abs()) + 2.0 * ea * eb;
+	if (left - right).abs() > 2.0 * bound + 8.0 * f64::EPSILON * (left.abs() + right.abs()) {
+		return left - right;
+	}
+	let minor = |x: usize, y: usize| &(&b.coords[x] * &c.coords[y]) - &(&b.coords[y] * &c.coords[x]);
+	let det = &(&(&a.coords[i] * &minor(j, 3)) - &(&a.coords[j] * &minor(i, 3))) + &(&a.coords[3] * &minor(i, j));
+	det.sign()
+}
+
+/// Both meshes in one, with coincident vertices merged, and the points and
+/// segments where their triangles meet.
+pub(crate) struct Arrangement {
+	pub mesh: Mesh,
+	/// Index of the second mesh's first triangle
+	pub first_b: usize,
+	pub keys: Vec<Vec<Key>>,
+	pub segments: Vec<Vec<(Key, Key)>>,
+	/// Triangles of the other mesh in the same plane that overlap each one
+	coplanar: Vec<Vec<u32>>,
+}
+
+impl Arrangement {
+	pub fn new(a: &Mesh, b: &Mesh) -> Arrangement {
+		let mut mesh = Mesh::default();
+		let mut welded = HashMap::<[u64; 3], u32>::new();
+		for part in [a, b] {
+			let ids: Vec<u32> = part
+				.positions
+				.iter()
+				.map(|p| {
+					*welded
+						.entry([p.x.to_bits(), p.y.to_bits(), p.z.to_bits()])
+						.or_insert_with(|| {
+							mesh.positions.push(*p);
+							mesh.positions.len() as u32 - 1
+						})
+				})
+				.collect();
+			mesh
+				.triangles
+				.extend(part.triangles.iter().map(|t| t.map(|v| ids[v as usize])));
+		}
+		let n = mesh.triangles.len();
+		Arrangement {
+			mesh,
+			first_b: a.triangles.len(),
+			keys: vec![Vec::new(); n],
+			segments: vec![Vec::new(); n],
+			coplanar: vec![Vec::new(); n],
+		}
+	}
+
+	fn edges(&self, t: usize) -> [Edge; 3] {
+		let tri = self.mesh.triangles[t];
+		[0, 1, 2].map(|k| edge(tri[k], tri[(k + 1) % 3]))
+	}
+
+	/// Where a point is, exactly.
+	pub fn point(&self, key: Key) -> Point {
+		let p = |v: u32| self.mesh.positions[v as usize];
+		let diff = |q: Vec3, r: Vec3| [0, 1, 2].map(|i| Exact::diff(q[i], r[i]));
+		match key {
+			Key::Vertex(v) => Point::vertex(p(v)),
+			Key::EdgeFace((s, t), f) => {
+				// Where the edge meets the plane of the face
+				let [a, b, c] = self.mesh.corners(f as usize);
+				let (ab, ac) = (diff(b, a), diff(c, a));
+				let n = [0, 1, 2].map(|i| {
+					let (j, k) = kept(i);
+					&(&ab[j] * &ac[k]) - &(&ab[k] * &ac[j])
+				});
+				let dot = |q: Vec3| {
+					let d = diff(q, p(s));
+					(0..3).fold(Exact::new(0.0), |sum, i| &sum + &(&n[i] * &d[i]))
+				};
+				Point::along(p(s), p(t), dot(a), dot(p(t)))
+			}
+			Key::EdgeEdge((a0, a1), (b0, b1)) => {
+				// Where the lines meet, as seen along an axis they don't look
+				// parallel from
+				let (u, v, w) = (diff(p(a1), p(a0)), diff(p(b1), p(b0)), diff(p(b0), p(a0)));
+				let cross = (p(a1) - p(a0)).cross(p(b1) - p(b0));
+				let first = dominant_axis(cross);
+				for drop in [first, (first + 1) % 3, (first + 2) % 3] {
+					let (i, j) = kept(drop);
+					let d = &(&u[i] * &v[j]) - &(&u[j] * &v[i]);
+					if d.sign() != 0.0 {
+						let s = &(&w[i] * &v[j]) - &(&w[j] * &v[i]);
+						return Point::along(p(a0), p(a1), s, d);
+					}
+				}
+				Point::vertex(p(a0))
+			}
+		}
+	}
+
+	/// Projects onto the coordinate plane a triangle is most parallel to, or
+	/// failing that one it doesn't look like a line from.
+	fn drop_axis(&self, t: usize) -> usize {
+		let first = dominant_axis(self.mesh.face_cross(t));
+		let [a, b, c] = self.mesh.corners(t);
+		[first, (first + 1) % 3, (first + 2) % 3]
+			.iter()
+			.copied()
+			.find(|&drop| {
+				let [a, b, c] = [a, b, c].map(|q| project(q, drop));
+				orient2d(a.0, a.1, b.0, b.1, c.0, c.1) != 0.0
+			})
+			.unwrap_or(first)
+	}
+
+	/// Whether `p`, in the plane of triangle `t`, is in it or on its boundary.
+	fn contains(&self, t: usize, p: &Point) -> bool {
+		let drop = self.drop_axis(t);
+		let [a, b, c] = self.mesh.corners(t).map(Point::vertex);
+		same_sign(&[
+			orient(&a, &b, p, drop),
+			orient(&b, &c, p, drop),
+			orient(&c, &a, p, drop),
+		])
+	}
+
+	fn locate(&self, t: usize, key: Key) -> Location {
+		let tri = self.mesh.triangles[t];
+		let edges = self.edges(t);
+		let on_edge = |e: Edge| {
+			edges
+				.iter()
+				.position(|&f| f == e)
+				.map_or(Location::Inside, Location::Edge)
+		};
+		match key {
+			Key::Vertex(v) => {
+				if let Some(k) = tri.iter().position(|&c| c == v) {
+					return Location::Corner(k);
+				}
+				let drop = self.drop_axis(t);
+				let [a, b, c] = self.mesh.corners(t).map(|q| project(q, drop));
+				let p = project(self.mesh.positions[v as usize], drop);
+				let sides = [
+					orient2d(a.0, a.1, b.0, b.1, p.0, p.1),
+					orient2d(b.0, b.1, c.0, c.1, p.0, p.1),
+					orient2d(c.0, c.1, a.0, a.1, p.0, p.1),
+				];
+				sides
+					.iter()
+					.position(|&s| s == 0.0)
+					.map_or(Location::Inside, Location::Edge)
+			}
+			Key::EdgeFace(e, f) if f as usize != t => on_edge(e),
+			Key::EdgeFace(..) => Location::Inside,
+			Key::EdgeEdge(ea, eb) => match on_edge(ea) {
+				Location::Inside => on_edge(eb),
+				found => found,
+			},
+		}
+	}
+
+	/// Features of triangle `o` that lie on triangle `t`, given the sides of
+	/// the plane of `t` the corners of `o` are on.
+	fn crossings(&self, t: usize, o: usize, sides: &[f64; 3], found: &mut Vec<Key>) {
+		let (ti, oi) = (self.mesh.triangles[t], self.mesh.triangles[o]);
+		let (p, q) = (self.mesh.corners(t), self.mesh.corners(o));
+		let t_edges = self.edges(t);
+		for i in 0..3 {
+			if sides[i] == 0.0 && self.contains(t, &Point::vertex(q[i])) {
+				found.push(Key::Vertex(oi[i]));
+			}
+			let j = (i + 1) % 3;
+			if !(sides[i] > 0.0 && sides[j] < 0.0 || sides[i] < 0.0 && sides[j] > 0.0) {
+				continue;
+			}
+			// The side of the edge's line each edge of t passes, which is the
+			// same for all three if the edge goes through t
+			let e = [0, 1, 2].map(|k| orient3d(q[i], q[j], p[k], p[(k + 1) % 3]));
+			if !same_sign(&e) {
+				continue;
+			}
+			let o_edge = edge(oi[i], oi[j]);
+			let zeros: Vec<usize> = (0..3).filter(|&k| e[k] == 0.0).collect();
+			found.push(match zeros[..] {
+				[] => Key::EdgeFace(o_edge, t as u32),
+				[k] if t < self.first_b => Key::EdgeEdge(t_edges[k], o_edge),
+				[k] => Key::EdgeEdge(o_edge, t_edges[k]),
+				// Through the corner between the two edges
+				[0, 1] => Key::Vertex(ti[1]),
+				[1, 2] => Key::Vertex(ti[2]),
+				[0, 2] => Key::Vertex(ti[0]),
+				_ => continue,
+			});
+		}
+	}
+
+	fn add(&mut self, t: usize, keys: &[Key], segments: &[(Key, Key)]) {
+		self.keys[t].extend_from_slice(keys);
+		self.segments[t].extend_from_slice(segments);
+	}
+
+	/// Records where triangle `ta` of the first mesh meets `tb` of the second.
+	pub fn intersect(&mut self, ta: usize, tb: usize) {
+		let (pa, pb) = (self.mesh.corners(ta), self.mesh.corners(tb));
+		let sb = pb.map(|p| orient3d(pa[0], pa[1], pa[2], p));
+		let sa = pa.map(|p| orient3d(pb[0], pb[1], pb[2], p));
+		if one_side(&sb) || one_side(&sa) {
+			return;
+		}
+		if sb.iter().all(|&s| s == 0.0) {
+			self.intersect_coplanar(ta, tb);
+			return;
+		}
+		let mut found = Vec::new();
+		self.crossings(ta, tb, &sb, &mut found);
+		self.crossings(tb, ta, &sa, &mut found);
+		found.sort_unstable();
+		found.dedup();
+		// In order along the line where the planes meet
+		let mut along: Vec<(Point, Key)> = found.iter().map(|&k| (self.point(k), k)).collect();
+		along.sort_by(|a, b| a.0.compare(&b.0));
+		let segments: Vec<(Key, Key)> = along.windows(2).map(|w| (w[0].1, w[1].1)).collect();
+		self.add(ta, &found, &segments);
+		self.add(tb, &found, &segments);
+	}
+
+	fn intersect_coplanar(&mut self, ta: usize, tb: usize) {
+		let (ia, ib) = (self.mesh.triangles[ta], self.mesh.triangles[tb]);
+		let (pa, pb) = (self.mesh.corners(ta), self.mesh.corners(tb));
+		let drop = self.drop_axis(ta);
+		let (qa, qb) = (pa.map(|p| project(p, drop)), pb.map(|p| project(p, drop)));
+		let orient = |a: (f64, f64), b: (f64, f64), c: (f64, f64)| orient2d(a.0, a.1, b.0, b.1, c.0, c.1);
+
+		let mut found = Vec::new();
+		for i in 0..3 {
+			if self.contains(ta, &Point::vertex(pb[i])) {
+				found.push(Key::Vertex(ib[i]));
+			}
+			if self.contains(tb, &Point::vertex(pa[i])) {
+				found.push(Key::Vertex(ia[i]));
+			}
+		}
+		let (edges_a, edges_b) = (self.edges(ta), self.edges(tb));
+		for i in 0..3 {
+			let (a0, a1) = (qa[i], qa[(i + 1) % 3]);
+			for j in 0..3 {
+				let (b0, b1) = (qb[j], qb[(j + 1) % 3]);
+				let (d0, d1) = (orient(a0, a1, b0), orient(a0, a1, b1));
+				let (d2, d3) = (orient(b0, b1, a0), orient(b0, b1, a1));
+				if d0 * d1 < 0.0 && d2 * d3 < 0.0 {
+					found.push(Key::EdgeEdge(edges_a[i], edges_b[j]));
+				}
+			}
+		}
+		found.sort_unstable();
+		found.dedup();
+		if found.is_empty() {
+			return;
+		}
+
+		// Each triangle is cut along the other's edges where they cross it,
+		// between the points found on them, which are all on both triangles
+		for (t, o) in [(ta, tb), (tb, ta)] {
+			let mut segments = Vec::new();
+			for (k, &e) in self.edges(o).iter().enumerate() {
+				let mut on: Vec<(Point, Key)> = found
+					.iter()
+					.filter(|&&key| match key {
+						Key::Vertex(v) => v == e.0 || v == e.1 || self.locate(o, key) == Location::Edge(k),
+						Key::EdgeEdge(ea, eb) => ea == e || eb == e,
+						Key::EdgeFace(..) => false,
+					})
+					.map(|&key| (self.point(key), key))
+					.collect();
+				on.sort_by(|a, b| a.0.compare(&b.0));
+				segments.extend(on.windows(2).map(|w| (w[0].1, w[1].1)));
+			}
+			self.add(t, &found, &segments);
+		}
+		self.coplanar[ta].push(tb as u32);
+		self.coplanar[tb].push(ta as u32);
+	}
+
+	/// Adds the points found on each edge to every triangle around the edge,
+	/// so that neighbours are split the same way.
+	pub fn share_edge_points(&mut self) {
+		let mut edge_triangles = HashMap::<Edge, Vec<usize>>::new();
+		for t in 0..self.mesh.triangles.len() {
+			for e in self.edges(t) {
+				edge_triangles.entry(e).or_default().push(t);
+			}
+		}
+		let mut on_edges = Vec::new();
+		for t in 0..self.mesh.triangles.len() {
+			self.keys[t].sort_unstable();
+			self.keys[t].dedup();
+			let edges = self.edges(t);
+			for &key in &self.keys[t] {
+				if let Location::Edge(k) = self.locate(t, key) {
+					on_edges.push((edges[k], key));
+				}
+			}
+		}
+		for (e, key) in on_edges {
+			for &t in &edge_triangles[&e] {
+				self.keys[t].push(key);
+			}
+		}
+		for keys in &mut self.keys {
+			keys.sort_unstable();
+			keys.dedup();
+		}
+	}
+
+	/// Triangulates triangle `t` with the points and segments on it.
+	/// Triangles are grouped by the face of the cut up triangle they fill,
+	/// which are each on one side of the other mesh. Also returns the
+	/// segments, in pieces between the points on them. Fails if a point or
+	/// segment can't be placed, as the faces on either side of it would run
+	/// together.
+	pub fn split(&self, t: usize, points: &HashMap<Key, Point>) -> Result<(Vec<Face>, Vec<Cut>), String> {
+		let corners: Vec<Key> = self.mesh.triangles[t].iter().map(|&v| Key::Vertex(v)).collect();
+		let mut local = corners.clone();
+		local.extend(self.keys[t].iter().filter(|k| !corners.contains(k)));
+		let index: HashMap<Key, usize> = local.iter().enumerate().map(|(i, &k)| (k, i)).collect();
+
+		let drop = self.drop_axis(t);
+		let at: Vec<&Point> = local.iter().map(|k| &points[k]).collect();
+		// The points on each edge, from one corner to the next
+		let mut inside = Vec::new();
+		let mut chains = [0, 1, 2].map(|k| vec![k]);
+		for (i, &key) in local.iter().enumerate().skip(3) {
+			match self.locate(t, key) {
+				Location::Edge(k) => chains[k].push(i),
+				_ => inside.push(i),
+			}
+		}
+		for (k, chain) in chains.iter_mut().enumerate() {
+			let (start, end) = (at[k], at[(k + 1) % 3]);
+			let towards = start.compare(end);
+			chain[1..].sort_by(|&i, &j| {
+				let order = at[i].compare(at[j]);
+				if towards == Ordering::Less {
+					order
+				} else {
+					order.reverse()
+				}
+			});
+			chain.push((k + 1) % 3);
+		}
+
+		let mut cut = CutTriangle::new(at, drop);
+		// Each edge with points on it is fanned out to from the corner
+		// opposite it in the triangle it's on, which needs no tests
+		for chain in &chains {
+			if chain.len() > 2 {
+				cut.fan(chain);
+			}
+		}
+		// Points that coincide with one already there stand for it
+		let mut alias: Vec<usize> = (0..local.len()).collect();
+		for p in inside {
+			alias[p] = cut
+				.insert(p)
+				.ok_or_else(|| format!("Intersection point {:?} is off triangle {}", local[p], t))?;
+		}
+		for &(p, q) in &self.segments[t] {
+			if !cut.insert_segment(alias[index[&p]], alias[index[&q]]) {
+				return Err(format!("Couldn't cut triangle {} from {:?} to {:?}", t, p, q));
+			}
+		}
+
+		let faces = cut
+			.faces()
+			.iter()
+			.map(|face| face.iter().map(|tri| tri.map(|i| local[i])).collect())
+			.collect();
+		let cuts = cut.fixed().iter().map(|&(i, j)| (local[i], local[j])).collect();
+		Ok((faces, cuts))
+	}
+
+	/// Where a piece of triangle `t` is relative to the other mesh, going by
+	/// one of its triangles.
+	pub fn status(&self, t: usize, [a, b, c]: [&Point; 3], other: &WindingNumbers) -> Status {
+		let centroid = Point::centroid(a, b, c);
+		// Boxes rule out most of the triangles before the exact test, as a
+		// face can be coplanar with many small ones
+		let spread = Vec3::new(centroid.error, centroid.error, centroid.error);
+		let around = Aabb {
+			min: centroid.approx - spread,
+			max: centroid.approx + spread,
+		};
+		for &o in &self.coplanar[t] {
+			let o = o as usize;
+			if Aabb::of_triangle(&self.mesh, o).overlaps(&around) && self.contains(o, &centroid) {
+				return if self.mesh.face_cross(t).dot(self.mesh.face_cross(o)) > 0.0 {
+					Status::Same
+				} else {
+					Status::Opposite
+				};
+			}
+		}
+		if other.at((a.approx + b.approx + c.approx) / 3.0) >= 0.5 {
+			Status::Inside
+		} else {
+			Status::Outside
+		}
+	}
+}
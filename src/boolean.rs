@@ -0,0 +1,395 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
+
+use wasm_bindgen::prelude::*;
+
+use crate::arrangement::{edge, Arrangement, Edge, Key, Point, Status};
+use crate::bvh::{Aabb, Bvh};
+use crate::mesh::{Mesh, MeshBuffers};
+use crate::sdf::WindingNumbers;
+use crate::vec3::Vec3;
+
+/// How two solids are combined.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BooleanOperation {
+	/// Everything inside either mesh.
+	Union = 0,
+	/// Everything inside the first mesh but not the second.
+	Difference = 1,
+	/// Everything inside both meshes.
+	Intersection = 2,
+}
+
+/// The root of `x` in a union-find forest, for joining faces into patches.
+fn root(parent: &mut [usize], mut x: usize) -> usize {
+	while parent[x] != x {
+		parent[x] = parent[parent[x]];
+		x = parent[x];
+	}
+	x
+}
+
+/// Combines two closed meshes after Laidlaw et al., "Constructive Solid
+/// Geometry for Polyhedral Objects": triangles are split where the meshes
+/// meet, each piece is classified as inside or outside the other mesh, or on
+/// a face of it, and the pieces the operation keeps are joined up. Where
+/// triangles meet is decided with exact predicates, and the points where they
+/// do are kept exact until they're output, so the pieces on both sides of a
+/// cut share their vertices and edges.
+pub(crate) fn boolean(a: &Mesh, b: &Mesh, operation: BooleanOperation) -> Result<Mesh, String> {
+	let mut arrangement = Arrangement::new(a, b);
+	let n = arrangement.mesh.triangles.len();
+	let degenerate: Vec<bool> = (0..n).map(|t| arrangement.mesh.face_cross(t) == Vec3::ZERO).collect();
+	let boxes: Vec<Aabb> = (0..n).map(|t| Aabb::of_triangle(&arrangement.mesh, t)).collect();
+	let mut pairs = Vec::new();
+	Bvh::build(&boxes).self_overlaps(&boxes, |s, t| {
+		let (s, t) = (s.min(t) as usize, s.max(t) as usize);
+		if s < arrangement.first_b && t >= arrangement.first_b && !degenerate[s] && !degenerate[t] {
+			pairs.push((s, t));
+		}
+		ControlFlow::Continue(())
+	});
+	for (ta, tb) in pairs {
+		arrangement.intersect(ta, tb);
+	}
+	arrangement.share_edge_points();
+	assemble(&arrangement, a, b, operation)
+}
+
+/// Splits the triangles of `a` and `b`, found where they meet in
+/// `arrangement`, and joins up the pieces `operation` keeps.
+fn assemble(arrangement: &Arrangement, a: &Mesh, b: &Mesh, operation: BooleanOperation) -> Result<Mesh, String> {
+	let n = arrangement.mesh.triangles.len();
+	let degenerate: Vec<bool> = (0..n).map(|t| arrangement.mesh.face_cross(t) == Vec3::ZERO).collect();
+	let mut positions = arrangement.mesh.positions.clone();
+	let mut ids = HashMap::<Key, u32>::new();
+	let mut points = HashMap::<Key, Point>::new();
+	for (v, &p) in positions.iter().enumerate() {
+		ids.insert(Key::Vertex(v as u32), v as u32);
+		points.insert(Key::Vertex(v as u32), Point::vertex(p));
+	}
+	for keys in &arrangement.keys {
+		for &key in keys {
+			if let Entry::Vacant(slot) = points.entry(key) {
+				let point = slot.insert(arrangement.point(key));
+				ids.insert(key, positions.len() as u32);
+				positions.push(point.approx);
+			}
+		}
+	}
+
+	// Each face of a cut up triangle is classified on its own, but all the
+	// faces of a patch of one mesh between cuts are on the same side, so the
+	// most reliable classification in the patch goes for all of them
+	let (winding_a, winding_b) = (WindingNumbers::new(a), WindingNumbers::new(b));
+	let mut faces = Vec::new();
+	let mut cuts = HashSet::<Edge>::new();
+	for (t, &degenerate) in degenerate.iter().enumerate() {
+		if degenerate {
+			continue;
+		}
+		let tri = arrangement.mesh.triangles[t].map(Key::Vertex);
+		let pieces = if arrangement.keys[t].iter().all(|k| tri.contains(k)) && arrangement.segments[t].is_empty() {
+			vec![vec![tri]]
+		} else {
+			let (pieces, fixed) = arrangement.split(t, &points)?;
+			cuts.extend(fixed.iter().map(|(p, q)| edge(ids[p], ids[q])));
+			pieces
+		};
+		let in_a = t < arrangement.first_b;
+		let other = if in_a { &winding_b } else { &winding_a };
+		for piece in pieces {
+			// Pieces along the cut can be slivers with their centroids right
+			// on the other mesh, so the largest one stands in for the face
+			let area = |tri: &[Key; 3]| {
+				let [a, b, c] = tri.map(|k| points[&k].approx);
+				(b - a).cross(c - a).length()
+			};
+			let Some(largest) = piece.iter().max_by(|x, y| area(x).total_cmp(&area(y))) else {
+				continue;
+			};
+			let [p, q, r] = largest.map(|k| &points[&k]);
+			let status = arrangement.status(t, [p, q, r], other);
+			let triangles: Vec<[u32; 3]> = piece.iter().map(|tri| tri.map(|k| ids[&k])).collect();
+			faces.push((in_a, status, area(largest), triangles));
+		}
+	}
+
+	let mut parent: Vec<usize> = (0..faces.len()).collect();
+	let mut by_edge = HashMap::<(bool, Edge), usize>::new();
+	for (f, (in_a, _, _, triangles)) in faces.iter().enumerate() {
+		for tri in triangles {
+			for k in 0..3 {
+				let e = edge(tri[k], tri[(k + 1) % 3]);
+				if cuts.contains(&e) {
+					continue;
+				}
+				let g = *by_edge.entry((*in_a, e)).or_insert(f);
+				let (rf, rg) = (root(&mut parent, f), root(&mut parent, g));
+				parent[rf] = rg;
+			}
+		}
+	}
+	let mut patch_status = HashMap::<usize, (f64, Status)>::new();
+	for (f, &(_, status, weight, _)) in faces.iter().enumerate() {
+		let best = patch_status.entry(root(&mut parent, f)).or_insert((weight, status));
+		if weight > best.0 {
+			*best = (weight, status);
+		}
+	}
+
+	let mut result = Mesh {
+		positions,
+		triangles: Vec::new(),
+	};
+	for (f, (in_a, _, _, triangles)) in faces.iter().enumerate() {
+		let status = patch_status[&root(&mut parent, f)].1;
+		let (keep, flip) = match (operation, *in_a, status) {
+			(BooleanOperation::Union, _, Status::Outside) => (true, false),
+			(BooleanOperation::Union, true, Status::Same) => (true, false),
+			(BooleanOperation::Intersection, _, Status::Inside) => (true, false),
+			(BooleanOperation::Intersection, true, Status::Same) => (true, false),
+			(BooleanOperation::Difference, true, Status::Outside | Status::Opposite) => (true, false),
+			(BooleanOperation::Difference, false, Status::Inside) => (true, true),
+			_ => (false, false),
+		};
+		if keep {
+			result
+				.triangles
+				.extend(triangles.iter().map(|&p| if flip { [p[0], p[2], p[1]] } else { p }));
+		}
+	}
+	let all: Vec<u32> = (0..result.triangles.len() as u32).collect();
+	Ok(result.submesh(&all))
+}
+
+fn is_closed(mesh: &Mesh) -> bool {
+	mesh.edge_faces().values().all(|faces| faces.len() % 2 == 0)
+}
+
+pub(crate) fn mesh_boolean_impl(
+	a_vertices: &[f32],
+	a_indices: &[u32],
+	b_vertices: &[f32],
+	b_indices: &[u32],
+	operation: BooleanOperation,
+) -> Result<MeshBuffers, String> {
+	let a = Mesh::from_buffers(a_vertices, a_indices)?;
+	let b = Mesh::from_buffers(b_vertices, b_indices)?;
+	if !is_closed(&a) || !is_closed(&b) {
+		return Err(String::from("Both meshes must be closed"));
+	}
+	Ok(boolean(&a, &b, operation)?.to_buffers())
+}
+
+/// Combine two closed meshes from `parseSTLMesh` into one: their union, to
+/// merge parts, the first with the second cut out of it, e.g. to engrave or
+/// drill holes, or their intersection. Faces the meshes share are kept once,
+/// or not at all if they face each other. The result is closed, in the same
+/// layout.
+#[wasm_bindgen(js_name = "meshBoolean")]
+pub fn mesh_boolean(
+	a_vertices: &[f32],
+	a_indices: &[u32],
+	b_vertices: &[f32],
+	b_indices: &[u32],
+	operation: BooleanOperation,
+) -> Result<MeshBuffers, JsValue> {
+	mesh_boolean_impl(a_vertices, a_indices, b_vertices, b_indices, operation).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::linalg::{axis_angle, mul_vec3};
+
+	fn assert_closed(mesh: &Mesh) {
+		for (edge, faces) in mesh.edge_faces() {
+			assert_eq!(faces.len(), 2, "edge {:?}", edge);
+		}
+	}
+
+	fn combine(a: &Mesh, b: &Mesh, operation: BooleanOperation) -> Mesh {
+		let (av, _, ai, _) = a.parsed();
+		let (bv, _, bi, _) = b.parsed();
+		let buffers = mesh_boolean_impl(&av, &ai, &bv, &bi, operation).unwrap();
+		let mesh = Mesh::from_buffers(&buffers.vertices(), &buffers.v_indices()).unwrap();
+		assert_closed(&mesh);
+		mesh
+	}
+
+	fn stretched(min: Vec3, size: Vec3) -> Mesh {
+		let mut mesh = Mesh::tessellated_cube(Vec3::ZERO, 1.0, 2);
+		mesh.positions.iter_mut().for_each(|p| {
+			*p = min + Vec3::new(p.x * size.x, p.y * size.y, p.z * size.z);
+		});
+		mesh
+	}
+
+	#[test]
+	fn overlapping_boxes() {
+		let a = Mesh::tessellated_cube(Vec3::ZERO, 1.0, 2);
+		let b = stretched(Vec3::new(0.5, 0.25, 0.3), Vec3::new(1.0, 1.0, 1.0));
+		// The overlap is 0.5 x 0.75 x 0.7
+		let overlap = 0.2625;
+		for (operation, volume) in [
+			(BooleanOperation::Union, 2.0 - overlap),
+			(BooleanOperation::Difference, 1.0 - overlap),
+			(BooleanOperation::Intersection, overlap),
+		] {
+			let result = combine(&a, &b, operation);
+			// Off by the rounding of the corners to f32
+			assert!(
+				(result.volume() - volume).abs() < 1e-6,
+				"{:?} {}",
+				operation,
+				result.volume()
+			);
+		}
+	}
+
+	#[test]
+	fn finely_cut_face() {
+		// The bottom of each big triangle of the cube is cut by hundreds of
+		// small triangles flush with it
+		let a = Mesh::cube(Vec3::ZERO, 1.0);
+		let mut b = Mesh::tessellated_cube(Vec3::ZERO, 1.0, 16);
+		b.positions.iter_mut().for_each(|p| {
+			*p = Vec3::new(0.1 + 0.8 * p.x, 0.13 + 0.8 * p.y, 1.5 * p.z);
+		});
+		let result = combine(&a, &b, BooleanOperation::Difference);
+		assert!((result.volume() - 0.36).abs() < 1e-6, "{}", result.volume());
+	}
+
+	#[test]
+	fn shared_faces() {
+		// Flush on four sides
+		let a = Mesh::tessellated_cube(Vec3::ZERO, 1.0, 2);
+		let b = stretched(Vec3::new(0.5, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+		for (operation, volume) in [
+			(BooleanOperation::Union, 1.5),
+			(BooleanOperation::Difference, 0.5),
+			(BooleanOperation::Intersection, 0.5),
+		] {
+			let result = combine(&a, &b, operation);
+			assert!(
+				(result.volume() - volume).abs() < 1e-9,
+				"{:?} {}",
+				operation,
+				result.volume()
+			);
+		}
+
+		// Side by side, with the faces between them dropped
+		let c = Mesh::cube(Vec3::new(1.0, 0.0, 0.0), 1.0);
+		let union = combine(&a, &c, BooleanOperation::Union);
+		assert!((union.volume() - 2.0).abs() < 1e-9);
+		let (lo, hi) = union.bounds();
+		assert_eq!((lo, hi), (Vec3::ZERO, Vec3::new(2.0, 1.0, 1.0)));
+		assert!(union
+			.positions
+			.iter()
+			.all(|p| p.x != 1.0 || p.y == 0.0 || p.y == 1.0 || p.z == 0.0 || p.z == 1.0));
+
+		// Edges that only meet up to rounding, as 1/3 isn't exact
+		let d = Mesh::tessellated_cube(Vec3::ZERO, 1.0, 3);
+		let e = Mesh::tessellated_cube(Vec3::new(1.0 / 3.0, 0.0, 0.0), 1.0, 2);
+		let difference = combine(&d, &e, BooleanOperation::Difference);
+		assert!(
+			(difference.volume() - 1.0 / 3.0).abs() < 1e-6,
+			"{}",
+			difference.volume()
+		);
+
+		// Crossings on edges of both meshes that aren't at representable
+		// points, unrounded
+		let f = Mesh::tessellated_cube(Vec3::new(1.0 / 3.0, 1.0 / 6.0, 0.0), 1.0, 2);
+		// The overlap is 2/3 x 5/6 x 1
+		let overlap = 5.0 / 9.0;
+		for (operation, volume) in [
+			(BooleanOperation::Union, 2.0 - overlap),
+			(BooleanOperation::Difference, 1.0 - overlap),
+			(BooleanOperation::Intersection, overlap),
+		] {
+			let result = boolean(&d, &f, operation).unwrap();
+			assert_closed(&result);
+			assert!(
+				(result.volume() - volume).abs() < 1e-9,
+				"{:?} {}",
+				operation,
+				result.volume()
+			);
+		}
+	}
+
+	#[test]
+	fn rotated_flush_faces() {
+		// Rounding the rotated corners to f32 leaves the faces between the
+		// cubes only nearly flush, and the points where their edges cross
+		// only nearly on the faces
+		for (k, w) in [
+			Vec3::new(0.3, -1.2, 0.8),
+			Vec3::new(2.0, 0.5, -0.7),
+			Vec3::new(-0.4, 0.9, 1.6),
+			Vec3::new(1.1, 1.3, -2.2),
+		]
+		.iter()
+		.enumerate()
+		{
+			let rotation = axis_angle(*w);
+			let mut a = Mesh::tessellated_cube(Vec3::ZERO, 1.0, 1 + k % 3);
+			let mut b = Mesh::tessellated_cube(Vec3::new(1.0, 0.3, -0.2), 1.0, 3 - k % 3);
+			for p in a.positions.iter_mut().chain(b.positions.iter_mut()) {
+				*p = mul_vec3(&rotation, *p);
+			}
+			for operation in [
+				BooleanOperation::Union,
+				BooleanOperation::Difference,
+				BooleanOperation::Intersection,
+			] {
+				combine(&a, &b, operation);
+			}
+		}
+	}
+
+	#[test]
+	fn spheres() {
+		let sphere = |center: Vec3| {
+			let mut mesh = Mesh::tessellated_cube(Vec3::new(-1.0, -1.0, -1.0), 2.0, 6);
+			mesh.positions.iter_mut().for_each(|p| *p = p.normalized() + center);
+			mesh
+		};
+		let rounded = |mesh: Mesh| {
+			let (vertices, _, v_indices, _) = mesh.parsed();
+			Mesh::from_buffers(&vertices, &v_indices).unwrap()
+		};
+		let a = rounded(sphere(Vec3::ZERO));
+		let b = rounded(sphere(Vec3::new(0.7, 0.3, 0.2)));
+		let union = combine(&a, &b, BooleanOperation::Union);
+		let intersection = combine(&a, &b, BooleanOperation::Intersection);
+		let difference = combine(&a, &b, BooleanOperation::Difference);
+		// The pieces add up, up to rounding the new vertices to f32
+		assert!((union.volume() + intersection.volume() - a.volume() - b.volume()).abs() < 1e-6);
+		assert!((difference.volume() + intersection.volume() - a.volume()).abs() < 1e-6);
+		assert!(intersection.volume() > 0.0 && intersection.volume() < a.volume());
+
+		let (av, _, ai, _) = a.parsed();
+		assert!(mesh_boolean_impl(&av, &ai[3..], &av, &ai, BooleanOperation::Union).is_err());
+	}
+
+	#[test]
+	fn point_off_triangle() {
+		let a = Mesh::cube(Vec3::ZERO, 1.0);
+		let b = Mesh::cube(Vec3::new(5.0, 0.0, 0.0), 1.0);
+		let mut arrangement = Arrangement::new(&a, &b);
+		// A cut to a point nowhere near the triangle can't be made, and
+		// leaving it out would classify both sides of it together
+		let corner = Key::Vertex(arrangement.mesh.triangles[0][0]);
+		let far = Key::Vertex(arrangement.mesh.triangles[arrangement.first_b][0]);
+		arrangement.keys[0].push(far);
+		arrangement.segments[0].push((corner, far));
+		assert!(assemble(&arrangement, &a, &b, BooleanOperation::Union).is_err());
+	}
+}
@@ -0,0 +1,412 @@
+use std::ops::ControlFlow;
+
+use crate::mesh::Mesh;
+use crate::vec3::Vec3;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Aabb {
+	pub min: Vec3,
+	pub max: Vec3,
+}
+
+impl Aabb {
+	pub const EMPTY: Aabb = Aabb {
+		min: Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+		max: Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+	};
+
+	pub fn of_triangle(mesh: &Mesh, t: usize) -> Aabb {
+		let [a, b, c] = mesh.corners(t);
+		Aabb {
+			min: a.min(b).min(c),
+			max: a.max(b).max(c),
+		}
+	}
+
+	pub fn union(&self, o: &Aabb) -> Aabb {
+		Aabb {
+			min: self.min.min(o.min),
+			max: self.max.max(o.max),
+		}
+	}
+
+	pub fn grow(&mut self, p: Vec3) {
+		self.min = self.min.min(p);
+		self.max = self.max.max(p);
+	}
+
+	pub fn overlaps(&self, o: &Aabb) -> bool {
+		self.min.x <= o.max.x
+			&& self.max.x >= o.min.x
+			&& self.min.y <= o.max.y
+			&& self.max.y >= o.min.y
+			&& self.min.z <= o.max.z
+			&& self.max.z >= o.min.z
+	}
+
+	pub fn center(&self) -> Vec3 {
+		(self.min + self.max) * 0.5
+	}
+
+	/// Distance along the ray `origin + t * dir` at which it enters the box,
+	/// if it does so before `max_t`. `inv_dir` holds the reciprocals of the
+	/// direction's components.
+	pub fn ray_entry(&self, origin: Vec3, inv_dir: Vec3, max_t: f64) -> Option<f64> {
+		let mut t0 = 0.0f64;
+		let mut t1 = max_t;
+		for axis in 0..3 {
+			if inv_dir[axis].is_infinite() {
+				// Parallel to the slab
+				if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+					return None;
+				}
+				continue;
+			}
+			let a = (self.min[axis] - origin[axis]) * inv_dir[axis];
+			let b = (self.max[axis] - origin[axis]) * inv_dir[axis];
+			t0 = t0.max(a.min(b));
+			t1 = t1.min(a.max(b));
+		}
+		if t0 <= t1 {
+			Some(t0)
+		} else {
+			None
+		}
+	}
+
+	/// Squared distance from `p` to the nearest point of the box.
+	pub fn distance_squared(&self, p: Vec3) -> f64 {
+		let d = (self.min - p).max(p - self.max).max(Vec3::ZERO);
+		d.dot(d)
+	}
+
+	pub fn surface_area(&self) -> f64 {
+		let d = self.max - self.min;
+		if d.x < 0.0 {
+			return 0.0;
+		}
+		2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+	}
+}
+
+/// A node of the hierarchy. Leaves reference `count` items starting at
+/// `start` in `Bvh::items`. Interior nodes have `count == 0` and their two
+/// children are stored next to each other starting at index `start`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BvhNode {
+	pub bounds: Aabb,
+	pub start: u32,
+	pub count: u32,
+}
+
+impl BvhNode {
+	pub fn is_leaf(&self) -> bool {
+		self.count > 0
+	}
+}
+
+/// Bounding volume hierarchy over a set of boxes, built with the binned
+/// surface area heuristic.
+pub(crate) struct Bvh {
+	pub nodes: Vec<BvhNode>,
+	pub items: Vec<u32>,
+}
+
+const MAX_LEAF_SIZE: usize = 4;
+const NUM_BINS: usize = 16;
+
+impl Bvh {
+	pub fn build(boxes: &[Aabb]) -> Bvh {
+		let centers: Vec<Vec3> = boxes.iter().map(|b| b.center()).collect();
+		let mut bvh = Bvh {
+			nodes: Vec::with_capacity(2 * boxes.len() / MAX_LEAF_SIZE + 1),
+			items: (0..boxes.len() as u32).collect(),
+		};
+		if boxes.is_empty() {
+			return bvh;
+		}
+		let mut bins = vec![0u8; boxes.len()];
+
+		let empty = BvhNode {
+			bounds: Aabb::EMPTY,
+			start: 0,
+			count: 0,
+		};
+		bvh.nodes.push(empty);
+		// Ranges still to be split, with the node they belong to
+		let mut stack = vec![Range::of_items(0, 0, boxes.len(), &bvh.items, boxes, &centers)];
+		while let Some(range) = stack.pop() {
+			let node = range.node;
+			bvh.nodes[node].bounds = range.bounds;
+			let items = &mut bvh.items[range.start..range.end];
+			let halves = if items.len() <= MAX_LEAF_SIZE {
+				None
+			} else {
+				split(items, boxes, &centers, &range, &mut bins)
+			};
+			match halves {
+				None => {
+					bvh.nodes[node].start = range.start as u32;
+					bvh.nodes[node].count = items.len() as u32;
+				}
+				Some((mid, left_bounds, right_bounds)) => {
+					let left = bvh.nodes.len();
+					bvh.nodes.push(empty);
+					bvh.nodes.push(empty);
+					bvh.nodes[node].start = left as u32;
+					let mid = range.start + mid;
+					let (left_range, right_range) = match (left_bounds, right_bounds) {
+						(Some(l), Some(r)) => (
+							Range {
+								node: left,
+								start: range.start,
+								end: mid,
+								bounds: l.0,
+								center_bounds: l.1,
+							},
+							Range {
+								node: left + 1,
+								start: mid,
+								end: range.end,
+								bounds: r.0,
+								center_bounds: r.1,
+							},
+						),
+						_ => (
+							Range::of_items(left, range.start, mid, &bvh.items, boxes, &centers),
+							Range::of_items(left + 1, mid, range.end, &bvh.items, boxes, &centers),
+						),
+					};
+					stack.push(right_range);
+					stack.push(left_range);
+				}
+			}
+		}
+		bvh
+	}
+
+	pub fn children(&self, node: usize) -> (usize, usize) {
+		let left = self.nodes[node].start as usize;
+		(left, left + 1)
+	}
+
+	pub fn leaf_items(&self, node: usize) -> &[u32] {
+		let n = &self.nodes[node];
+		&self.items[n.start as usize..(n.start + n.count) as usize]
+	}
+
+	/// Calls `f` with every pair of distinct items whose boxes overlap, until
+	/// it breaks. Each unordered pair is reported once.
+	pub fn self_overlaps<F: FnMut(u32, u32) -> ControlFlow<()>>(&self, boxes: &[Aabb], mut f: F) {
+		if self.nodes.is_empty() {
+			return;
+		}
+		let mut stack = vec![(0usize, 0usize)];
+		while let Some((a, b)) = stack.pop() {
+			let na = &self.nodes[a];
+			let nb = &self.nodes[b];
+			if a == b {
+				if na.is_leaf() {
+					let items = self.leaf_items(a);
+					for (i, &x) in items.iter().enumerate() {
+						for &y in &items[i + 1..] {
+							if boxes[x as usize].overlaps(&boxes[y as usize]) && f(x, y).is_break() {
+								return;
+							}
+						}
+					}
+				} else {
+					let (l, r) = self.children(a);
+					stack.push((l, l));
+					stack.push((r, r));
+					stack.push((l, r));
+				}
+				continue;
+			}
+			if !na.bounds.overlaps(&nb.bounds) {
+				continue;
+			}
+			match (na.is_leaf(), nb.is_leaf()) {
+				(true, true) => {
+					for &x in self.leaf_items(a) {
+						for &y in self.leaf_items(b) {
+							if boxes[x as usize].overlaps(&boxes[y as usize]) && f(x, y).is_break() {
+								return;
+							}
+						}
+					}
+				}
+				(false, true) => {
+					let (l, r) = self.children(a);
+					stack.push((l, b));
+					stack.push((r, b));
+				}
+				(true, false) => {
+					let (l, r) = self.children(b);
+					stack.push((a, l));
+					stack.push((a, r));
+				}
+				(false, false) => {
+					if na.bounds.surface_area() > nb.bounds.surface_area() {
+						let (l, r) = self.children(a);
+						stack.push((l, b));
+						stack.push((r, b));
+					} else {
+						let (l, r) = self.children(b);
+						stack.push((a, l));
+						stack.push((a, r));
+					}
+				}
+			}
+		}
+	}
+}
+
+/// A range of `Bvh::items` waiting to be split, with the bounds of its boxes
+/// and of their centers.
+struct Range {
+	node: usize,
+	start: usize,
+	end: usize,
+	bounds: Aabb,
+	center_bounds: Aabb,
+}
+
+impl Range {
+	fn of_items(node: usize, start: usize, end: usize, items: &[u32], boxes: &[Aabb], centers: &[Vec3]) -> Range {
+		let mut bounds = Aabb::EMPTY;
+		let mut center_bounds = Aabb::EMPTY;
+		for &i in &items[start..end] {
+			bounds = bounds.union(&boxes[i as usize]);
+			center_bounds.grow(centers[i as usize]);
+		}
+		Range {
+			node,
+			start,
+			end,
+			bounds,
+			center_bounds,
+		}
+	}
+}
+
+type Halves = (usize, Option<(Aabb, Aabb)>, Option<(Aabb, Aabb)>);
+
+/// Partitions `items` using the binned surface area heuristic. Returns the
+/// number of items in the left half and, if known, the box and center bounds
+/// of each half. Returns None if a leaf is cheaper.
+fn split(items: &mut [u32], boxes: &[Aabb], centers: &[Vec3], range: &Range, bins: &mut [u8]) -> Option<Halves> {
+	let extent = range.center_bounds.max - range.center_bounds.min;
+	let axis = if extent.x >= extent.y && extent.x >= extent.z {
+		0
+	} else if extent.y >= extent.z {
+		1
+	} else {
+		2
+	};
+	let lo = range.center_bounds.min[axis];
+	let width = extent[axis];
+	if width <= 0.0 {
+		// All centers coincide; split in the middle to bound the leaf size.
+		return Some((items.len() / 2, None, None));
+	}
+
+	let scale = NUM_BINS as f64 / width;
+	let mut bin_bounds = [Aabb::EMPTY; NUM_BINS];
+	let mut bin_centers = [Aabb::EMPTY; NUM_BINS];
+	let mut bin_counts = [0usize; NUM_BINS];
+	for &i in items.iter() {
+		let c = centers[i as usize];
+		let b = (((c[axis] - lo) * scale) as usize).min(NUM_BINS - 1);
+		bins[i as usize] = b as u8;
+		bin_bounds[b] = bin_bounds[b].union(&boxes[i as usize]);
+		bin_centers[b].grow(c);
+		bin_counts[b] += 1;
+	}
+
+	// Sweep from the right to get the cost of every right half
+	let mut right_area = [0.0; NUM_BINS];
+	let mut acc = Aabb::EMPTY;
+	let mut count = 0;
+	let mut right_count = [0usize; NUM_BINS];
+	for b in (1..NUM_BINS).rev() {
+		acc = acc.union(&bin_bounds[b]);
+		count += bin_counts[b];
+		right_area[b] = acc.surface_area();
+		right_count[b] = count;
+	}
+
+	let mut best = (f64::INFINITY, 0);
+	let mut acc = Aabb::EMPTY;
+	let mut count = 0;
+	for b in 0..NUM_BINS - 1 {
+		acc = acc.union(&bin_bounds[b]);
+		count += bin_counts[b];
+		let cost = acc.surface_area() * count as f64 + right_area[b + 1] * right_count[b + 1] as f64;
+		if count > 0 && right_count[b + 1] > 0 && cost < best.0 {
+			best = (cost, b);
+		}
+	}
+
+	// Relative cost of traversing a node compared to intersecting an item
+	let leaf_cost = range.bounds.surface_area() * items.len() as f64;
+	if best.0 >= leaf_cost && items.len() <= 2 * MAX_LEAF_SIZE {
+		return None;
+	}
+	if best.0 == f64::INFINITY {
+		return Some((items.len() / 2, None, None));
+	}
+
+	let mut mid = 0;
+	for k in 0..items.len() {
+		if bins[items[k] as usize] as usize <= best.1 {
+			items.swap(k, mid);
+			mid += 1;
+		}
+	}
+
+	let mut left = (Aabb::EMPTY, Aabb::EMPTY);
+	let mut right = (Aabb::EMPTY, Aabb::EMPTY);
+	for b in 0..NUM_BINS {
+		let half = if b <= best.1 { &mut left } else { &mut right };
+		half.0 = half.0.union(&bin_bounds[b]);
+		half.1 = half.1.union(&bin_centers[b]);
+	}
+	Some((mid, Some(left), Some(right)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn overlapping_pairs() {
+		// A row of unit boxes, each overlapping only its neighbours
+		let boxes: Vec<Aabb> = (0..100)
+			.map(|i| Aabb {
+				min: Vec3::new(i as f64 * 0.9, 0.0, 0.0),
+				max: Vec3::new(i as f64 * 0.9 + 1.0, 1.0, 1.0),
+			})
+			.collect();
+		let bvh = Bvh::build(&boxes);
+		let mut pairs = Vec::new();
+		bvh.self_overlaps(&boxes, |a, b| {
+			pairs.push((a.min(b), a.max(b)));
+			ControlFlow::Continue(())
+		});
+		pairs.sort_unstable();
+		let expected: Vec<(u32, u32)> = (0..99).map(|i| (i, i + 1)).collect();
+		assert_eq!(pairs, expected);
+
+		let mut calls = 0;
+		bvh.self_overlaps(&boxes, |_, _| {
+			calls += 1;
+			if calls == 10 {
+				ControlFlow::Break(())
+			} else {
+				ControlFlow::Continue(())
+			}
+		});
+		assert_eq!(calls, 10);
+	}
+}
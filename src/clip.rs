@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+
+use crate::mesh::{Mesh, MeshBuffers};
+use crate::slice::{section, Plane};
+use crate::triangulate::triangulate;
+use crate::vec3::Vec3;
+
+/// The part of a mesh below a plane, closed off with a cap over the cut.
+/// Vertices exactly on the plane count as above it, like in `section`, so the
+/// cap's boundary matches the cut edges, and faces lying in the plane are
+/// left out, as the cap already covers them.
+fn below(mesh: &Mesh, plane: &Plane) -> Mesh {
+	let distances: Vec<f64> = mesh.positions.iter().map(|&p| plane.signed_distance(p)).collect();
+	let above = |v: u32| distances[v as usize] >= 0.0;
+	let mut positions = mesh.positions.clone();
+	let mut crossings = HashMap::<(u32, u32), u32>::new();
+	// The vertex where the edge (a, b), with a < b, crosses the plane. The
+	// position is computed the same way as in `section`.
+	let mut crossing = |positions: &mut Vec<Vec3>, (a, b): (u32, u32)| -> u32 {
+		let (da, db) = (distances[a as usize], distances[b as usize]);
+		if da == 0.0 {
+			return a;
+		}
+		if db == 0.0 {
+			return b;
+		}
+		*crossings.entry((a, b)).or_insert_with(|| {
+			let (pa, pb) = (positions[a as usize], positions[b as usize]);
+			positions.push(pa.lerp(pb, da / (da - db)));
+			positions.len() as u32 - 1
+		})
+	};
+	let key = |a: u32, b: u32| (a.min(b), a.max(b));
+
+	let mut triangles = Vec::new();
+	for (t, &tri) in mesh.triangles.iter().enumerate() {
+		if mesh.is_degenerate(t) {
+			continue;
+		}
+		let sides = tri.map(above);
+		if sides[0] == sides[1] && sides[1] == sides[2] {
+			if !sides[0] {
+				triangles.push(tri);
+			}
+			continue;
+		}
+		// Rotate so that the vertex alone on its side comes first
+		let lone = (0..3)
+			.find(|&i| sides[i] != sides[(i + 1) % 3] && sides[i] != sides[(i + 2) % 3])
+			.unwrap();
+		let [i, j, k] = [tri[lone], tri[(lone + 1) % 3], tri[(lone + 2) % 3]];
+		let mij = crossing(&mut positions, key(i, j));
+		let mki = crossing(&mut positions, key(k, i));
+		if sides[lone] {
+			triangles.push([mij, j, k]);
+			triangles.push([mij, k, mki]);
+		} else {
+			triangles.push([i, mij, mki]);
+		}
+	}
+
+	// The cap, counter-clockwise seen from above
+	let contours = section(mesh, 0..mesh.triangles.len() as u32, plane);
+	for (o, outer) in contours.iter().enumerate() {
+		if !outer.closed || outer.is_hole() {
+			continue;
+		}
+		let holes: Vec<usize> = (0..contours.len())
+			.filter(|&h| contours[h].parent == Some(o as u32))
+			.collect();
+		let mut points = outer.points2d.clone();
+		let mut vertices: Vec<u32> = outer.edges.iter().map(|&e| crossing(&mut positions, e)).collect();
+		let outer_ring: Vec<u32> = (0..points.len() as u32).collect();
+		let mut hole_rings = Vec::with_capacity(holes.len());
+		for &h in &holes {
+			let start = points.len() as u32;
+			points.extend_from_slice(&contours[h].points2d);
+			vertices.extend(contours[h].edges.iter().map(|&e| crossing(&mut positions, e)));
+			hole_rings.push((start..points.len() as u32).collect::<Vec<u32>>());
+		}
+		let hole_refs: Vec<&[u32]> = hole_rings.iter().map(|r| r.as_slice()).collect();
+		for t in triangulate(&points, &outer_ring, &hole_refs) {
+			triangles.push(t.map(|i| vertices[i as usize]));
+		}
+	}
+
+	let whole = Mesh { positions, triangles };
+	let live: Vec<u32> = (0..whole.triangles.len() as u32)
+		.filter(|&t| !whole.is_degenerate(t as usize))
+		.collect();
+	whole.submesh(&live)
+}
+
+/// Splits a mesh by a plane into the parts below and above it, each closed
+/// off with a cap over the cut. The part above is the part below the flipped
+/// plane, so faces lying in the plane are in neither part: they face the
+/// part whose cap replaces them, and the other part has none there.
+pub(crate) fn clip(mesh: &Mesh, plane: &Plane) -> (Mesh, Mesh) {
+	let flipped = Plane::new(-plane.normal, -plane.offset).unwrap();
+	(below(mesh, plane), below(mesh, &flipped))
+}
+
+/// The two parts of a mesh cut by a plane.
+#[wasm_bindgen]
+pub struct MeshHalves {
+	below: MeshBuffers,
+	above: MeshBuffers,
+}
+
+#[wasm_bindgen]
+impl MeshHalves {
+	/// The part on the side the plane normal points away from.
+	pub fn below(&self) -> MeshBuffers {
+		self.below.clone()
+	}
+
+	/// The part on the side the plane normal points to.
+	pub fn above(&self) -> MeshBuffers {
+		self.above.clone()
+	}
+}
+
+pub(crate) fn clip_mesh_impl(
+	vertices: &[f32],
+	v_indices: &[u32],
+	plane_normal: &[f64],
+	plane_offset: f64,
+) -> Result<MeshHalves, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	if plane_normal.len() != 3 {
+		return Err(format!(
+			"Plane normal must have 3 components, but has {}",
+			plane_normal.len()
+		));
+	}
+	let plane = Plane::new(
+		Vec3::new(plane_normal[0], plane_normal[1], plane_normal[2]),
+		plane_offset,
+	)
+	.ok_or_else(|| String::from("Plane normal must be nonzero"))?;
+	let (below, above) = clip(&mesh, &plane);
+	Ok(MeshHalves {
+		below: below.to_buffers(),
+		above: above.to_buffers(),
+	})
+}
+
+/// Cut a mesh from `parseSTLMesh` with the plane of points x where
+/// dot(planeNormal, x) == planeOffset. The cut is filled in on both parts,
+/// so a closed mesh gives two closed meshes.
+#[wasm_bindgen(js_name = "clipMesh")]
+pub fn clip_mesh(
+	vertices: &[f32],
+	v_indices: &[u32],
+	plane_normal: &[f64],
+	plane_offset: f64,
+) -> Result<MeshHalves, JsValue> {
+	clip_mesh_impl(vertices, v_indices, plane_normal, plane_offset).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_closed(mesh: &Mesh) {
+		for (edge, faces) in mesh.edge_faces() {
+			assert_eq!(faces.len(), 2, "edge {:?}", edge);
+		}
+	}
+
+	#[test]
+	fn hollow_box() {
+		// A 3x3x3 box with a 1x1x1 cavity in the middle
+		let mut mesh = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 3.0, 3);
+		let mut cavity = Mesh::cube(Vec3::new(1.0, 1.0, 1.0), 1.0);
+		for t in cavity.triangles.iter_mut() {
+			t.swap(1, 2);
+		}
+		mesh.append(&cavity);
+
+		let plane = Plane::new(Vec3::new(0.0, 0.0, 1.0), 1.5).unwrap();
+		let (below, above) = clip(&mesh, &plane);
+		assert_closed(&below);
+		assert_closed(&above);
+		assert!((below.volume() - 13.0).abs() < 1e-9, "{}", below.volume());
+		assert!((above.volume() - 13.0).abs() < 1e-9, "{}", above.volume());
+
+		// Through a row of vertices, and tilted
+		let (below, above) = clip(&mesh, &Plane::new(Vec3::new(0.0, 0.0, 1.0), 1.0).unwrap());
+		assert_closed(&below);
+		assert_closed(&above);
+		assert!((below.volume() - 9.0).abs() < 1e-9);
+		let plane = Plane::new(Vec3::new(0.3, 0.2, 1.0), 2.0).unwrap();
+		let (below, above) = clip(&mesh, &plane);
+		assert_closed(&below);
+		assert_closed(&above);
+		assert!((below.volume() + above.volume() - 26.0).abs() < 1e-9);
+	}
+
+	#[test]
+	fn through_a_face() {
+		// The top face lies in the plane, and the cap takes its place
+		let mesh = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 1.0, 2);
+		let (below, above) = clip(&mesh, &Plane::new(Vec3::new(0.0, 0.0, 1.0), 1.0).unwrap());
+		assert_closed(&below);
+		assert!((below.volume() - 1.0).abs() < 1e-9);
+		assert!(above.triangles.is_empty());
+
+		// The same from the other side
+		let (below, above) = clip(&mesh, &Plane::new(Vec3::new(0.0, 0.0, -1.0), -1.0).unwrap());
+		assert!(below.triangles.is_empty());
+		assert_closed(&above);
+		assert!((above.volume() - 1.0).abs() < 1e-9);
+	}
+
+	#[test]
+	fn buffers() {
+		let (vertices, _, v_indices, _) = Mesh::cube(Vec3::new(0.0, 0.0, 0.0), 2.0).parsed();
+		let halves = clip_mesh_impl(&vertices, &v_indices, &[1.0, 0.0, 0.0], 0.5).unwrap();
+		assert!((halves.below().volume() - 2.0).abs() < 1e-9);
+		assert!((halves.above().volume() - 6.0).abs() < 1e-9);
+		assert_eq!(&*halves.above().bounds(), &[0.5, 0.0, 0.0, 2.0, 2.0, 2.0]);
+		assert!(clip_mesh_impl(&vertices, &v_indices, &[0.0, 0.0, 0.0], 0.5).is_err());
+	}
+}
@@ -0,0 +1,219 @@
+use wasm_bindgen::prelude::*;
+
+use crate::mesh::{Mesh, Rng};
+use crate::mesh_bvh::MeshBvh;
+use crate::vec3::Vec3;
+
+/// Random points sampled on each surface to measure distances at. Their
+/// statistics are within a percent or so of the exact ones.
+const SAMPLES: usize = 1 << 14;
+
+/// How far one mesh deviates from another.
+#[wasm_bindgen]
+pub struct MeshComparison {
+	deviations: Vec<f32>,
+	max_distance: f64,
+	mean_distance: f64,
+	rms_distance: f64,
+	histogram: Vec<u32>,
+	histogram_range: [f64; 2],
+}
+
+#[wasm_bindgen]
+impl MeshComparison {
+	/// Signed distance from each vertex of the first mesh to the second,
+	/// positive outside it, aligned with the vertices from `parseSTLMesh`.
+	pub fn deviations(&self) -> Box<[f32]> {
+		self.deviations.clone().into_boxed_slice()
+	}
+
+	/// Hausdorff distance: the largest distance from a point on either mesh
+	/// to the other mesh, measured at the vertices and at points sampled over
+	/// the surfaces.
+	#[wasm_bindgen(js_name = "maxDistance")]
+	pub fn max_distance(&self) -> f64 {
+		self.max_distance
+	}
+
+	/// Mean unsigned distance from the surface of the first mesh to the
+	/// second, over points sampled evenly by area.
+	#[wasm_bindgen(js_name = "meanDistance")]
+	pub fn mean_distance(&self) -> f64 {
+		self.mean_distance
+	}
+
+	/// Root mean square distance from the first mesh to the second, weighted
+	/// like `meanDistance`.
+	#[wasm_bindgen(js_name = "rmsDistance")]
+	pub fn rms_distance(&self) -> f64 {
+		self.rms_distance
+	}
+
+	/// Number of points sampled on the first mesh in each of equally wide
+	/// bins of signed deviation, from the smallest to the largest.
+	pub fn histogram(&self) -> Box<[u32]> {
+		self.histogram.clone().into_boxed_slice()
+	}
+
+	/// The smallest and largest signed deviation, the range the histogram
+	/// covers.
+	#[wasm_bindgen(js_name = "histogramRange")]
+	pub fn histogram_range(&self) -> Box<[f64]> {
+		Box::new(self.histogram_range)
+	}
+}
+
+/// Signed distance from each point to the surface. The sign comes from the
+/// interpolated vertex normals at the closest point, which unlike a face
+/// normal is reliable when the closest point is on an edge or a corner.
+fn signed_distances(points: &[Vec3], surface: &MeshBvh) -> Vec<f64> {
+	points
+		.iter()
+		.map(|&p| match surface.closest(p, f64::INFINITY) {
+			Some(hit) => {
+				let q = hit.point();
+				let n = hit.normal();
+				let outside = (p.x - q[0]) * n[0] + (p.y - q[1]) * n[1] + (p.z - q[2]) * n[2] >= 0.0;
+				if outside {
+					hit.distance()
+				} else {
+					-hit.distance()
+				}
+			}
+			None => f64::NAN,
+		})
+		.collect()
+}
+
+fn surface_points(mesh: &Mesh) -> Vec<Vec3> {
+	mesh
+		.random_surface_points(SAMPLES, &mut Rng(0))
+		.iter()
+		.map(|p| mesh.surface_position(p))
+		.collect()
+}
+
+fn compare(a: &Mesh, b: &Mesh, bins: u32) -> MeshComparison {
+	let a_bvh = MeshBvh::new(a.clone(), a.vertex_normals());
+	let b_bvh = MeshBvh::new(b.clone(), b.vertex_normals());
+	let deviations = signed_distances(&a.positions, &b_bvh);
+	let back = signed_distances(&b.positions, &a_bvh);
+	// Vertices alone miss deviations inside large faces, as on coarse CAD
+	// exports, so distances are also measured at points spread by area
+	let samples = signed_distances(&surface_points(a), &b_bvh);
+	let back_samples = signed_distances(&surface_points(b), &a_bvh);
+	let max_distance = [&deviations, &back, &samples, &back_samples]
+		.iter()
+		.flat_map(|d| d.iter())
+		.fold(0.0f64, |m, d| m.max(d.abs()));
+
+	let n = samples.len().max(1) as f64;
+	let mean_distance = samples.iter().map(|d| d.abs()).sum::<f64>() / n;
+	let rms_distance = (samples.iter().map(|d| d * d).sum::<f64>() / n).sqrt();
+
+	let (lo, hi) = samples.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &d| {
+		(lo.min(d), hi.max(d))
+	});
+	let mut histogram = vec![0; bins as usize];
+	if bins > 0 && lo <= hi {
+		let width = (hi - lo) / bins as f64;
+		for &d in &samples {
+			let bin = if width > 0.0 { ((d - lo) / width) as usize } else { 0 };
+			histogram[bin.min(bins as usize - 1)] += 1;
+		}
+	}
+
+	MeshComparison {
+		deviations: deviations.iter().map(|&d| d as f32).collect(),
+		max_distance,
+		mean_distance,
+		rms_distance,
+		histogram,
+		histogram_range: if lo <= hi { [lo, hi] } else { [0.0, 0.0] },
+	}
+}
+
+pub(crate) fn compare_meshes_impl(
+	a_vertices: &[f32],
+	a_indices: &[u32],
+	b_vertices: &[f32],
+	b_indices: &[u32],
+	bins: u32,
+) -> Result<MeshComparison, String> {
+	let a = Mesh::from_buffers(a_vertices, a_indices)?;
+	let b = Mesh::from_buffers(b_vertices, b_indices)?;
+	if a.triangles.is_empty() || b.triangles.is_empty() {
+		return Err(String::from("Both meshes must have triangles"));
+	}
+	Ok(compare(&a, &b, bins))
+}
+
+/// Compare two meshes from `parseSTLMesh`, e.g. a scan against the design it
+/// was made from. Distances are measured from each mesh to the surface of
+/// the other at the vertices and at points sampled evenly over the surface,
+/// so coarse meshes with large faces are measured fairly, with a histogram of
+/// the signed deviations of the first mesh in `bins` bins. The meshes should
+/// already be aligned.
+#[wasm_bindgen(js_name = "compareMeshes")]
+pub fn compare_meshes(
+	a_vertices: &[f32],
+	a_indices: &[u32],
+	b_vertices: &[f32],
+	b_indices: &[u32],
+	bins: u32,
+) -> Result<MeshComparison, JsValue> {
+	compare_meshes_impl(a_vertices, a_indices, b_vertices, b_indices, bins).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn offset_cubes() {
+		// A unit cube against one 0.2 larger on every side
+		let a = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 1.0, 4);
+		let b = Mesh::cube(Vec3::new(-0.2, -0.2, -0.2), 1.4);
+		let (av, _, ai, _) = a.parsed();
+		let (bv, _, bi, _) = b.parsed();
+
+		let inside = compare_meshes_impl(&av, &ai, &bv, &bi, 4).unwrap();
+		assert!(inside.deviations().iter().all(|d| (d + 0.2).abs() < 1e-6));
+		assert!((inside.mean_distance() - 0.2).abs() < 1e-6);
+		assert!((inside.rms_distance() - 0.2).abs() < 1e-6);
+		// From the corners of the big cube to the small one
+		assert!((inside.max_distance() - 0.2 * 3f64.sqrt()).abs() < 1e-6);
+		let histogram = inside.histogram();
+		assert_eq!(histogram.iter().sum::<u32>() as usize, SAMPLES);
+		let range = inside.histogram_range();
+		assert!(range[1] - range[0] < 1e-6);
+
+		let outside = compare_meshes_impl(&bv, &bi, &av, &ai, 4).unwrap();
+		assert!(outside
+			.deviations()
+			.iter()
+			.all(|&d| (d as f64 - 0.2 * 3f64.sqrt()).abs() < 1e-6));
+		// From the faces of the big cube, most of the way 0.2 out
+		assert!(outside.mean_distance() > 0.2 && outside.mean_distance() < 0.25);
+		let range = outside.histogram_range();
+		assert!((range[0] - 0.2).abs() < 0.01 && (range[1] - 0.2 * 3f64.sqrt()).abs() < 0.01);
+		assert!(compare_meshes_impl(&av, &ai, &[], &[], 4).is_err());
+	}
+
+	#[test]
+	fn coarse_box() {
+		// The corners of a plain box are all on a finer one with a dent in
+		// the middle of its top, which only samples inside the faces see
+		let a = Mesh::cube(Vec3::new(0.0, 0.0, 0.0), 1.0);
+		let mut b = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 1.0, 2);
+		let top = b.positions.iter().position(|&p| p == Vec3::new(0.5, 0.5, 1.0)).unwrap();
+		b.positions[top].z = 0.7;
+		let (av, _, ai, _) = a.parsed();
+		let (bv, _, bi, _) = b.parsed();
+
+		let comparison = compare_meshes_impl(&av, &ai, &bv, &bi, 4).unwrap();
+		assert!(comparison.deviations().iter().all(|d| d.abs() < 1e-6));
+		assert!(comparison.mean_distance() > 0.01, "{}", comparison.mean_distance());
+		assert!((comparison.max_distance() - 0.3).abs() < 1e-6);
+	}
+}
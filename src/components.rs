@@ -0,0 +1,186 @@
+use wasm_bindgen::prelude::*;
+
+use crate::mesh::{Mesh, MeshBuffers};
+
+/// Union-find over vertex indices.
+pub(crate) struct DisjointSet {
+	parent: Vec<u32>,
+	rank: Vec<u8>,
+}
+
+impl DisjointSet {
+	pub fn new(len: usize) -> DisjointSet {
+		DisjointSet {
+			parent: (0..len as u32).collect(),
+			rank: vec![0; len],
+		}
+	}
+
+	pub fn find(&mut self, mut x: u32) -> u32 {
+		while self.parent[x as usize] != x {
+			let grandparent = self.parent[self.parent[x as usize] as usize];
+			self.parent[x as usize] = grandparent;
+			x = grandparent;
+		}
+		x
+	}
+
+	pub fn union(&mut self, a: u32, b: u32) {
+		let a = self.find(a);
+		let b = self.find(b);
+		if a == b {
+			return;
+		}
+		let (ra, rb) = (self.rank[a as usize], self.rank[b as usize]);
+		if ra < rb {
+			self.parent[a as usize] = b;
+		} else {
+			self.parent[b as usize] = a;
+			if ra == rb {
+				self.rank[a as usize] += 1;
+			}
+		}
+	}
+}
+
+/// Labels each triangle with the index of the connected component it belongs
+/// to. Triangles are connected if they share a vertex. Components are numbered
+/// in the order their first triangle appears. Returns the labels and the
+/// number of components.
+pub(crate) fn label_components(mesh: &Mesh) -> (Vec<u32>, u32) {
+	let mut set = DisjointSet::new(mesh.positions.len());
+	for t in &mesh.triangles {
+		set.union(t[0], t[1]);
+		set.union(t[1], t[2]);
+	}
+
+	let mut root_label = vec![u32::MAX; mesh.positions.len()];
+	let mut count = 0;
+	let labels = mesh
+		.triangles
+		.iter()
+		.map(|t| {
+			let root = set.find(t[0]) as usize;
+			if root_label[root] == u32::MAX {
+				root_label[root] = count;
+				count += 1;
+			}
+			root_label[root]
+		})
+		.collect();
+	(labels, count)
+}
+
+/// The connected components ("bodies") of a mesh.
+#[wasm_bindgen]
+pub struct Components {
+	mesh: Mesh,
+	labels: Vec<u32>,
+	triangles: Vec<Vec<u32>>,
+	bounds: Vec<[f64; 6]>,
+	volumes: Vec<f64>,
+}
+
+#[wasm_bindgen]
+impl Components {
+	/// Number of components.
+	pub fn count(&self) -> u32 {
+		self.triangles.len() as u32
+	}
+
+	/// Component index of every triangle, aligned with the triangle order of
+	/// the index buffer.
+	pub fn labels(&self) -> Box<[u32]> {
+		self.labels.clone().into_boxed_slice()
+	}
+
+	/// The triangles of component `i` as a standalone mesh.
+	pub fn mesh(&self, i: u32) -> Option<MeshBuffers> {
+		let tris = self.triangles.get(i as usize)?;
+		Some(self.mesh.submesh(tris).to_buffers())
+	}
+
+	/// Indices of the triangles in component `i`.
+	pub fn triangles(&self, i: u32) -> Option<Box<[u32]>> {
+		Some(self.triangles.get(i as usize)?.clone().into_boxed_slice())
+	}
+
+	/// Number of triangles in component `i`.
+	#[wasm_bindgen(js_name = "triangleCount")]
+	pub fn triangle_count(&self, i: u32) -> Option<u32> {
+		Some(self.triangles.get(i as usize)?.len() as u32)
+	}
+
+	/// Axis-aligned bounds of component `i` as [minX, minY, minZ, maxX, maxY, maxZ].
+	pub fn bounds(&self, i: u32) -> Option<Box<[f64]>> {
+		Some(Box::new(*self.bounds.get(i as usize)?))
+	}
+
+	/// Signed volume of component `i`. Only meaningful if the component is closed.
+	pub fn volume(&self, i: u32) -> Option<f64> {
+		self.volumes.get(i as usize).copied()
+	}
+}
+
+pub(crate) fn split_components_impl(vertices: &[f32], v_indices: &[u32]) -> Result<Components, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	let (labels, count) = label_components(&mesh);
+
+	let mut triangles = vec![Vec::new(); count as usize];
+	for (t, &label) in labels.iter().enumerate() {
+		triangles[label as usize].push(t as u32);
+	}
+
+	let mut bounds = Vec::with_capacity(count as usize);
+	let mut volumes = Vec::with_capacity(count as usize);
+	for tris in &triangles {
+		let sub = mesh.submesh(tris);
+		let (min, max) = sub.bounds();
+		bounds.push([min.x, min.y, min.z, max.x, max.y, max.z]);
+		volumes.push(sub.volume());
+	}
+
+	Ok(Components {
+		mesh,
+		labels,
+		triangles,
+		bounds,
+		volumes,
+	})
+}
+
+/// Split a mesh from `parseSTLMesh` into its connected components. Triangles
+/// that share a vertex belong to the same component.
+#[wasm_bindgen(js_name = "splitComponents")]
+pub fn split_components(vertices: &[f32], v_indices: &[u32]) -> Result<Components, JsValue> {
+	split_components_impl(vertices, v_indices).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vec3::Vec3;
+
+	#[test]
+	fn two_cubes() {
+		let mut plate = Mesh::cube(Vec3::new(0.0, 0.0, 0.0), 1.0);
+		plate.append(&Mesh::cube(Vec3::new(5.0, 0.0, 0.0), 2.0));
+		let (vertices, _, v_indices, _) = plate.parsed();
+
+		let components = split_components_impl(&vertices, &v_indices).unwrap();
+		assert_eq!(components.count(), 2);
+		assert_eq!(&components.labels()[..12], &[0; 12]);
+		assert_eq!(&components.labels()[12..], &[1; 12]);
+		assert_eq!(components.triangle_count(1), Some(12));
+		assert!((components.volume(0).unwrap() - 1.0).abs() < 1e-9);
+		assert!((components.volume(1).unwrap() - 8.0).abs() < 1e-9);
+		assert_eq!(components.triangle_count(2), None);
+		assert_eq!(components.volume(2), None);
+		assert_eq!(&*components.bounds(1).unwrap(), &[5.0, 0.0, 0.0, 7.0, 2.0, 2.0]);
+
+		let body = components.mesh(1).unwrap();
+		assert_eq!(body.vertex_count(), 8);
+		assert_eq!(body.triangle_count(), 12);
+		assert!(components.mesh(2).is_none());
+	}
+}
@@ -0,0 +1,250 @@
+use std::f64::consts::PI;
+
+use wasm_bindgen::prelude::*;
+
+use crate::mesh::Mesh;
+use crate::vec3::Vec3;
+
+/// Curvature at every vertex of a mesh. Positive curvature bends away from
+/// the side the normals point to, as on the outside of a sphere.
+#[wasm_bindgen]
+pub struct Curvature {
+	mean: Vec<f32>,
+	gaussian: Vec<f32>,
+	principal: Vec<f32>,
+	max_directions: Vec<f32>,
+	min_directions: Vec<f32>,
+}
+
+#[wasm_bindgen]
+impl Curvature {
+	/// Mean curvature per vertex, the average of the principal curvatures.
+	pub fn mean(&self) -> Box<[f32]> {
+		self.mean.clone().into_boxed_slice()
+	}
+
+	/// Gaussian curvature per vertex, the product of the principal curvatures.
+	pub fn gaussian(&self) -> Box<[f32]> {
+		self.gaussian.clone().into_boxed_slice()
+	}
+
+	/// Largest and smallest principal curvature per vertex, 2 values each.
+	pub fn principal(&self) -> Box<[f32]> {
+		self.principal.clone().into_boxed_slice()
+	}
+
+	/// Unit direction of the largest curvature per vertex, 3 values each.
+	#[wasm_bindgen(js_name = "maxDirections")]
+	pub fn max_directions(&self) -> Box<[f32]> {
+		self.max_directions.clone().into_boxed_slice()
+	}
+
+	/// Unit direction of the smallest curvature per vertex, 3 values each.
+	#[wasm_bindgen(js_name = "minDirections")]
+	pub fn min_directions(&self) -> Box<[f32]> {
+		self.min_directions.clone().into_boxed_slice()
+	}
+}
+
+/// Discrete curvature after Meyer et al., "Discrete Differential-Geometry
+/// Operators for Triangulated 2-Manifolds": the mean curvature from the
+/// cotangent Laplacian and the Gaussian curvature from the angle deficit,
+/// both over the mixed Voronoi area. Principal directions come from Taubin's
+/// curvature tensor, "Estimating the Tensor of Curvature of a Surface from a
+/// Polyhedral Approximation".
+fn curvature(mesh: &Mesh) -> Curvature {
+	let n = mesh.positions.len();
+	let normals = mesh.vertex_normals();
+	let mut area = vec![0.0; n];
+	let mut laplacian = vec![Vec3::ZERO; n];
+	let mut angle_sum = vec![0.0; n];
+	// Curvature tensors as the upper triangle of a symmetric 3x3 matrix
+	let mut tensors = vec![[0.0; 6]; n];
+	let mut tensor_weight = vec![0.0; n];
+	let mut on_boundary = vec![false; n];
+	for (&(a, b), faces) in &mesh.edge_faces() {
+		if faces.len() == 1 {
+			on_boundary[a as usize] = true;
+			on_boundary[b as usize] = true;
+		}
+	}
+
+	for (t, tri) in mesh.triangles.iter().enumerate() {
+		if mesh.is_degenerate(t) {
+			continue;
+		}
+		let p = mesh.corners(t);
+		let face_area = mesh.face_cross(t).length() / 2.0;
+		let cot = |i: usize| {
+			let (u, v) = (p[(i + 1) % 3] - p[i], p[(i + 2) % 3] - p[i]);
+			u.dot(v) / u.cross(v).length()
+		};
+		let cots = [cot(0), cot(1), cot(2)];
+		let obtuse = (0..3).find(|&i| cots[i] < 0.0);
+		for i in 0..3 {
+			let (j, k) = ((i + 1) % 3, (i + 2) % 3);
+			let vi = tri[i] as usize;
+			let (eij, eik) = (p[j] - p[i], p[k] - p[i]);
+			// The edge opposite a corner is weighted by that corner's cotangent
+			laplacian[vi] += eij * cots[k] + eik * cots[j];
+			angle_sum[vi] += eij.cross(eik).length().atan2(eij.dot(eik));
+			area[vi] += match obtuse {
+				None => (eij.dot(eij) * cots[k] + eik.dot(eik) * cots[j]) / 8.0,
+				Some(o) if o == i => face_area / 2.0,
+				Some(_) => face_area / 4.0,
+			};
+
+			// Normal curvature along each edge, weighted by the area around it
+			for e in [eij, eik] {
+				let normal = normals[vi];
+				let tangent = (e - normal * e.dot(normal)).normalized();
+				let len2 = e.dot(e);
+				if tangent == Vec3::ZERO || len2 == 0.0 {
+					continue;
+				}
+				let kappa = -2.0 * normal.dot(e) / len2;
+				let tensor = &mut tensors[vi];
+				let entries: [(usize, usize); 6] = [(0, 0), (0, 1), (0, 2), (1, 1), (1, 2), (2, 2)];
+				for (m, &(r, c)) in entries.iter().enumerate() {
+					tensor[m] += face_area * kappa * tangent[r] * tangent[c];
+				}
+				tensor_weight[vi] += face_area;
+			}
+		}
+	}
+
+	let mut curvature = Curvature {
+		mean: Vec::with_capacity(n),
+		gaussian: Vec::with_capacity(n),
+		principal: Vec::with_capacity(2 * n),
+		max_directions: Vec::with_capacity(3 * n),
+		min_directions: Vec::with_capacity(3 * n),
+	};
+	for v in 0..n {
+		let (h, k) = if area[v] > 0.0 {
+			let full_turn = if on_boundary[v] { PI } else { 2.0 * PI };
+			// The Laplacian of position is -2 H n
+			(
+				-laplacian[v].dot(normals[v]) / (4.0 * area[v]),
+				(full_turn - angle_sum[v]) / area[v],
+			)
+		} else {
+			(0.0, 0.0)
+		};
+		let root = (h * h - k).max(0.0).sqrt();
+		let (k_max, k_min) = (h + root, h - root);
+
+		// Eigenvectors of the tensor within the tangent plane
+		let normal = normals[v];
+		let (max_dir, min_dir) = if tensor_weight[v] > 0.0 && normal != Vec3::ZERO {
+			let m = tensors[v];
+			let apply = |x: Vec3| {
+				Vec3::new(
+					m[0] * x.x + m[1] * x.y + m[2] * x.z,
+					m[1] * x.x + m[3] * x.y + m[4] * x.z,
+					m[2] * x.x + m[4] * x.y + m[5] * x.z,
+				)
+			};
+			let u = normal.any_perpendicular();
+			let w = normal.cross(u);
+			let (a, b, c) = (u.dot(apply(u)), u.dot(apply(w)), w.dot(apply(w)));
+			// Angle of the eigenvector with the larger eigenvalue
+			let theta = 0.5 * (2.0 * b).atan2(a - c);
+			let max_dir = u * theta.cos() + w * theta.sin();
+			(max_dir, normal.cross(max_dir))
+		} else {
+			(Vec3::ZERO, Vec3::ZERO)
+		};
+
+		curvature.mean.push(h as f32);
+		curvature.gaussian.push(k as f32);
+		curvature.principal.extend([k_max as f32, k_min as f32]);
+		curvature
+			.max_directions
+			.extend([max_dir.x as f32, max_dir.y as f32, max_dir.z as f32]);
+		curvature
+			.min_directions
+			.extend([min_dir.x as f32, min_dir.y as f32, min_dir.z as f32]);
+	}
+	curvature
+}
+
+pub(crate) fn compute_curvature_impl(vertices: &[f32], v_indices: &[u32]) -> Result<Curvature, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	Ok(curvature(&mesh))
+}
+
+/// Curvature at every vertex of a mesh from `parseSTLMesh`, whose vertices
+/// are already welded. Buffers are aligned with its vertices. At the boundary
+/// of an open mesh the Gaussian curvature is measured against a half turn and
+/// is only a rough guide.
+#[wasm_bindgen(js_name = "computeCurvature")]
+pub fn compute_curvature(vertices: &[f32], v_indices: &[u32]) -> Result<Curvature, JsValue> {
+	compute_curvature_impl(vertices, v_indices).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sphere() {
+		// A tessellated cube blown up into a sphere of radius 2
+		let mut mesh = Mesh::tessellated_cube(Vec3::new(-1.0, -1.0, -1.0), 2.0, 16);
+		mesh.positions.iter_mut().for_each(|p| *p = p.normalized() * 2.0);
+		let (vertices, _, v_indices, _) = mesh.parsed();
+		let c = compute_curvature_impl(&vertices, &v_indices).unwrap();
+		let n = c.mean().len() as f32;
+		let mean_h = c.mean().iter().sum::<f32>() / n;
+		let mean_k = c.gaussian().iter().sum::<f32>() / n;
+		assert!((mean_h - 0.5).abs() < 0.01, "{}", mean_h);
+		assert!((mean_k - 0.25).abs() < 0.01, "{}", mean_k);
+		// Apart from at the cube's corners, where the triangles are most
+		// distorted
+		assert!(c.mean().iter().filter(|h| (*h - 0.5).abs() > 0.05).count() <= 8);
+	}
+
+	#[test]
+	fn cylinder() {
+		// An open tube of radius 2 along z
+		let segments = 48;
+		let rings = 7;
+		let mut mesh = Mesh::default();
+		for r in 0..rings {
+			for i in 0..segments {
+				let a = 2.0 * PI * i as f64 / segments as f64;
+				mesh
+					.positions
+					.push(Vec3::new(2.0 * a.cos(), 2.0 * a.sin(), r as f64 * 0.5));
+			}
+		}
+		for r in 0..rings - 1 {
+			for i in 0..segments {
+				let j = (i + 1) % segments;
+				let (a, b, c, d) = (
+					r * segments + i,
+					r * segments + j,
+					(r + 1) * segments + j,
+					(r + 1) * segments + i,
+				);
+				mesh.triangles.push([a, b, c]);
+				mesh.triangles.push([a, c, d]);
+			}
+		}
+		let c = curvature(&mesh);
+		let inner = segments as usize..(rings as usize - 1) * segments as usize;
+		for v in inner {
+			let p = mesh.positions[v];
+			assert!((c.mean[v] - 0.25).abs() < 0.01, "{}", c.mean[v]);
+			assert!(c.gaussian[v].abs() < 1e-3, "{}", c.gaussian[v]);
+			assert!((c.principal[2 * v] - 0.5).abs() < 0.01);
+			assert!(c.principal[2 * v + 1].abs() < 0.01);
+			// Most curved around the tube, least along it. The diagonals of the
+			// quads all lean the same way, which tilts the estimate a little.
+			let min_dir = Vec3::from_f32(&c.min_directions, v);
+			assert!(min_dir.z.abs() > 0.99, "{:?}", min_dir);
+			let max_dir = Vec3::from_f32(&c.max_directions, v);
+			assert!(max_dir.dot(Vec3::new(-p.y, p.x, 0.0).normalized()).abs() > 0.99);
+		}
+	}
+}
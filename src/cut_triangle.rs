@@ -0,0 +1,371 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use crate::arrangement::{orient, Point};
+
+/// A triangle cut up by the points and segments on it, built by inserting
+/// points and then flipping edges out of the way of segments, so that it
+/// takes nothing but orientation tests, which are exact.
+pub(crate) struct CutTriangle<'a> {
+	points: Vec<&'a Point>,
+	drop: usize,
+	/// Negative if the projection mirrors the triangle
+	sense: f64,
+	/// Counterclockwise
+	triangles: Vec<[usize; 3]>,
+	/// The triangle on the left of each edge, by (from, to)
+	edges: HashMap<(usize, usize), usize>,
+	/// A triangle around each point that has been added
+	around: Vec<Option<usize>>,
+	/// Where the last point was found, to look for the next from
+	last: usize,
+	/// Edges along segments, as (low, high)
+	fixed: BTreeSet<(usize, usize)>,
+}
+
+impl<'a> CutTriangle<'a> {
+	/// The triangle of the first three points, to cut up with the rest.
+	pub fn new(points: Vec<&'a Point>, drop: usize) -> CutTriangle<'a> {
+		let mut cut = CutTriangle {
+			sense: orient(points[0], points[1], points[2], drop).signum(),
+			around: vec![None; points.len()],
+			points,
+			drop,
+			triangles: Vec::new(),
+			edges: HashMap::new(),
+			last: 0,
+			fixed: BTreeSet::new(),
+		};
+		cut.push([0, 1, 2]);
+		cut
+	}
+
+	fn orient(&self, a: usize, b: usize, c: usize) -> f64 {
+		self.sense * orient(self.points[a], self.points[b], self.points[c], self.drop)
+	}
+
+	fn link(&mut self, t: usize) {
+		let tri = self.triangles[t];
+		for k in 0..3 {
+			self.edges.insert((tri[k], tri[(k + 1) % 3]), t);
+			self.around[tri[k]] = Some(t);
+		}
+	}
+
+	/// Replaces triangle `t`. Its old edges that no other triangle has taken
+	/// over are forgotten.
+	fn set(&mut self, t: usize, tri: [usize; 3]) {
+		let old = self.triangles[t];
+		for k in 0..3 {
+			let e = (old[k], old[(k + 1) % 3]);
+			if self.edges.get(&e) == Some(&t) {
+				self.edges.remove(&e);
+			}
+		}
+		self.triangles[t] = tri;
+		self.link(t);
+	}
+
+	fn push(&mut self, tri: [usize; 3]) {
+		self.triangles.push(tri);
+		self.link(self.triangles.len() - 1);
+	}
+
+	/// The triangle with the edge from `a` to `b`, and its corner opposite.
+	fn across(&self, a: usize, b: usize) -> Option<(usize, usize)> {
+		let t = *self.edges.get(&(a, b))?;
+		let tri = self.triangles[t];
+		let k = tri.iter().position(|&c| c == a)?;
+		Some((t, tri[(k + 2) % 3]))
+	}
+
+	/// Splits the triangle on the edge from `chain[0]` to its last point with
+	/// a fan from the corner opposite through the points in between, which
+	/// must be on the edge in order.
+	pub fn fan(&mut self, chain: &[usize]) {
+		let (t, apex) = self.across(chain[0], chain[chain.len() - 1]).unwrap();
+		self.set(t, [apex, chain[0], chain[1]]);
+		for w in chain[1..].windows(2) {
+			self.push([apex, w[0], w[1]]);
+		}
+	}
+
+	/// The triangle point `p` is in or on, with the side of each edge it's
+	/// on, found by walking towards it from the last one found.
+	fn locate(&self, p: usize) -> Option<(usize, [f64; 3])> {
+		let sides = |t: usize| {
+			let tri = self.triangles[t];
+			[0, 1, 2].map(|k| self.orient(tri[k], tri[(k + 1) % 3], p))
+		};
+		let mut t = self.last;
+		for step in 0..self.triangles.len() {
+			let (tri, s) = (self.triangles[t], sides(t));
+			// Starting from a different edge each step keeps the walk from
+			// going round in circles
+			let next = (0..3)
+				.map(|k| (k + step) % 3)
+				.filter(|&k| s[k] < 0.0)
+				.find_map(|k| self.across(tri[(k + 1) % 3], tri[k]));
+			match next {
+				Some((n, _)) => t = n,
+				None if s.iter().all(|&s| s >= 0.0) => return Some((t, s)),
+				// Outside the outer edges
+				None => return None,
+			}
+		}
+		(0..self.triangles.len())
+			.map(|t| (t, sides(t)))
+			.find(|(_, s)| s.iter().all(|&s| s >= 0.0))
+	}
+
+	/// Adds point `p`, splitting the triangle it's in, or the two on either
+	/// side of the edge it's on. Returns the vertex it coincides with instead,
+	/// if there is one, or None if it's outside the triangle.
+	pub fn insert(&mut self, p: usize) -> Option<usize> {
+		let (t, sides) = self.locate(p)?;
+		self.last = t;
+		let tri = self.triangles[t];
+		let zeros: Vec<usize> = (0..3).filter(|&k| sides[k] == 0.0).collect();
+		match zeros[..] {
+			[] => {
+				let [a, b, c] = tri;
+				self.set(t, [a, b, p]);
+				self.push([b, c, p]);
+				self.push([c, a, p]);
+			}
+			[k] => {
+				let (u, v, w) = (tri[k], tri[(k + 1) % 3], tri[(k + 2) % 3]);
+				let other = self.across(v, u);
+				self.set(t, [u, p, w]);
+				self.push([p, v, w]);
+				if let Some((n, x)) = other {
+					self.set(n, [v, p, x]);
+					self.push([p, u, x]);
+				}
+			}
+			[0, 1] => return Some(tri[1]),
+			[1, 2] => return Some(tri[2]),
+			_ => return Some(tri[0]),
+		}
+		Some(p)
+	}
+
+	/// Whether the segments from `a` to `b` and from `c` to `d` cross at a
+	/// point inside both.
+	fn crosses(&self, a: usize, b: usize, c: usize, d: usize) -> bool {
+		let opposite = |s: f64, t: f64| s > 0.0 && t < 0.0 || s < 0.0 && t > 0.0;
+		opposite(self.orient(a, b, c), self.orient(a, b, d)) && opposite(self.orient(c, d, a), self.orient(c, d, b))
+	}
+
+	/// Whether point `k` is on the segment from `i` to `j`, between its ends.
+	fn between(&self, i: usize, k: usize, j: usize) -> bool {
+		let (p, q, r) = (self.points[i], self.points[j], self.points[k]);
+		self.orient(i, j, k) == 0.0 && p.compare(r) == r.compare(q)
+	}
+
+	/// The triangles around point `i`.
+	fn star(&self, i: usize) -> Vec<usize> {
+		let Some(start) = self.around[i] else {
+			return Vec::new();
+		};
+		// Counterclockwise round to the start, or to the outer edge and then
+		// clockwise from the start to the other side of it
+		let corner = |t: usize, offset: usize| {
+			let tri = self.triangles[t];
+			tri[(tri.iter().position(|&c| c == i).unwrap() + offset) % 3]
+		};
+		let mut star = vec![start];
+		let mut t = start;
+		while let Some((n, _)) = self.across(i, corner(t, 2)) {
+			if n == start {
+				return star;
+			}
+			star.push(n);
+			t = n;
+		}
+		t = start;
+		while let Some((n, _)) = self.across(corner(t, 1), i) {
+			star.push(n);
+			t = n;
+		}
+		star
+	}
+
+	/// The edges the segment from `i` to `j` crosses, in order, up to the
+	/// first point on it if there is one, which is also returned.
+	fn crossed(&self, i: usize, j: usize) -> (VecDeque<(usize, usize)>, Option<usize>) {
+		let mut crossed = VecDeque::new();
+		// Leaving i through the edge opposite it in one of the triangles
+		// around it, from its corner right of the segment to its corner left
+		let mut first = None;
+		for t in self.star(i) {
+			let tri = self.triangles[t];
+			let k = tri.iter().position(|&c| c == i).unwrap();
+			let (a, b) = (tri[(k + 1) % 3], tri[(k + 2) % 3]);
+			for c in [a, b] {
+				if self.between(i, c, j) {
+					return (crossed, Some(c));
+				}
+			}
+			if self.orient(i, j, a) < 0.0 && self.orient(i, j, b) > 0.0 {
+				first = Some((a, b));
+				break;
+			}
+		}
+		let Some((mut a, mut b)) = first else {
+			return (crossed, None);
+		};
+		loop {
+			crossed.push_back((a, b));
+			let Some((_, x)) = self.across(b, a) else {
+				break;
+			};
+			if x == j {
+				break;
+			}
+			if self.between(i, x, j) {
+				return (crossed, Some(x));
+			}
+			if self.orient(i, j, x) > 0.0 {
+				b = x;
+			} else {
+				a = x;
+			}
+		}
+		(crossed, None)
+	}
+
+	/// Makes the segment from `i` to `j` a fixed edge, or a chain of them
+	/// through the points on it, flipping the edges that cross it out of the
+	/// way (Sloan, "A fast algorithm for generating constrained Delaunay
+	/// triangulations"). Returns false if the flips don't clear the way, which
+	/// leaves the segment out of the fixed edges.
+	pub fn insert_segment(&mut self, i: usize, j: usize) -> bool {
+		if i == j {
+			return true;
+		}
+		if self.edges.contains_key(&(i, j)) || self.edges.contains_key(&(j, i)) {
+			self.fixed.insert((i.min(j), i.max(j)));
+			return true;
+		}
+		let (mut queue, on) = self.crossed(i, j);
+		if let Some(k) = on {
+			return self.insert_segment(i, k) && self.insert_segment(k, j);
+		}
+		// Some edge crossing the segment always has a convex quadrilateral
+		// around it, so going round the queue flips them all out of the way.
+		// Edges that can't be flipped yet go to the back.
+		let mut stalled = 0;
+		while let Some((u, v)) = queue.pop_front() {
+			let (Some((t, a)), Some((n, b))) = (self.across(u, v), self.across(v, u)) else {
+				continue;
+			};
+			if self.orient(a, u, b) > 0.0 && self.orient(b, v, a) > 0.0 {
+				self.set(t, [a, u, b]);
+				self.set(n, [b, v, a]);
+				if self.crosses(i, j, a, b) {
+					queue.push_back((a, b));
+				}
+				stalled = 0;
+			} else {
+				queue.push_back((u, v));
+				stalled += 1;
+				if stalled > queue.len() {
+					break;
+				}
+			}
+		}
+		if !self.edges.contains_key(&(i, j)) && !self.edges.contains_key(&(j, i)) {
+			return false;
+		}
+		self.fixed.insert((i.min(j), i.max(j)));
+		true
+	}
+
+	pub fn fixed(&self) -> &BTreeSet<(usize, usize)> {
+		&self.fixed
+	}
+
+	/// The triangles, grouped into the regions between fixed edges.
+	pub fn faces(&self) -> Vec<Vec<[usize; 3]>> {
+		let mut seen = vec![false; self.triangles.len()];
+		let mut faces = Vec::new();
+		for start in 0..self.triangles.len() {
+			if seen[start] {
+				continue;
+			}
+			seen[start] = true;
+			let mut stack = vec![start];
+			let mut face = Vec::new();
+			while let Some(t) = stack.pop() {
+				let tri = self.triangles[t];
+				face.push(tri);
+				for k in 0..3 {
+					let (u, v) = (tri[k], tri[(k + 1) % 3]);
+					if self.fixed.contains(&(u.min(v), u.max(v))) {
+						continue;
+					}
+					if let Some(&n) = self.edges.get(&(v, u)) {
+						if !seen[n] {
+							seen[n] = true;
+							stack.push(n);
+						}
+					}
+				}
+			}
+			faces.push(face);
+		}
+		faces
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vec3::Vec3;
+
+	#[test]
+	fn segment_through_grid() {
+		let mut points = vec![
+			Point::vertex(Vec3::new(0.0, 0.0, 0.0)),
+			Point::vertex(Vec3::new(10.0, 0.0, 0.0)),
+			Point::vertex(Vec3::new(0.0, 10.0, 0.0)),
+			Point::vertex(Vec3::new(5.0, 5.0, 0.0)),
+		];
+		for x in 1..9 {
+			for y in 1..(9 - x) {
+				points.push(Point::vertex(Vec3::new(x as f64 + 0.3, y as f64 + 0.1, 0.0)));
+			}
+		}
+		let mut cut = CutTriangle::new(points.iter().collect(), 2);
+		for p in 3..points.len() {
+			assert_eq!(cut.insert(p), Some(p));
+		}
+		assert!(cut.insert_segment(0, 3));
+		assert!(cut.fixed().contains(&(0, 3)));
+		// Cut in two, either side of the segment
+		let faces = cut.faces();
+		assert_eq!(faces.len(), 2);
+		// Four of the points are on the outline
+		assert_eq!(faces.iter().map(Vec::len).sum::<usize>(), 2 * points.len() - 6);
+	}
+
+	#[test]
+	fn outside_point() {
+		let points = [
+			Point::vertex(Vec3::new(0.0, 0.0, 0.0)),
+			Point::vertex(Vec3::new(10.0, 0.0, 0.0)),
+			Point::vertex(Vec3::new(0.0, 10.0, 0.0)),
+			Point::vertex(Vec3::new(2.0, 2.0, 0.0)),
+			Point::vertex(Vec3::new(20.0, 20.0, 0.0)),
+		];
+		let mut cut = CutTriangle::new(points.iter().collect(), 2);
+		assert_eq!(cut.insert(3), Some(3));
+		assert_eq!(cut.insert(4), None);
+		// A segment to a point that isn't there can't become an edge, and
+		// mustn't join the regions on either side of it
+		assert!(!cut.insert_segment(3, 4));
+		assert!(cut.fixed().is_empty());
+		assert!(cut.insert_segment(0, 3));
+		assert_eq!(cut.faces().len(), 1);
+	}
+}
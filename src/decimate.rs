@@ -0,0 +1,446 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use wasm_bindgen::prelude::*;
+
+use crate::linalg::solve3;
+use crate::mesh::{Mesh, MeshBuffers};
+use crate::vec3::Vec3;
+
+/// Weight of the planes that hold boundary and feature edges in place,
+/// relative to the planes of the faces themselves.
+const CONSTRAINT_WEIGHT: f64 = 1000.0;
+
+/// Symmetric 4x4 matrix measuring the sum of squared distances of a point to
+/// a set of planes (Garland & Heckbert, "Surface Simplification Using Quadric
+/// Error Metrics"). Stored as the upper triangle.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Quadric {
+	a2: f64,
+	ab: f64,
+	ac: f64,
+	ad: f64,
+	b2: f64,
+	bc: f64,
+	bd: f64,
+	c2: f64,
+	cd: f64,
+	d2: f64,
+}
+
+impl Quadric {
+	/// Quadric of the plane through `p` with unit normal `n`, scaled by `w`.
+	pub fn from_plane(n: Vec3, p: Vec3, w: f64) -> Quadric {
+		let d = -n.dot(p);
+		Quadric {
+			a2: w * n.x * n.x,
+			ab: w * n.x * n.y,
+			ac: w * n.x * n.z,
+			ad: w * n.x * d,
+			b2: w * n.y * n.y,
+			bc: w * n.y * n.z,
+			bd: w * n.y * d,
+			c2: w * n.z * n.z,
+			cd: w * n.z * d,
+			d2: w * d * d,
+		}
+	}
+
+	pub fn add(&self, o: &Quadric) -> Quadric {
+		Quadric {
+			a2: self.a2 + o.a2,
+			ab: self.ab + o.ab,
+			ac: self.ac + o.ac,
+			ad: self.ad + o.ad,
+			b2: self.b2 + o.b2,
+			bc: self.bc + o.bc,
+			bd: self.bd + o.bd,
+			c2: self.c2 + o.c2,
+			cd: self.cd + o.cd,
+			d2: self.d2 + o.d2,
+		}
+	}
+
+	pub fn error(&self, p: Vec3) -> f64 {
+		let (x, y, z) = (p.x, p.y, p.z);
+		let e = x * (self.a2 * x + 2.0 * (self.ab * y + self.ac * z + self.ad))
+			+ y * (self.b2 * y + 2.0 * (self.bc * z + self.bd))
+			+ z * (self.c2 * z + 2.0 * self.cd)
+			+ self.d2;
+		e.max(0.0)
+	}
+
+	/// The point with the least error, if it is well defined.
+	pub fn minimizer(&self) -> Option<Vec3> {
+		let m = [
+			[self.a2, self.ab, self.ac],
+			[self.ab, self.b2, self.bc],
+			[self.ac, self.bc, self.c2],
+		];
+		solve3(&m, Vec3::new(-self.ad, -self.bd, -self.cd), 1e-9)
+	}
+}
+
+#[derive(PartialEq)]
+struct Candidate {
+	cost: f64,
+	a: u32,
+	b: u32,
+	versions: (u32, u32),
+	target: Vec3,
+}
+
+impl Eq for Candidate {}
+
+// Reversed so the BinaryHeap pops the cheapest collapse first
+impl Ord for Candidate {
+	fn cmp(&self, other: &Self) -> Ordering {
+		other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+	}
+}
+
+impl PartialOrd for Candidate {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+/// Incremental edge-collapse simplifier. Collapses can be run in several
+/// steps, taking a snapshot of the mesh in between.
+pub(crate) struct Decimator {
+	positions: Vec<Vec3>,
+	quadrics: Vec<Quadric>,
+	triangles: Vec<[u32; 3]>,
+	alive: Vec<bool>,
+	vertex_triangles: Vec<Vec<u32>>,
+	versions: Vec<u32>,
+	heap: BinaryHeap<Candidate>,
+	/// Collapses that failed the manifold or flip checks, to retry later
+	rejected: Vec<(u32, u32, (u32, u32))>,
+	live_triangles: usize,
+}
+
+impl Decimator {
+	/// Edges whose faces meet at more than `feature_angle` radians are
+	/// preserved like boundary edges. Pass 0 to only preserve boundaries.
+	pub fn new(mesh: &Mesh, feature_angle: f64) -> Decimator {
+		let n = mesh.positions.len();
+		let mut quadrics = vec![Quadric::default(); n];
+		let mut alive = vec![true; mesh.triangles.len()];
+		let edge_faces = mesh.edge_faces();
+		let normals: Vec<Vec3> = (0..mesh.triangles.len()).map(|t| mesh.face_normal(t)).collect();
+
+		for (t, tri) in mesh.triangles.iter().enumerate() {
+			if mesh.is_degenerate(t) {
+				alive[t] = false;
+				continue;
+			}
+			let q = Quadric::from_plane(normals[t], mesh.positions[tri[0] as usize], 1.0);
+			for &v in tri {
+				quadrics[v as usize] = quadrics[v as usize].add(&q);
+			}
+		}
+
+		let cos_feature = feature_angle.cos();
+		for (&(a, b), faces) in &edge_faces {
+			let constrained = match faces.len() {
+				2 => feature_angle > 0.0 && normals[faces[0] as usize].dot(normals[faces[1] as usize]) < cos_feature,
+				_ => true,
+			};
+			if !constrained {
+				continue;
+			}
+			let (pa, pb) = (mesh.positions[a as usize], mesh.positions[b as usize]);
+			let edge = pb - pa;
+			for &f in faces {
+				// Plane through the edge, perpendicular to the face
+				let n = edge.cross(normals[f as usize]).normalized();
+				let q = Quadric::from_plane(n, pa, CONSTRAINT_WEIGHT);
+				quadrics[a as usize] = quadrics[a as usize].add(&q);
+				quadrics[b as usize] = quadrics[b as usize].add(&q);
+			}
+		}
+
+		let mut dec = Decimator {
+			positions: mesh.positions.clone(),
+			quadrics,
+			triangles: mesh.triangles.clone(),
+			live_triangles: alive.iter().filter(|a| **a).count(),
+			alive,
+			vertex_triangles: vec![Vec::new(); n],
+			versions: vec![0; n],
+			heap: BinaryHeap::with_capacity(edge_faces.len()),
+			rejected: Vec::new(),
+		};
+		for (t, tri) in dec.triangles.iter().enumerate() {
+			if dec.alive[t] {
+				for &v in tri {
+					dec.vertex_triangles[v as usize].push(t as u32);
+				}
+			}
+		}
+		// Sorted so that ties between collapses are broken the same way
+		// every time
+		let mut edges: Vec<(u32, u32)> = edge_faces.keys().copied().collect();
+		edges.sort_unstable();
+		for (a, b) in edges {
+			dec.push_candidate(a, b);
+		}
+		dec
+	}
+
+	fn push_candidate(&mut self, a: u32, b: u32) {
+		let q = self.quadrics[a as usize].add(&self.quadrics[b as usize]);
+		let (pa, pb) = (self.positions[a as usize], self.positions[b as usize]);
+		let mut best = (q.error(pa), pa);
+		for &p in &[pb, (pa + pb) * 0.5] {
+			let e = q.error(p);
+			if e < best.0 {
+				best = (e, p);
+			}
+		}
+		if let Some(p) = q.minimizer() {
+			// Ignore minimizers far away from the edge, which come from
+			// nearly-singular quadrics.
+			let len = (pb - pa).length();
+			if (p - pa).length() <= 2.0 * len && (p - pb).length() <= 2.0 * len {
+				let e = q.error(p);
+				if e < best.0 {
+					best = (e, p);
+				}
+			}
+		}
+		self.heap.push(Candidate {
+			cost: best.0,
+			a,
+			b,
+			versions: (self.versions[a as usize], self.versions[b as usize]),
+			target: best.1,
+		});
+	}
+
+	/// Checks that collapsing b into a at `target` keeps the mesh manifold and
+	/// doesn't flip any triangle.
+	fn can_collapse(&self, a: u32, b: u32, target: Vec3) -> bool {
+		// Link condition: the only vertices adjacent to both a and b must be
+		// the apexes of the triangles on edge ab.
+		let mut shared_faces = 0;
+		let mut link_a = Vec::with_capacity(12);
+		for &t in &self.vertex_triangles[a as usize] {
+			let tri = self.triangles[t as usize];
+			if tri.contains(&b) {
+				shared_faces += 1;
+			}
+			link_a.extend(tri.iter().filter(|&&v| v != a));
+		}
+		let mut common = Vec::with_capacity(4);
+		for &t in &self.vertex_triangles[b as usize] {
+			for &v in &self.triangles[t as usize] {
+				if v != a && v != b && link_a.contains(&v) && !common.contains(&v) {
+					common.push(v);
+				}
+			}
+		}
+		if shared_faces == 0 || common.len() != shared_faces {
+			return false;
+		}
+
+		for &(v, other) in &[(a, b), (b, a)] {
+			for &t in &self.vertex_triangles[v as usize] {
+				let tri = self.triangles[t as usize];
+				if tri.contains(&other) {
+					continue;
+				}
+				let p = |i: u32| -> Vec3 {
+					if i == v {
+						target
+					} else {
+						self.positions[i as usize]
+					}
+				};
+				let old = self.face_cross(tri);
+				let new = (p(tri[1]) - p(tri[0])).cross(p(tri[2]) - p(tri[0]));
+				if new.length() == 0.0 || new.dot(old) < 0.2 * new.length() * old.length() {
+					return false;
+				}
+			}
+		}
+		true
+	}
+
+	fn face_cross(&self, tri: [u32; 3]) -> Vec3 {
+		let [a, b, c] = [
+			self.positions[tri[0] as usize],
+			self.positions[tri[1] as usize],
+			self.positions[tri[2] as usize],
+		];
+		(b - a).cross(c - a)
+	}
+
+	fn collapse(&mut self, a: u32, b: u32, target: Vec3) {
+		self.positions[a as usize] = target;
+		self.quadrics[a as usize] = self.quadrics[a as usize].add(&self.quadrics[b as usize]);
+		self.versions[b as usize] += 1;
+
+		let b_tris = std::mem::take(&mut self.vertex_triangles[b as usize]);
+		for t in b_tris {
+			let tri = &mut self.triangles[t as usize];
+			if tri.contains(&a) {
+				self.alive[t as usize] = false;
+				self.live_triangles -= 1;
+			} else {
+				for v in tri.iter_mut() {
+					if *v == b {
+						*v = a;
+					}
+				}
+				self.vertex_triangles[a as usize].push(t);
+			}
+		}
+		let alive = &self.alive;
+		self.vertex_triangles[a as usize].retain(|&t| alive[t as usize]);
+		// Neighbours lost triangles too
+		let mut neighbours = Vec::with_capacity(12);
+		for &t in &self.vertex_triangles[a as usize] {
+			for &v in &self.triangles[t as usize] {
+				if v != a && !neighbours.contains(&v) {
+					neighbours.push(v);
+				}
+			}
+		}
+		for &v in &neighbours {
+			self.vertex_triangles[v as usize].retain(|&t| alive[t as usize]);
+		}
+
+		self.versions[a as usize] += 1;
+		for v in neighbours {
+			self.push_candidate(a, v);
+		}
+	}
+
+	/// Collapses edges until at most `target_triangles` remain or the next
+	/// collapse would move the surface by more than `max_error`. Either limit
+	/// is ignored if it is 0.
+	pub fn run(&mut self, target_triangles: usize, max_error: f64) {
+		let max_cost = if max_error > 0.0 {
+			max_error * max_error
+		} else {
+			f64::INFINITY
+		};
+		loop {
+			let mut collapsed = false;
+			while self.live_triangles > target_triangles {
+				let c = match self.heap.peek() {
+					Some(c) => c,
+					None => break,
+				};
+				let (a, b) = (c.a, c.b);
+				if c.versions != (self.versions[a as usize], self.versions[b as usize]) {
+					self.heap.pop();
+					continue;
+				}
+				if c.cost > max_cost {
+					break;
+				}
+				let c = self.heap.pop().unwrap();
+				if self.can_collapse(a, b, c.target) {
+					self.collapse(a, b, c.target);
+					collapsed = true;
+				} else {
+					self.rejected.push((a, b, c.versions));
+				}
+			}
+
+			// Collapses elsewhere may have made rejected ones valid, since the
+			// links of the vertices around a collapse change too.
+			if !collapsed || self.live_triangles <= target_triangles {
+				break;
+			}
+			for (a, b, versions) in std::mem::take(&mut self.rejected) {
+				if versions == (self.versions[a as usize], self.versions[b as usize]) {
+					self.push_candidate(a, b);
+				}
+			}
+		}
+	}
+
+	/// The current state of the mesh, without unused vertices.
+	pub fn to_mesh(&self) -> Mesh {
+		let tris: Vec<u32> = (0..self.triangles.len() as u32)
+			.filter(|&t| self.alive[t as usize])
+			.collect();
+		let full = Mesh {
+			positions: self.positions.clone(),
+			triangles: self.triangles.clone(),
+		};
+		full.submesh(&tris)
+	}
+}
+
+pub(crate) fn simplify_impl(
+	vertices: &[f32],
+	v_indices: &[u32],
+	target_triangles: u32,
+	max_error: f64,
+	feature_angle: f64,
+) -> Result<MeshBuffers, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	let mut dec = Decimator::new(&mesh, feature_angle);
+	dec.run(target_triangles as usize, max_error);
+	Ok(dec.to_mesh().to_buffers())
+}
+
+/// Simplify a mesh from `parseSTLMesh` by collapsing edges in order of their
+/// quadric error. Stops when `target_triangles` remain or when the next
+/// collapse would move the surface by more than `max_error`; pass 0 to
+/// ignore either limit. Boundary edges, and edges whose faces meet at more
+/// than `feature_angle` radians, are kept in place.
+#[wasm_bindgen]
+pub fn simplify(
+	vertices: &[f32],
+	v_indices: &[u32],
+	target_triangles: u32,
+	max_error: f64,
+	feature_angle: f64,
+) -> Result<MeshBuffers, JsValue> {
+	simplify_impl(vertices, v_indices, target_triangles, max_error, feature_angle).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tessellated_cube() {
+		let mesh = Mesh::tessellated_cube(Vec3::new(-1.0, -1.0, -1.0), 2.0, 8);
+		assert_eq!(mesh.triangles.len(), 6 * 8 * 8 * 2);
+		let (vertices, _, v_indices, _) = mesh.parsed();
+
+		// Flat faces collapse for free, so only the error limit stops it
+		let res = simplify_impl(&vertices, &v_indices, 0, 1e-6, 0.5).unwrap();
+		assert_eq!(res.triangle_count(), 12);
+		assert_eq!(res.vertex_count(), 8);
+		assert!((res.volume() - 8.0).abs() < 1e-9);
+		assert_eq!(&*res.bounds(), &[-1.0, -1.0, -1.0, 1.0, 1.0, 1.0]);
+
+		let res = simplify_impl(&vertices, &v_indices, 100, 0.0, 0.5).unwrap();
+		assert!(res.triangle_count() <= 100);
+		assert!((res.volume() - 8.0).abs() < 1e-9);
+	}
+
+	#[test]
+	fn open_grid_keeps_boundary() {
+		let mesh = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 1.0, 10);
+		// Keep only the bottom face, a 10x10 grid
+		let bottom: Vec<u32> = (0..mesh.triangles.len() as u32)
+			.filter(|&t| mesh.corners(t as usize).iter().all(|p| p.z == 0.0))
+			.collect();
+		let mesh = mesh.submesh(&bottom);
+		let mut dec = Decimator::new(&mesh, 0.0);
+		dec.run(0, 1e-6);
+		let res = dec.to_mesh();
+		assert_eq!(res.triangles.len(), 2);
+		let area: f64 = (0..2).map(|t| res.face_cross(t).length() / 2.0).sum();
+		assert!((area - 1.0).abs() < 1e-9);
+	}
+}
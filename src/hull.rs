@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+
+use crate::mesh::{Mesh, MeshBuffers};
+use crate::predicates::{orient2d, orient3d};
+use crate::vec3::Vec3;
+
+struct Face {
+	v: [u32; 3],
+	/// Unit normal, only used to pick the farthest outside point
+	normal: Vec3,
+	/// Points strictly above the face that no earlier face has claimed
+	outside: Vec<u32>,
+	alive: bool,
+}
+
+/// Incremental quickhull (Barber et al., "The Quickhull Algorithm for Convex
+/// Hulls"). Which side of a face a point is on is decided with exact
+/// predicates, and points on a face's plane count as inside, so coplanar and
+/// duplicate points never produce degenerate or flipped faces.
+struct Hull<'a> {
+	points: &'a [Vec3],
+	faces: Vec<Face>,
+	/// Face on the left of each directed edge
+	edges: HashMap<(u32, u32), usize>,
+}
+
+impl<'a> Hull<'a> {
+	fn is_above(&self, f: usize, p: u32) -> bool {
+		let [a, b, c] = self.faces[f].v.map(|v| self.points[v as usize]);
+		orient3d(a, b, c, self.points[p as usize]) < 0.0
+	}
+
+	fn add_face(&mut self, v: [u32; 3]) -> usize {
+		let [a, b, c] = v.map(|i| self.points[i as usize]);
+		let f = self.faces.len();
+		self.faces.push(Face {
+			v,
+			normal: (b - a).cross(c - a).normalized(),
+			outside: Vec::new(),
+			alive: true,
+		});
+		for i in 0..3 {
+			self.edges.insert((v[i], v[(i + 1) % 3]), f);
+		}
+		f
+	}
+
+	/// Gives `p` to the first of `faces` it is above, if any.
+	fn assign(&mut self, p: u32, faces: &[usize]) {
+		if let Some(&f) = faces.iter().find(|&&f| self.is_above(f, p)) {
+			self.faces[f].outside.push(p);
+		}
+	}
+
+	fn add_point(&mut self, f: usize) {
+		let face = &self.faces[f];
+		let base = self.points[face.v[0] as usize];
+		let eye = *face
+			.outside
+			.iter()
+			.max_by(|&&p, &&q| {
+				let dp = face.normal.dot(self.points[p as usize] - base);
+				let dq = face.normal.dot(self.points[q as usize] - base);
+				dp.total_cmp(&dq)
+			})
+			.unwrap();
+
+		// Faces that can see the eye point form a connected region
+		let mut visible = vec![f];
+		self.faces[f].alive = false;
+		let mut k = 0;
+		while k < visible.len() {
+			let v = self.faces[visible[k]].v;
+			for i in 0..3 {
+				let g = self.edges[&(v[(i + 1) % 3], v[i])];
+				if self.faces[g].alive && self.is_above(g, eye) {
+					self.faces[g].alive = false;
+					visible.push(g);
+				}
+			}
+			k += 1;
+		}
+
+		// Join the eye to every edge on the boundary of the visible region
+		let mut horizon = Vec::new();
+		for &g in &visible {
+			let v = self.faces[g].v;
+			for i in 0..3 {
+				let (a, b) = (v[i], v[(i + 1) % 3]);
+				if self.faces[self.edges[&(b, a)]].alive {
+					horizon.push((a, b));
+				}
+			}
+		}
+		for &g in &visible {
+			let v = self.faces[g].v;
+			for i in 0..3 {
+				self.edges.remove(&(v[i], v[(i + 1) % 3]));
+			}
+		}
+		let new_faces: Vec<usize> = horizon.iter().map(|&(a, b)| self.add_face([a, b, eye])).collect();
+
+		for g in visible {
+			for p in std::mem::take(&mut self.faces[g].outside) {
+				if p != eye {
+					self.assign(p, &new_faces);
+				}
+			}
+		}
+	}
+}
+
+/// The distinct points, in a deterministic order.
+fn unique_points(points: &[Vec3]) -> Vec<Vec3> {
+	let mut sorted = points.to_vec();
+	sorted.sort_by(|p, q| p.x.total_cmp(&q.x).then(p.y.total_cmp(&q.y)).then(p.z.total_cmp(&q.z)));
+	sorted.dedup();
+	sorted
+}
+
+/// Convex hull of a set of points as a closed mesh with outward-facing
+/// triangles. If the points are coplanar the hull is a flat, two-sided
+/// polygon, and if they are collinear it is empty.
+pub(crate) fn convex_hull(points: &[Vec3]) -> Mesh {
+	let points = unique_points(points);
+	if points.len() < 3 {
+		return Mesh::default();
+	}
+
+	// Initial tetrahedron: the lexicographically smallest point, the one
+	// farthest from it, the one farthest from the line through them and the
+	// one farthest from their plane.
+	let p0 = 0;
+	let farthest = |key: &dyn Fn(Vec3) -> f64| -> usize {
+		(0..points.len())
+			.max_by(|&i, &j| key(points[i]).total_cmp(&key(points[j])))
+			.unwrap()
+	};
+	let p1 = farthest(&|p| (p - points[p0]).length());
+	let axis = (points[p1] - points[p0]).normalized();
+	let p2 = farthest(&|p| {
+		let d = p - points[p0];
+		(d - axis * d.dot(axis)).length()
+	});
+	let normal = (points[p1] - points[p0]).cross(points[p2] - points[p0]);
+	if normal.length() == 0.0 {
+		return Mesh::default();
+	}
+	let p3 = farthest(&|p| normal.dot(p - points[p0]).abs());
+	let [a, b, c, d] = [p0, p1, p2, p3].map(|i| points[i]);
+	let orientation = orient3d(a, b, c, d);
+	if orientation == 0.0 {
+		return flat_hull(&points, normal);
+	}
+
+	let mut hull = Hull {
+		points: &points,
+		faces: Vec::new(),
+		edges: HashMap::new(),
+	};
+	let [p0, p1, p2, p3] = [p0, p1, p2, p3].map(|i| i as u32);
+	// Wind the faces so that the remaining vertex is below each of them
+	let (p1, p2) = if orientation > 0.0 { (p1, p2) } else { (p2, p1) };
+	let initial = [
+		hull.add_face([p0, p1, p2]),
+		hull.add_face([p0, p3, p1]),
+		hull.add_face([p1, p3, p2]),
+		hull.add_face([p2, p3, p0]),
+	];
+	for p in 0..points.len() as u32 {
+		if p != p0 && p != p1 && p != p2 && p != p3 {
+			hull.assign(p, &initial);
+		}
+	}
+
+	let mut f = 0;
+	while f < hull.faces.len() {
+		if hull.faces[f].alive && !hull.faces[f].outside.is_empty() {
+			hull.add_point(f);
+		}
+		f += 1;
+	}
+
+	let triangles: Vec<[u32; 3]> = hull.faces.iter().filter(|f| f.alive).map(|f| f.v).collect();
+	let all = Mesh {
+		positions: points.clone(),
+		triangles,
+	};
+	let tris: Vec<u32> = (0..all.triangles.len() as u32).collect();
+	all.submesh(&tris)
+}
+
+/// Hull of coplanar points: their 2D hull (Andrew's monotone chain), with
+/// triangles facing both ways.
+fn flat_hull(points: &[Vec3], normal: Vec3) -> Mesh {
+	// Drop the coordinate the plane is most perpendicular to, which keeps the
+	// projection exact
+	let (ax, ay, az) = (normal.x.abs(), normal.y.abs(), normal.z.abs());
+	let (i, j) = if az >= ax && az >= ay {
+		(0, 1)
+	} else if ax >= ay {
+		(1, 2)
+	} else {
+		(2, 0)
+	};
+	let mut order: Vec<usize> = (0..points.len()).collect();
+	order.sort_by(|&p, &q| {
+		points[p][i]
+			.total_cmp(&points[q][i])
+			.then(points[p][j].total_cmp(&points[q][j]))
+	});
+	let turns_left = |o: usize, a: usize, b: usize| {
+		orient2d(
+			points[o][i],
+			points[o][j],
+			points[a][i],
+			points[a][j],
+			points[b][i],
+			points[b][j],
+		) > 0.0
+	};
+	let mut chain: Vec<usize> = Vec::with_capacity(2 * points.len());
+	for pass in 0..2 {
+		let start = chain.len();
+		for &p in order.iter() {
+			while chain.len() >= start + 2 && !turns_left(chain[chain.len() - 2], chain[chain.len() - 1], p) {
+				chain.pop();
+			}
+			chain.push(p);
+		}
+		chain.pop();
+		if pass == 0 {
+			order.reverse();
+		}
+	}
+
+	let positions: Vec<Vec3> = chain.iter().map(|&p| points[p]).collect();
+	let mut triangles = Vec::new();
+	for k in 1..positions.len().saturating_sub(1) as u32 {
+		triangles.push([0, k, k + 1]);
+		triangles.push([0, k + 1, k]);
+	}
+	Mesh { positions, triangles }
+}
+
+/// Distinct face normals of a hull with the total area facing each way,
+/// largest first. With `either_way`, opposite normals count as the same
+/// direction.
+pub(crate) fn face_directions(hull: &Mesh, either_way: bool) -> Vec<(Vec3, f64)> {
+	let mut directions = HashMap::<[i64; 3], (Vec3, f64)>::new();
+	for t in 0..hull.triangles.len() {
+		let cross = hull.face_cross(t);
+		let area = cross.length();
+		if area == 0.0 {
+			continue;
+		}
+		let mut n = cross / area;
+		if either_way && (n.x < 0.0 || (n.x == 0.0 && (n.y < 0.0 || (n.y == 0.0 && n.z < 0.0)))) {
+			n = -n;
+		}
+		let key = [n.x, n.y, n.z].map(|c| (c * 1e9).round() as i64);
+		directions.entry(key).or_insert((n, 0.0)).1 += area / 2.0;
+	}
+	let mut directions: Vec<(Vec3, f64)> = directions.into_values().collect();
+	directions.sort_by(|a, b| b.1.total_cmp(&a.1));
+	directions
+}
+
+pub(crate) fn convex_hull_impl(vertices: &[f32], v_indices: &[u32]) -> Result<MeshBuffers, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	Ok(convex_hull(&mesh.positions).to_buffers())
+}
+
+/// Convex hull of the vertices of a mesh from `parseSTLMesh`, as a closed
+/// mesh. Its volume is available from the result.
+#[wasm_bindgen(js_name = "convexHull")]
+pub fn convex_hull_export(vertices: &[f32], v_indices: &[u32]) -> Result<MeshBuffers, JsValue> {
+	convex_hull_impl(vertices, v_indices).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cube_and_clutter() {
+		// A tessellated cube, so lots of coplanar points, twice over, with
+		// points inside it
+		let mut mesh = Mesh::tessellated_cube(Vec3::new(-1.0, -1.0, -1.0), 2.0, 6);
+		mesh.append(&Mesh::tessellated_cube(Vec3::new(-1.0, -1.0, -1.0), 2.0, 6));
+		let mut seed = 12345u32;
+		for _ in 0..200 {
+			let mut next = || {
+				seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+				seed as f64 / u32::MAX as f64 * 1.8 - 0.9
+			};
+			mesh.positions.push(Vec3::new(next(), next(), next()));
+		}
+		let hull = convex_hull(&mesh.positions);
+		assert!((hull.volume() - 8.0).abs() < 1e-9, "{}", hull.volume());
+		for (_, faces) in hull.edge_faces() {
+			assert_eq!(faces.len(), 2);
+		}
+		assert_eq!(hull.bounds(), (Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0)));
+
+		let (vertices, _, v_indices, _) = Mesh::cube(Vec3::new(0.0, 0.0, 0.0), 1.0).parsed();
+		let buffers = convex_hull_impl(&vertices, &v_indices).unwrap();
+		assert_eq!(buffers.vertex_count(), 8);
+		assert_eq!(buffers.triangle_count(), 12);
+		assert!((buffers.volume() - 1.0).abs() < 1e-12);
+	}
+
+	#[test]
+	fn degenerate() {
+		let square: Vec<Vec3> = (0..25)
+			.map(|i| Vec3::new((i % 5) as f64, (i / 5) as f64, 1.0))
+			.collect();
+		let hull = convex_hull(&square);
+		assert_eq!(hull.positions.len(), 4);
+		assert_eq!(hull.triangles.len(), 4);
+		assert_eq!(hull.volume(), 0.0);
+
+		let line: Vec<Vec3> = (0..5).map(|i| Vec3::new(i as f64, 0.0, 0.0)).collect();
+		assert!(convex_hull(&line).triangles.is_empty());
+	}
+}
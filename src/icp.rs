@@ -0,0 +1,298 @@
+use wasm_bindgen::prelude::*;
+
+use crate::linalg::{axis_angle, mul_mat3, mul_vec3, solve6, to_mat4x4, Mat3};
+use crate::mesh::Mesh;
+use crate::mesh_bvh::MeshBvh;
+use crate::obb::principal_axes;
+use crate::vec3::Vec3;
+
+/// Source vertices used for matching. Larger meshes are sampled evenly.
+const MAX_SAMPLES: usize = 4096;
+
+const IDENTITY: Mat3 = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// A rigid transform aligning one mesh with another.
+#[wasm_bindgen]
+pub struct Registration {
+	transform: [f64; 16],
+	residuals: Vec<f64>,
+}
+
+#[wasm_bindgen]
+impl Registration {
+	/// Column-major 4x4 matrix taking the source mesh onto the target.
+	pub fn transform(&self) -> Box<[f64]> {
+		Box::new(self.transform)
+	}
+
+	/// Root mean square point-to-plane distance before each iteration, and
+	/// after the last one.
+	pub fn residuals(&self) -> Box<[f64]> {
+		self.residuals.clone().into_boxed_slice()
+	}
+
+	/// The final residual.
+	#[wasm_bindgen(js_name = "rmsError")]
+	pub fn rms_error(&self) -> f64 {
+		self.residuals.last().copied().unwrap_or(f64::NAN)
+	}
+}
+
+struct Matcher<'a> {
+	samples: Vec<Vec3>,
+	target: &'a MeshBvh,
+	max_distance: f64,
+}
+
+impl Matcher<'_> {
+	/// Each sample moved by (r, t), paired with the closest point and normal
+	/// on the target, leaving out pairs farther apart than `max_distance`.
+	fn pairs(&self, r: &Mat3, t: Vec3) -> Vec<(Vec3, Vec3, Vec3)> {
+		self
+			.samples
+			.iter()
+			.filter_map(|&p| {
+				let p = mul_vec3(r, p) + t;
+				let hit = self.target.closest(p, self.max_distance)?;
+				let (q, n) = (hit.point(), hit.normal());
+				Some((p, Vec3::new(q[0], q[1], q[2]), Vec3::new(n[0], n[1], n[2])))
+			})
+			.collect()
+	}
+
+	/// NaN if no sample has a match.
+	fn rms(&self, r: &Mat3, t: Vec3) -> f64 {
+		let pairs = self.pairs(r, t);
+		let sum: f64 = pairs.iter().map(|&(p, q, n)| (p - q).dot(n).powi(2)).sum();
+		(sum / pairs.len() as f64).sqrt()
+	}
+
+	/// Mean distance to the target, to tell apart orientations that are only
+	/// a half turn apart.
+	fn mean_distance(&self, r: &Mat3, t: Vec3) -> f64 {
+		let pairs = self.pairs(r, t);
+		pairs.iter().map(|&(p, q, _)| (p - q).length()).sum::<f64>() / pairs.len().max(1) as f64
+	}
+}
+
+/// Lines up the principal axes of the source with those of the target. The
+/// axes only define a frame up to half turns about each axis, so the four
+/// possibilities are tried.
+fn prealign(source: &Mesh, target: &Mesh, matcher: &Matcher) -> (Mat3, Vec3) {
+	let (source_center, source_axes) = principal_axes(source, &source.positions);
+	let (target_center, target_axes) = principal_axes(target, &target.positions);
+	[[1.0, 1.0, 1.0], [-1.0, -1.0, 1.0], [-1.0, 1.0, -1.0], [1.0, -1.0, -1.0]]
+		.iter()
+		.map(|signs| {
+			let mut r: Mat3 = [[0.0; 3]; 3];
+			for k in 0..3 {
+				for i in 0..3 {
+					for j in 0..3 {
+						r[i][j] += signs[k] * target_axes[k][i] * source_axes[k][j];
+					}
+				}
+			}
+			let t = target_center - mul_vec3(&r, source_center);
+			(matcher.mean_distance(&r, t), r, t)
+		})
+		.min_by(|a, b| a.0.total_cmp(&b.0))
+		.map(|(_, r, t)| (r, t))
+		.unwrap()
+}
+
+/// Point-to-plane ICP (Chen and Medioni, "Object Modeling by Registration of
+/// Multiple Range Images"), linearizing the rotation at each step. Fails if
+/// fewer samples match than it takes to fix all six degrees of freedom.
+fn register(
+	source: &Mesh,
+	target: &Mesh,
+	max_iterations: u32,
+	tolerance: f64,
+	max_distance: f64,
+	prealigned: bool,
+) -> Result<Registration, String> {
+	let target_bvh = MeshBvh::new(target.clone(), Vec::new());
+	let stride = source.positions.len().div_ceil(MAX_SAMPLES).max(1);
+	let matcher = Matcher {
+		samples: source.positions.iter().step_by(stride).copied().collect(),
+		target: &target_bvh,
+		max_distance,
+	};
+	let (lo, hi) = target.bounds();
+	let size = (hi - lo).length().max(f64::MIN_POSITIVE);
+
+	let (mut r, mut t) = if prealigned {
+		prealign(source, target, &matcher)
+	} else {
+		(IDENTITY, Vec3::ZERO)
+	};
+	let mut residuals = Vec::new();
+	for _ in 0..max_iterations {
+		let pairs = matcher.pairs(&r, t);
+		if pairs.len() < 6 {
+			return Err(format!(
+				"Only {} points are within {} of the target, at least 6 are needed",
+				pairs.len(),
+				max_distance
+			));
+		}
+		// Normal equations for the rotation w and translation dt minimizing
+		// the sum of ((w x p + dt + p - q) . n)^2
+		let mut ata = [[0.0; 6]; 6];
+		let mut atb = [0.0; 6];
+		let mut sum_sq = 0.0;
+		for &(p, q, n) in &pairs {
+			let c = p.cross(n);
+			let row = [c.x, c.y, c.z, n.x, n.y, n.z];
+			let residual = (p - q).dot(n);
+			sum_sq += residual * residual;
+			for i in 0..6 {
+				for j in 0..6 {
+					ata[i][j] += row[i] * row[j];
+				}
+				atb[i] -= row[i] * residual;
+			}
+		}
+		residuals.push((sum_sq / pairs.len() as f64).sqrt());
+		// Flat or rotationally symmetric targets leave some motions undefined
+		let Some(x) = solve6(ata, atb, 1e-12) else {
+			break;
+		};
+		let w = Vec3::new(x[0], x[1], x[2]);
+		let dt = Vec3::new(x[3], x[4], x[5]);
+		let dr = axis_angle(w);
+		r = mul_mat3(&dr, &r);
+		t = mul_vec3(&dr, t) + dt;
+		if w.length() + dt.length() / size < tolerance {
+			break;
+		}
+	}
+	residuals.push(matcher.rms(&r, t));
+	Ok(Registration {
+		transform: to_mat4x4(&r, t),
+		residuals,
+	})
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn register_meshes_impl(
+	source_vertices: &[f32],
+	source_indices: &[u32],
+	target_vertices: &[f32],
+	target_indices: &[u32],
+	max_iterations: u32,
+	tolerance: f64,
+	max_distance: f64,
+	prealign: bool,
+) -> Result<Registration, String> {
+	let source = Mesh::from_buffers(source_vertices, source_indices)?;
+	let target = Mesh::from_buffers(target_vertices, target_indices)?;
+	if source.triangles.is_empty() || target.triangles.is_empty() {
+		return Err(String::from("Both meshes must have triangles"));
+	}
+	if max_distance.is_nan() || max_distance <= 0.0 {
+		return Err(format!("Maximum distance must be positive, but is {}", max_distance));
+	}
+	register(&source, &target, max_iterations, tolerance, max_distance, prealign)
+}
+
+/// Align a source mesh with a target mesh, both from `parseSTLMesh`, by
+/// iterative closest points. Stops after `maxIterations` or once a step
+/// turns by less than `tolerance` radians and moves by less than
+/// `tolerance` times the target's size, e.g. 1e-6. Matches farther apart
+/// than `maxDistance` are ignored; pass Infinity to use them all. Fewer than
+/// 6 matches is an error rather than a fit. ICP only
+/// finds the nearest fit, so meshes that start far apart should be
+/// `prealign`ed by their principal axes first.
+#[allow(clippy::too_many_arguments)]
+#[wasm_bindgen(js_name = "registerMeshes")]
+pub fn register_meshes(
+	source_vertices: &[f32],
+	source_indices: &[u32],
+	target_vertices: &[f32],
+	target_indices: &[u32],
+	max_iterations: u32,
+	tolerance: f64,
+	max_distance: f64,
+	prealign: bool,
+) -> Result<Registration, JsValue> {
+	register_meshes_impl(
+		source_vertices,
+		source_indices,
+		target_vertices,
+		target_indices,
+		max_iterations,
+		tolerance,
+		max_distance,
+		prealign,
+	)
+	.map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::linalg::transform_point;
+
+	/// A 3x2x1 box with a 1x1x1 block on one end, so that no half turn maps
+	/// it onto itself.
+	fn part() -> Mesh {
+		let mut mesh = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 1.0, 6);
+		mesh
+			.positions
+			.iter_mut()
+			.for_each(|p| *p = Vec3::new(p.x * 3.0, p.y * 2.0, p.z));
+		mesh.append(&Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 1.0), 1.0, 3));
+		mesh
+	}
+
+	fn moved(mesh: &Mesh, w: Vec3, t: Vec3) -> Mesh {
+		let r = axis_angle(w);
+		Mesh {
+			positions: mesh.positions.iter().map(|&p| mul_vec3(&r, p) + t).collect(),
+			triangles: mesh.triangles.clone(),
+		}
+	}
+
+	#[test]
+	fn recovers_motion() {
+		let target = part();
+		let source = moved(&target, Vec3::new(0.05, -0.1, 0.08), Vec3::new(0.2, 0.1, -0.15));
+		let (sv, _, si, _) = source.parsed();
+		let (tv, _, ti, _) = target.parsed();
+		let reg = register_meshes_impl(&sv, &si, &tv, &ti, 50, 1e-9, f64::INFINITY, false).unwrap();
+		let residuals = reg.residuals();
+		assert!(residuals[0] > 0.01);
+		assert!(reg.rms_error() < 1e-5, "{:?}", residuals);
+		let m = reg.transform();
+		for (p, q) in source.positions.iter().zip(&target.positions) {
+			assert!((transform_point(&m, *p) - *q).length() < 1e-4);
+		}
+	}
+
+	#[test]
+	fn too_far_to_match() {
+		let target = part();
+		let source = moved(&target, Vec3::ZERO, Vec3::new(10.0, 0.0, 0.0));
+		let (sv, _, si, _) = source.parsed();
+		let (tv, _, ti, _) = target.parsed();
+		let register = |max_distance| register_meshes_impl(&sv, &si, &tv, &ti, 50, 1e-9, max_distance, false);
+		// Nothing is within reach, which mustn't read as a perfect fit
+		assert!(register(0.5).is_err());
+		assert!(register(0.0).is_err());
+		assert!(register(f64::NAN).is_err());
+		assert!(register(f64::INFINITY).is_ok());
+	}
+
+	#[test]
+	fn prealigned() {
+		// Turned too far for ICP alone
+		let target = part();
+		let source = moved(&target, Vec3::new(0.3, 0.2, 2.5), Vec3::new(5.0, -3.0, 1.0));
+		let reg = register(&source, &target, 50, 1e-9, f64::INFINITY, true).unwrap();
+		assert!(reg.rms_error() < 1e-5, "{:?}", reg.residuals);
+		for (p, q) in source.positions.iter().zip(&target.positions) {
+			assert!((transform_point(&reg.transform, *p) - *q).length() < 1e-4);
+		}
+	}
+}
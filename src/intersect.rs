@@ -0,0 +1,375 @@
+use std::ops::ControlFlow;
+
+use wasm_bindgen::prelude::*;
+
+use crate::bvh::{Aabb, Bvh};
+use crate::mesh::Mesh;
+use crate::predicates::{orient2d, orient3d};
+use crate::vec3::Vec3;
+
+/// Index of the largest component of the normal, used to pick the axis to
+/// drop when projecting coplanar geometry to 2D.
+pub(crate) fn dominant_axis(n: Vec3) -> usize {
+	let (x, y, z) = (n.x.abs(), n.y.abs(), n.z.abs());
+	if x >= y && x >= z {
+		0
+	} else if y >= z {
+		1
+	} else {
+		2
+	}
+}
+
+pub(crate) fn project(p: Vec3, drop: usize) -> (f64, f64) {
+	match drop {
+		0 => (p.y, p.z),
+		1 => (p.z, p.x),
+		_ => (p.x, p.y),
+	}
+}
+
+fn orient2d_p(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+	orient2d(a.0, a.1, b.0, b.1, c.0, c.1)
+}
+
+fn same_sign_or_zero(a: f64, b: f64, c: f64) -> bool {
+	(a >= 0.0 && b >= 0.0 && c >= 0.0) || (a <= 0.0 && b <= 0.0 && c <= 0.0)
+}
+
+fn point_in_triangle_2d(p: (f64, f64), a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> bool {
+	same_sign_or_zero(orient2d_p(a, b, p), orient2d_p(b, c, p), orient2d_p(c, a, p))
+}
+
+fn on_segment_2d(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> bool {
+	p.0 >= a.0.min(b.0) && p.0 <= a.0.max(b.0) && p.1 >= a.1.min(b.1) && p.1 <= a.1.max(b.1)
+}
+
+/// Whether two closed 2D segments share a point.
+fn segments_intersect_2d(a: (f64, f64), b: (f64, f64), c: (f64, f64), d: (f64, f64)) -> bool {
+	let d1 = orient2d_p(a, b, c);
+	let d2 = orient2d_p(a, b, d);
+	let d3 = orient2d_p(c, d, a);
+	let d4 = orient2d_p(c, d, b);
+	if ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0)) && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0)) {
+		return true;
+	}
+	(d1 == 0.0 && on_segment_2d(c, a, b))
+		|| (d2 == 0.0 && on_segment_2d(d, a, b))
+		|| (d3 == 0.0 && on_segment_2d(a, c, d))
+		|| (d4 == 0.0 && on_segment_2d(b, c, d))
+}
+
+/// Whether the closed segment st shares a point with the closed triangle abc.
+/// Exact for the given inputs.
+pub(crate) fn segment_triangle_intersect(s: Vec3, t: Vec3, a: Vec3, b: Vec3, c: Vec3) -> bool {
+	let os = orient3d(a, b, c, s);
+	let ot = orient3d(a, b, c, t);
+	if (os > 0.0 && ot > 0.0) || (os < 0.0 && ot < 0.0) {
+		return false;
+	}
+	if os == 0.0 && ot == 0.0 {
+		let drop = dominant_axis((b - a).cross(c - a));
+		let (s2, t2) = (project(s, drop), project(t, drop));
+		let (a2, b2, c2) = (project(a, drop), project(b, drop), project(c, drop));
+		return point_in_triangle_2d(s2, a2, b2, c2)
+			|| segments_intersect_2d(s2, t2, a2, b2)
+			|| segments_intersect_2d(s2, t2, b2, c2)
+			|| segments_intersect_2d(s2, t2, c2, a2);
+	}
+	// The segment crosses or touches the plane; check the crossing point is
+	// within the triangle by the side of the line st each edge passes.
+	let e1 = orient3d(s, t, a, b);
+	let e2 = orient3d(s, t, b, c);
+	let e3 = orient3d(s, t, c, a);
+	same_sign_or_zero(e1, e2, e3)
+}
+
+fn plane_sides(a: Vec3, b: Vec3, c: Vec3, pts: &[Vec3; 3]) -> [f64; 3] {
+	[
+		orient3d(a, b, c, pts[0]),
+		orient3d(a, b, c, pts[1]),
+		orient3d(a, b, c, pts[2]),
+	]
+}
+
+fn all_one_side(d: &[f64; 3]) -> bool {
+	(d[0] > 0.0 && d[1] > 0.0 && d[2] > 0.0) || (d[0] < 0.0 && d[1] < 0.0 && d[2] < 0.0)
+}
+
+/// Whether two closed triangles share a point. Exact for the given inputs.
+pub(crate) fn triangles_intersect(p: &[Vec3; 3], q: &[Vec3; 3]) -> bool {
+	if all_one_side(&plane_sides(p[0], p[1], p[2], q)) || all_one_side(&plane_sides(q[0], q[1], q[2], p)) {
+		return false;
+	}
+	// If the triangles meet, the boundary of their intersection lies on an edge
+	// of one of them.
+	(0..3).any(|i| segment_triangle_intersect(p[i], p[(i + 1) % 3], q[0], q[1], q[2]))
+		|| (0..3).any(|i| segment_triangle_intersect(q[i], q[(i + 1) % 3], p[0], p[1], p[2]))
+}
+
+/// Whether two triangles of the same mesh intersect anywhere other than along
+/// the vertices and edges they share.
+fn mesh_triangles_intersect(mesh: &Mesh, t1: usize, t2: usize) -> bool {
+	let i1 = mesh.triangles[t1];
+	let i2 = mesh.triangles[t2];
+	let p = mesh.corners(t1);
+	let q = mesh.corners(t2);
+
+	let mut shared = [(0, 0); 3];
+	let mut num_shared = 0;
+	for (i, &a) in i1.iter().enumerate() {
+		for (j, &b) in i2.iter().enumerate() {
+			if a == b {
+				shared[num_shared] = (i, j);
+				num_shared += 1;
+			}
+		}
+	}
+	match num_shared {
+		0 => triangles_intersect(&p, &q),
+		1 => {
+			// If the triangles overlap beyond the shared vertex, the nearer end
+			// of the overlap lies on an edge opposite it.
+			let (i, j) = shared[0];
+			let (a1, b1) = (p[(i + 1) % 3], p[(i + 2) % 3]);
+			let (a2, b2) = (q[(j + 1) % 3], q[(j + 2) % 3]);
+			let (s1, s2) = (orient3d(p[0], p[1], p[2], a2), orient3d(p[0], p[1], p[2], b2));
+			if (s1 > 0.0 && s2 > 0.0) || (s1 < 0.0 && s2 < 0.0) {
+				// Only the shared vertex touches the plane of the first triangle
+				return false;
+			}
+			segment_triangle_intersect(a1, b1, q[0], q[1], q[2]) || segment_triangle_intersect(a2, b2, p[0], p[1], p[2])
+		}
+		2 => {
+			// Triangles sharing an edge only overlap if they are coplanar and
+			// folded onto the same side of the edge.
+			let (e0, e1) = (p[shared[0].0], p[shared[1].0]);
+			let c1 = p[3 - shared[0].0 - shared[1].0];
+			let c2 = q[3 - shared[0].1 - shared[1].1];
+			if orient3d(e0, e1, c1, c2) != 0.0 {
+				return false;
+			}
+			let drop = dominant_axis((e1 - e0).cross(c1 - e0));
+			let (e0, e1) = (project(e0, drop), project(e1, drop));
+			let s1 = orient2d_p(e0, e1, project(c1, drop));
+			let s2 = orient2d_p(e0, e1, project(c2, drop));
+			s1 * s2 > 0.0
+		}
+		_ => true,
+	}
+}
+
+fn plane_crossing(s: Vec3, t: Vec3, a: Vec3, n: Vec3) -> Option<Vec3> {
+	let ds = (s - a).dot(n);
+	let dt = (t - a).dot(n);
+	if ds == dt {
+		return None;
+	}
+	Some(s.lerp(t, ds / (ds - dt)))
+}
+
+/// Approximate segment along which two intersecting triangles meet, for
+/// display. Exactness isn't needed here, only for deciding whether they meet.
+pub(crate) fn intersection_segment(p: &[Vec3; 3], q: &[Vec3; 3]) -> (Vec3, Vec3) {
+	let np = (p[1] - p[0]).cross(p[2] - p[0]);
+	let nq = (q[1] - q[0]).cross(q[2] - q[0]);
+	let mut pts = Vec::with_capacity(6);
+	for (tri, other, n) in &[(p, q, nq), (q, p, np)] {
+		for i in 0..3 {
+			let (s, t) = (tri[i], tri[(i + 1) % 3]);
+			if segment_triangle_intersect(s, t, other[0], other[1], other[2]) {
+				match plane_crossing(s, t, other[0], *n) {
+					Some(x) => pts.push(x),
+					None => {
+						// Coplanar edge, keep whichever ends lie in the other triangle
+						for &e in &[s, t] {
+							if segment_triangle_intersect(e, e, other[0], other[1], other[2]) {
+								pts.push(e);
+							}
+						}
+					}
+				}
+			}
+		}
+	}
+
+	let mut best = (
+		0.0,
+		pts.first().copied().unwrap_or(p[0]),
+		pts.first().copied().unwrap_or(p[0]),
+	);
+	for (i, &a) in pts.iter().enumerate() {
+		for &b in &pts[i + 1..] {
+			let d = (b - a).length();
+			if d > best.0 {
+				best = (d, a, b);
+			}
+		}
+	}
+	(best.1, best.2)
+}
+
+/// Finds pairs of triangles that intersect other than along shared edges and
+/// vertices. Stops after `max_pairs` pairs if it is non-zero. Returns the
+/// pairs, each ordered by triangle index, and whether the search was cut short.
+pub(crate) fn self_intersections(mesh: &Mesh, max_pairs: usize) -> (Vec<(u32, u32)>, bool) {
+	let boxes: Vec<Aabb> = (0..mesh.triangles.len()).map(|t| Aabb::of_triangle(mesh, t)).collect();
+	let bvh = Bvh::build(&boxes);
+	let degenerate: Vec<bool> = (0..mesh.triangles.len())
+		.map(|t| mesh.face_cross(t) == Vec3::ZERO)
+		.collect();
+
+	let mut pairs = Vec::new();
+	let mut truncated = false;
+	bvh.self_overlaps(&boxes, |a, b| {
+		if degenerate[a as usize] || degenerate[b as usize] {
+			return ControlFlow::Continue(());
+		}
+		if mesh_triangles_intersect(mesh, a as usize, b as usize) {
+			if max_pairs > 0 && pairs.len() == max_pairs {
+				truncated = true;
+				return ControlFlow::Break(());
+			}
+			pairs.push((a.min(b), a.max(b)));
+		}
+		ControlFlow::Continue(())
+	});
+	pairs.sort_unstable();
+	(pairs, truncated)
+}
+
+/// Pairs of intersecting triangles found by `findSelfIntersections`.
+#[wasm_bindgen]
+pub struct SelfIntersections {
+	pairs: Vec<u32>,
+	segments: Vec<f32>,
+	truncated: bool,
+}
+
+#[wasm_bindgen]
+impl SelfIntersections {
+	/// Number of intersecting pairs.
+	pub fn count(&self) -> u32 {
+		(self.pairs.len() / 2) as u32
+	}
+
+	/// Triangle indices, two per intersecting pair.
+	pub fn pairs(&self) -> Box<[u32]> {
+		self.pairs.clone().into_boxed_slice()
+	}
+
+	/// Endpoints of the intersection of each pair, 6 floats per pair. Empty
+	/// unless segments were requested.
+	pub fn segments(&self) -> Box<[f32]> {
+		self.segments.clone().into_boxed_slice()
+	}
+
+	/// Whether the search stopped at the maximum number of pairs.
+	pub fn truncated(&self) -> bool {
+		self.truncated
+	}
+}
+
+pub(crate) fn find_self_intersections_impl(
+	vertices: &[f32],
+	v_indices: &[u32],
+	with_segments: bool,
+	max_pairs: u32,
+) -> Result<SelfIntersections, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	let (found, truncated) = self_intersections(&mesh, max_pairs as usize);
+
+	let mut pairs = Vec::with_capacity(found.len() * 2);
+	let mut segments = Vec::new();
+	for &(a, b) in &found {
+		pairs.extend_from_slice(&[a, b]);
+		if with_segments {
+			let (s, t) = intersection_segment(&mesh.corners(a as usize), &mesh.corners(b as usize));
+			segments.extend_from_slice(&[s.x as f32, s.y as f32, s.z as f32, t.x as f32, t.y as f32, t.z as f32]);
+		}
+	}
+	Ok(SelfIntersections {
+		pairs,
+		segments,
+		truncated,
+	})
+}
+
+/// Find the pairs of triangles in a mesh from `parseSTLMesh` that intersect
+/// each other. Triangles touching along a shared edge or vertex don't count.
+/// If `with_segments` is set the intersection segments are computed for
+/// display. A non-zero `max_pairs` stops the search after that many pairs.
+#[wasm_bindgen(js_name = "findSelfIntersections")]
+pub fn find_self_intersections(
+	vertices: &[f32],
+	v_indices: &[u32],
+	with_segments: bool,
+	max_pairs: u32,
+) -> Result<SelfIntersections, JsValue> {
+	find_self_intersections_impl(vertices, v_indices, with_segments, max_pairs).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn closed_cube() {
+		let (vertices, _, v_indices, _) = Mesh::cube(Vec3::new(0.0, 0.0, 0.0), 1.0).parsed();
+		let res = find_self_intersections_impl(&vertices, &v_indices, true, 0).unwrap();
+		assert_eq!(res.count(), 0);
+
+		// Cubes sharing a face have coincident triangles, which do overlap
+		let mut mesh = Mesh::cube(Vec3::new(0.0, 0.0, 0.0), 1.0);
+		mesh.append(&Mesh::cube(Vec3::new(1.0, 0.0, 0.0), 1.0));
+		let (vertices, _, v_indices, _) = mesh.parsed();
+		let res = find_self_intersections_impl(&vertices, &v_indices, false, 0).unwrap();
+		assert!(res.count() > 0);
+	}
+
+	#[test]
+	fn overlapping_cubes() {
+		let mut mesh = Mesh::cube(Vec3::new(0.0, 0.0, 0.0), 1.0);
+		mesh.append(&Mesh::cube(Vec3::new(0.5, 0.25, 0.25), 1.0));
+		let (vertices, _, v_indices, _) = mesh.parsed();
+		let res = find_self_intersections_impl(&vertices, &v_indices, true, 0).unwrap();
+		assert!(res.count() > 0);
+		let pairs = res.pairs();
+		let segments = res.segments();
+		for (k, pair) in pairs.chunks(2).enumerate() {
+			// Every pair is between the two cubes
+			assert!(pair[0] < 12 && pair[1] >= 12);
+			// Intersections lie on the boundary of the overlap region
+			for s in segments[k * 6..k * 6 + 6].chunks(3) {
+				assert!(s[0] >= 0.5 - 1e-6 && s[0] <= 1.0 + 1e-6);
+			}
+		}
+
+		let res = find_self_intersections_impl(&vertices, &v_indices, false, 3).unwrap();
+		assert_eq!(res.count(), 3);
+		assert!(res.truncated());
+		assert!(res.segments().is_empty());
+	}
+
+	#[test]
+	fn folded_and_fanned_neighbours() {
+		let o = Vec3::new(0.0, 0.0, 0.0);
+		let x = Vec3::new(1.0, 0.0, 0.0);
+		let y = Vec3::new(0.0, 1.0, 0.0);
+		let mesh = Mesh {
+			positions: vec![
+				o,
+				x,
+				y,
+				Vec3::new(0.5, 0.2, 0.0),
+				Vec3::new(0.2, 0.5, 1.0),
+				Vec3::new(0.3, 0.3, -1.0),
+			],
+			triangles: vec![[0, 1, 2], [0, 1, 3], [0, 4, 5]],
+		};
+		// Triangle 1 is folded onto triangle 0 along their shared edge, and
+		// triangle 2 pokes through triangle 0 from their shared vertex.
+		let (pairs, _) = self_intersections(&mesh, 0);
+		assert_eq!(pairs, vec![(0, 1), (0, 2)]);
+	}
+}
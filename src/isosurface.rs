@@ -0,0 +1,296 @@
+use wasm_bindgen::prelude::*;
+
+use crate::mesh::{Mesh, MeshBuffers};
+use crate::vec3::Vec3;
+
+/// Values sampled at the corners of a regular grid, less than the iso value
+/// inside the solid, e.g. signed distances.
+pub(crate) struct ScalarGrid {
+	pub dims: [usize; 3],
+	pub origin: Vec3,
+	pub spacing: f64,
+	pub values: Vec<f32>,
+}
+
+impl ScalarGrid {
+	/// Index of the sample at (x, y, z), x + nx * (y + ny * z).
+	pub(crate) fn index(&self, [x, y, z]: [usize; 3]) -> usize {
+		x + self.dims[0] * (y + self.dims[1] * z)
+	}
+
+	pub(crate) fn point(&self, node: [usize; 3]) -> Vec3 {
+		self.origin + Vec3::new(node[0] as f64, node[1] as f64, node[2] as f64) * self.spacing
+	}
+
+	fn value(&self, node: [usize; 3]) -> f64 {
+		self.values[self.index(node)] as f64
+	}
+
+	/// Trilinear interpolation of the samples at `p`, if it's inside the grid.
+	pub(crate) fn interpolate(&self, p: Vec3) -> Option<f64> {
+		let local = (p - self.origin) / self.spacing;
+		let mut cell = [0; 3];
+		let mut t = [0.0; 3];
+		for i in 0..3 {
+			let last = self.dims[i] as f64 - 1.0;
+			if !(0.0..=last).contains(&local[i]) || last < 1.0 {
+				return None;
+			}
+			let c = local[i].floor().min(last - 1.0);
+			cell[i] = c as usize;
+			t[i] = local[i] - c;
+		}
+		let mut value = 0.0;
+		for k in 0..8 {
+			let o = corner_offset(k);
+			let w = (0..3).fold(1.0, |w, i| w * if o[i] == 1 { t[i] } else { 1.0 - t[i] });
+			value += w * self.value([cell[0] + o[0], cell[1] + o[1], cell[2] + o[2]]);
+		}
+		Some(value)
+	}
+}
+
+/// Offset of each corner of a cell, corner k at (k & 1, k >> 1 & 1, k >> 2 & 1).
+fn corner_offset(k: usize) -> [usize; 3] {
+	[k & 1, k >> 1 & 1, k >> 2 & 1]
+}
+
+/// A vertex inside each cell the surface passes through, at the mean of the
+/// points where it crosses the cell's edges, with the gradient of the
+/// trilinear interpolation there as its normal.
+fn cell_vertex(grid: &ScalarGrid, cell: [usize; 3], iso: f64) -> Option<(Vec3, Vec3)> {
+	let mut values = [0.0; 8];
+	for (k, v) in values.iter_mut().enumerate() {
+		let o = corner_offset(k);
+		*v = grid.value([cell[0] + o[0], cell[1] + o[1], cell[2] + o[2]]);
+	}
+	let mut sum = Vec3::ZERO;
+	let mut crossings = 0;
+	for k in 0..8 {
+		for axis in 0..3 {
+			let j = k | 1 << axis;
+			if j == k || (values[k] < iso) == (values[j] < iso) {
+				continue;
+			}
+			let o = corner_offset(k);
+			let mut p = [o[0] as f64, o[1] as f64, o[2] as f64];
+			p[axis] = (iso - values[k]) / (values[j] - values[k]);
+			sum += Vec3::new(p[0], p[1], p[2]);
+			crossings += 1;
+		}
+	}
+	if crossings == 0 {
+		return None;
+	}
+	let local = sum / crossings as f64;
+
+	let mut gradient = [0.0; 3];
+	for (k, &v) in values.iter().enumerate() {
+		let o = corner_offset(k);
+		let w = [0, 1, 2].map(|i| if o[i] == 1 { local[i] } else { 1.0 - local[i] });
+		let dw = [0, 1, 2].map(|i| if o[i] == 1 { 1.0 } else { -1.0 });
+		gradient[0] += v * dw[0] * w[1] * w[2];
+		gradient[1] += v * w[0] * dw[1] * w[2];
+		gradient[2] += v * w[0] * w[1] * dw[2];
+	}
+	let position = grid.origin
+		+ Vec3::new(
+			cell[0] as f64 + local.x,
+			cell[1] as f64 + local.y,
+			cell[2] as f64 + local.z,
+		) * grid.spacing;
+	Some((position, Vec3::new(gradient[0], gradient[1], gradient[2]).normalized()))
+}
+
+/// Naive surface nets (Gibson, "Constrained Elastic Surface Nets"), the dual
+/// of marching cubes: one vertex per cell the surface crosses, and a quad
+/// around every grid edge with one end inside and the other outside. Unlike
+/// marching cubes it needs no case tables and gives better shaped
+/// triangles. Faces point towards larger values. The surface is open where
+/// it leaves the grid.
+pub(crate) fn surface_nets(grid: &ScalarGrid, iso: f64) -> (Mesh, Vec<Vec3>) {
+	let mut mesh = Mesh::default();
+	let mut normals = Vec::new();
+	if grid.dims.iter().any(|&n| n < 2) {
+		return (mesh, normals);
+	}
+	let cells = grid.dims.map(|n| n - 1);
+	let cell_index = |c: [usize; 3]| c[0] + cells[0] * (c[1] + cells[1] * c[2]);
+	let mut cell_vertices = vec![u32::MAX; cells.iter().product()];
+	for z in 0..cells[2] {
+		for y in 0..cells[1] {
+			for x in 0..cells[0] {
+				if let Some((p, n)) = cell_vertex(grid, [x, y, z], iso) {
+					cell_vertices[cell_index([x, y, z])] = mesh.positions.len() as u32;
+					mesh.positions.push(p);
+					normals.push(n);
+				}
+			}
+		}
+	}
+
+	for axis in 0..3 {
+		// The other two axes in cyclic order, so that counterclockwise in
+		// (u, v) faces along the axis
+		let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+		for z in 0..grid.dims[2] {
+			for y in 0..grid.dims[1] {
+				for x in 0..grid.dims[0] {
+					let node = [x, y, z];
+					if node[axis] + 1 >= grid.dims[axis]
+						|| node[u] == 0
+						|| node[u] + 1 >= grid.dims[u]
+						|| node[v] == 0
+						|| node[v] + 1 >= grid.dims[v]
+					{
+						continue;
+					}
+					let mut next = node;
+					next[axis] += 1;
+					let inside = grid.value(node) < iso;
+					if inside == (grid.value(next) < iso) {
+						continue;
+					}
+					let quad = [(1, 1), (0, 1), (0, 0), (1, 0)].map(|(du, dv)| {
+						let mut cell = node;
+						cell[u] -= du;
+						cell[v] -= dv;
+						cell_vertices[cell_index(cell)]
+					});
+					let [a, b, c, d] = if inside {
+						quad
+					} else {
+						[quad[3], quad[2], quad[1], quad[0]]
+					};
+					// Split along the shorter diagonal
+					let p = |i: u32| mesh.positions[i as usize];
+					if (p(a) - p(c)).length() <= (p(b) - p(d)).length() {
+						mesh.triangles.extend([[a, b, c], [a, c, d]]);
+					} else {
+						mesh.triangles.extend([[a, b, d], [b, c, d]]);
+					}
+				}
+			}
+		}
+	}
+	(mesh, normals)
+}
+
+/// The surface of a grid in the buffer layout `parseSTLMesh` produces.
+pub(crate) fn contour(grid: &ScalarGrid, iso: f64) -> MeshBuffers {
+	let (mesh, normals) = surface_nets(grid, iso);
+	mesh.to_buffers_with_normals(&normals)
+}
+
+pub(crate) fn contour_grid_impl(
+	values: &[f32],
+	dims: &[u32],
+	origin: &[f64],
+	spacing: f64,
+	iso_value: f64,
+) -> Result<MeshBuffers, String> {
+	if dims.len() != 3 || origin.len() != 3 {
+		return Err(String::from("Dimensions and origin must have 3 entries"));
+	}
+	if !spacing.is_finite() || spacing <= 0.0 {
+		return Err(format!("Spacing must be positive, but is {}", spacing));
+	}
+	let dims = [dims[0] as usize, dims[1] as usize, dims[2] as usize];
+	let count = dims
+		.iter()
+		.try_fold(1usize, |n, &d| n.checked_mul(d))
+		.ok_or_else(|| format!("A {}x{}x{} grid is too large", dims[0], dims[1], dims[2]))?;
+	if values.len() != count {
+		return Err(format!(
+			"Expected {} values for a {}x{}x{} grid, got {}",
+			count,
+			dims[0],
+			dims[1],
+			dims[2],
+			values.len()
+		));
+	}
+	if values.iter().any(|v| v.is_nan()) {
+		return Err(String::from("Values must not be NaN"));
+	}
+	let grid = ScalarGrid {
+		dims,
+		origin: Vec3::new(origin[0], origin[1], origin[2]),
+		spacing,
+		values: values.to_vec(),
+	};
+	Ok(contour(&grid, iso_value))
+}
+
+/// Extract the surface where a sampled field crosses `isoValue`, e.g. the
+/// zero level of a signed distance field, as a mesh in the layout
+/// `parseSTLMesh` produces. The value at grid point (x, y, z) is at index
+/// x + nx * (y + ny * z) and position `origin` + (x, y, z) * `spacing`.
+/// Values below `isoValue` are inside; normals point towards larger values.
+#[wasm_bindgen(js_name = "contourGrid")]
+pub fn contour_grid(
+	values: &[f32],
+	dims: &[u32],
+	origin: &[f64],
+	spacing: f64,
+	iso_value: f64,
+) -> Result<MeshBuffers, JsValue> {
+	contour_grid_impl(values, dims, origin, spacing, iso_value).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sphere() {
+		let n = 31;
+		let origin = [-1.5, -1.5, -1.5];
+		let mut values = Vec::with_capacity(n * n * n);
+		for z in 0..n {
+			for y in 0..n {
+				for x in 0..n {
+					let p = Vec3::new(x as f64, y as f64, z as f64) * 0.1 + Vec3::new(-1.5, -1.5, -1.5);
+					values.push((p.length() - 1.0) as f32);
+				}
+			}
+		}
+		let dims = [n as u32; 3];
+		let buffers = contour_grid_impl(&values, &dims, &origin, 0.1, 0.0).unwrap();
+		let mesh = Mesh::from_buffers(&buffers.vertices(), &buffers.v_indices()).unwrap();
+		assert!(mesh.edge_faces().values().all(|faces| faces.len() == 2));
+		let expected = 4.0 / 3.0 * std::f64::consts::PI;
+		assert!(
+			(buffers.volume() - expected).abs() < 0.02 * expected,
+			"{}",
+			buffers.volume()
+		);
+		let normals = buffers.normals();
+		for (i, p) in mesh.positions.iter().enumerate() {
+			assert!((p.length() - 1.0).abs() < 0.01, "{:?}", p);
+			assert!(Vec3::from_f32(&normals, i).dot(p.normalized()) > 0.99);
+		}
+
+		// Everything inside at a higher level
+		let empty = contour_grid_impl(&values, &dims, &origin, 0.1, 10.0).unwrap();
+		assert_eq!(empty.triangle_count(), 0);
+		assert!(contour_grid_impl(&values[1..], &dims, &origin, 0.1, 0.0).is_err());
+		assert!(contour_grid_impl(&[], &[u32::MAX; 3], &origin, 0.1, 0.0).is_err());
+	}
+
+	#[test]
+	fn voxels() {
+		let mesh = Mesh::tessellated_cube(Vec3::new(0.1, 0.1, 0.1), 1.8, 3);
+		let grid = crate::voxel::voxelize(&mesh, 0.5, crate::voxel::VoxelFill::Winding).unwrap();
+		let buffers = grid.to_mesh();
+		let surface = Mesh::from_buffers(&buffers.vertices(), &buffers.v_indices()).unwrap();
+		assert!(surface.edge_faces().values().all(|faces| faces.len() == 2));
+		// The faces of the 2x2x2 block of voxels, with the edges cut off
+		let bounds = buffers.bounds();
+		assert!(bounds
+			.iter()
+			.zip([0.1, 0.1, 0.1, 2.1, 2.1, 2.1])
+			.all(|(b, e)| (b - e).abs() < 1e-9));
+		assert!(buffers.volume() > 6.5 && buffers.volume() < 8.0, "{}", buffers.volume());
+	}
+}
@@ -7,11 +7,68 @@ use std::collections::HashSet;
 
 use wasm_bindgen::prelude::*;
 
+mod arrangement;
+mod boolean;
+mod bvh;
+mod clip;
+mod compare;
+mod components;
+mod curvature;
+mod cut_triangle;
+mod decimate;
+mod hull;
+mod icp;
+mod intersect;
+mod isosurface;
+mod linalg;
+mod lod;
+mod measure;
+mod mesh;
+mod mesh_bvh;
+mod obb;
+mod offset;
+mod orient;
+mod overhang;
+mod predicates;
+mod sample;
+mod sdf;
+mod slice;
+mod smooth;
+mod subdivide;
+mod thickness;
+mod triangulate;
+mod vec3;
+mod voxel;
+
+pub use boolean::BooleanOperation;
+pub use clip::MeshHalves;
+pub use compare::MeshComparison;
+pub use components::Components;
+pub use curvature::Curvature;
+pub use icp::Registration;
+pub use intersect::SelfIntersections;
+pub use lod::LodChain;
+pub use measure::CylinderFit;
+pub use mesh::MeshBuffers;
+pub use mesh_bvh::{MeshBvh, RayHit};
+pub use obb::{BoxFit, OrientedBox};
+pub use offset::HollowedMesh;
+pub use orient::PrintOrientation;
+pub use overhang::OverhangAnalysis;
+pub use sample::{SampleDistribution, SurfaceSamples};
+pub use sdf::DistanceField;
+pub use slice::{Contours, Layers};
+pub use smooth::SmoothingMethod;
+pub use subdivide::SubdivisionScheme;
+pub use thickness::WallThickness;
+pub use voxel::{VoxelFill, VoxelGrid};
+
 const FRAME_SIZE: unt = 4 * 3 * std::mem::size_of::<f32>() + std::mem::size_of::<u16>();
 
 #[allow(non_camel_case_types)]
 // type int = isize;
 #[allow(non_camel_case_types)]
+#[allow(clippy::duplicated_attributes)]
 type unt = usize;
 
 #[derive(Clone, PartialOrd)]
@@ -73,6 +130,7 @@ impl core::hash::Hash for Edge {
 	}
 }
 
+#[allow(dead_code)]
 #[derive(Eq, PartialOrd)]
 struct VEdge {
 	a: Vertex,
@@ -137,6 +195,7 @@ fn check_stl(
 	Ok(num_triangles)
 }
 
+#[allow(clippy::vec_init_then_push)]
 fn add_vn_pair_to_map(vmap: &mut HashMap<Vertex, Vec<(Normal, u32)>>, vertex: Vertex, normal: Normal, idx: u32) {
 	match vmap.get_mut(&vertex) {
 		Some(lst) => {
@@ -150,6 +209,7 @@ fn add_vn_pair_to_map(vmap: &mut HashMap<Vertex, Vec<(Normal, u32)>>, vertex: Ve
 	}
 }
 
+#[allow(clippy::needless_borrow)]
 fn find_vn_pair_index(vmap: &HashMap<Vertex, Vec<(Normal, u32)>>, v: &Vertex, n: &Normal) -> Option<u32> {
 	match vmap.get(&v) {
 		None => return None,
@@ -338,8 +398,9 @@ pub fn parse_stl_mesh(
 	vset.reserve((num_triangles as unt / 2) + 2);
 	let mut eset = HashSet::<Edge>::new();
 	eset.reserve((num_triangles as f32 * 1.5) as unt);
-	let mut norm_count = Vec::<u32>::new();
-	norm_count.resize((num_triangles as unt / 2) + 2, 0);
+	// Sized by the vertex buffer rather than num_triangles / 2 + 2, which
+	// only holds for a single closed body.
+	let mut norm_count = vec![0u32; vertices.len() / 3];
 
 	let mut vpos = 0;
 	let mut epos = 0;
@@ -649,6 +710,44 @@ pub fn inverted_mat4x4(mat: &[f64]) -> Box<[f64]> {
 	Box::new(res)
 }
 
+/// Turn a point on the screen into a world-space ray for `MeshBvh.raycast`.
+/// `view_projection` is the column-major projection * view matrix, and `x`
+/// and `y` are in pixels from the top left of a `width` x `height` viewport.
+/// Returns [originX, originY, originZ, dirX, dirY, dirZ] with the origin on
+/// the near plane and a unit direction, or an empty array if the matrix is
+/// not invertible, the viewport is empty or the point doesn't unproject to a
+/// finite ray.
+#[wasm_bindgen(js_name = "unprojectRay")]
+pub fn unproject_ray(view_projection: &[f64], x: f64, y: f64, width: f64, height: f64) -> Box<[f64]> {
+	let inv = inverted_mat4x4(view_projection);
+	if inv.is_empty() || width <= 0.0 || height <= 0.0 {
+		return Box::new([]);
+	}
+	let ndc_x = 2.0 * x / width - 1.0;
+	let ndc_y = 1.0 - 2.0 * y / height;
+	// None for points at infinity
+	let unproject = |z: f64| -> Option<[f64; 3]> {
+		let mut p = [0.0; 4];
+		for (i, v) in p.iter_mut().enumerate() {
+			*v = inv[i] * ndc_x + inv[4 + i] * ndc_y + inv[8 + i] * z + inv[12 + i];
+		}
+		if p[3] == 0.0 {
+			return None;
+		}
+		Some([p[0] / p[3], p[1] / p[3], p[2] / p[3]])
+	};
+	let (near, far) = match (unproject(-1.0), unproject(1.0)) {
+		(Some(near), Some(far)) => (near, far),
+		_ => return Box::new([]),
+	};
+	let dir = [far[0] - near[0], far[1] - near[1], far[2] - near[2]];
+	let len = (dir[0] * dir[0] + dir[1] * dir[1] + dir[2] * dir[2]).sqrt();
+	if len == 0.0 || !len.is_finite() {
+		return Box::new([]);
+	}
+	Box::new([near[0], near[1], near[2], dir[0] / len, dir[1] / len, dir[2] / len])
+}
+
 #[rustfmt::skip]
 #[wasm_bindgen(js_name = "rotateMat4x4")]
 pub fn rotate_mat4x4(mat: &mut [f64], angle: f64, axis: &[f64]) {
@@ -703,6 +802,7 @@ pub fn rotate_mat4x4(mat: &mut [f64], angle: f64, axis: &[f64]) {
 #[cfg(test)]
 mod tests {
 	#[test]
+	#[allow(clippy::legacy_numeric_constants)]
 	fn inversion() {
 		#[rustfmt::skip]
 		let mut mat1: [f64; 16] = [1.0, 0.0, 1.0, 2.0,
@@ -750,6 +850,7 @@ mod tests {
 	}
 
 	#[test]
+	#[allow(clippy::legacy_numeric_constants)]
 	fn rotation() {
 		const PI: f64 = core::f64::consts::PI;
 		#[rustfmt::skip]
@@ -783,4 +884,62 @@ mod tests {
 			assert!((mat2[i] - expected2[i]).abs() <= core::f64::EPSILON);
 		}
 	}
+
+	#[test]
+	fn unprojection() {
+		#[rustfmt::skip]
+		let identity: [f64; 16] = [1.0, 0.0, 0.0, 0.0,
+		                           0.0, 1.0, 0.0, 0.0,
+		                           0.0, 0.0, 1.0, 0.0,
+		                           0.0, 0.0, 0.0, 1.0];
+		let ray = super::unproject_ray(&identity, 150.0, 25.0, 200.0, 100.0);
+		assert_eq!(&*ray, &[0.5, 0.5, -1.0, 0.0, 0.0, 1.0]);
+		assert!(super::unproject_ray(&[0.0; 16], 0.0, 0.0, 1.0, 1.0).is_empty());
+		assert!(super::unproject_ray(&identity, 0.0, 0.0, 0.0, 100.0).is_empty());
+		assert!(super::unproject_ray(&identity, 0.0, 0.0, 200.0, 0.0).is_empty());
+		assert!(super::unproject_ray(&identity, f64::NAN, 0.0, 200.0, 100.0).is_empty());
+		// The near plane unprojects to w = 0, a point at infinity
+		#[rustfmt::skip]
+		let at_infinity: [f64; 16] = [1.0, 0.0, 0.0,  0.0,
+		                              0.0, 1.0, 0.0,  0.0,
+		                              0.0, 0.0, 1.0, -1.0,
+		                              0.0, 0.0, 0.0,  1.0];
+		assert!(super::unproject_ray(&at_infinity, 150.0, 25.0, 200.0, 100.0).is_empty());
+	}
+
+	#[test]
+	fn several_bodies() {
+		use crate::mesh::Mesh;
+		use crate::vec3::Vec3;
+
+		// 16 vertices for 24 triangles, more than a single closed body has
+		let mut mesh = Mesh::cube(Vec3::new(0.0, 0.0, 0.0), 1.0);
+		mesh.append(&Mesh::cube(Vec3::new(5.0, 0.0, 0.0), 1.0));
+		let n = mesh.triangles.len();
+		let mut vertices = vec![0.0; 9 * n];
+		let mut normals = vec![0.0; 9 * n];
+		let mut v_indices = vec![0; 3 * n];
+		let mut e_indices = vec![0; 3 * n];
+		let err = super::parse_stl_mesh(
+			mesh.to_stl(),
+			&mut vertices,
+			&mut normals,
+			&mut v_indices,
+			&mut e_indices,
+		);
+		assert!(err.is_none(), "{:?}", err);
+		assert_eq!(v_indices.iter().max(), Some(&15));
+
+		// The minimum for a single closed body passes the up-front check but
+		// is too small for both cubes, which is reported rather than overrun
+		let min = 3 * (n / 2 + 2);
+		let err = super::parse_stl_mesh(
+			mesh.to_stl(),
+			&mut vertices[..min],
+			&mut normals[..min],
+			&mut v_indices,
+			&mut e_indices,
+		);
+		assert!(err.is_some());
+	}
 }
@@ -0,0 +1,248 @@
+use crate::vec3::Vec3;
+
+/// Row-major 3x3 matrix.
+pub(crate) type Mat3 = [[f64; 3]; 3];
+
+pub(crate) fn det3(m: &Mat3) -> f64 {
+	m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+		+ m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+/// Solves m x = b by Cramer's rule. Returns None if m is singular or too badly
+/// conditioned relative to `tolerance` times the scale of its entries.
+pub(crate) fn solve3(m: &Mat3, b: Vec3, tolerance: f64) -> Option<Vec3> {
+	let det = det3(m);
+	let scale = m.iter().flatten().fold(0.0f64, |acc, x| acc.max(x.abs()));
+	if scale == 0.0 || det.abs() <= tolerance * scale * scale * scale {
+		return None;
+	}
+	let with_column = |c: usize| -> Mat3 {
+		let mut r = *m;
+		for i in 0..3 {
+			r[i][c] = b[i];
+		}
+		r
+	};
+	Some(Vec3::new(
+		det3(&with_column(0)) / det,
+		det3(&with_column(1)) / det,
+		det3(&with_column(2)) / det,
+	))
+}
+
+/// Eigen decomposition of a symmetric matrix by Jacobi rotations. Returns the
+/// eigenvalues in ascending order with their unit eigenvectors.
+#[allow(clippy::needless_range_loop)]
+pub(crate) fn sym_eigen3(m: &Mat3) -> ([f64; 3], [Vec3; 3]) {
+	let mut a = *m;
+	let mut v: Mat3 = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+	for _ in 0..50 {
+		let off = a[0][1] * a[0][1] + a[0][2] * a[0][2] + a[1][2] * a[1][2];
+		if off <= 1e-30 * (a[0][0] * a[0][0] + a[1][1] * a[1][1] + a[2][2] * a[2][2]) || off == 0.0 {
+			break;
+		}
+		for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+			if a[p][q] == 0.0 {
+				continue;
+			}
+			// Rotation in the (p, q) plane that zeroes a[p][q]
+			let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+			let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+			let c = 1.0 / (t * t + 1.0).sqrt();
+			let s = t * c;
+			for k in 0..3 {
+				let (akp, akq) = (a[k][p], a[k][q]);
+				a[k][p] = c * akp - s * akq;
+				a[k][q] = s * akp + c * akq;
+			}
+			for k in 0..3 {
+				let (apk, aqk) = (a[p][k], a[q][k]);
+				a[p][k] = c * apk - s * aqk;
+				a[q][k] = s * apk + c * aqk;
+			}
+			for row in v.iter_mut() {
+				let (vkp, vkq) = (row[p], row[q]);
+				row[p] = c * vkp - s * vkq;
+				row[q] = s * vkp + c * vkq;
+			}
+		}
+	}
+	let mut order = [0, 1, 2];
+	order.sort_by(|&i, &j| a[i][i].total_cmp(&a[j][j]));
+	let values = order.map(|i| a[i][i]);
+	let vectors = order.map(|i| Vec3::new(v[0][i], v[1][i], v[2][i]));
+	(values, vectors)
+}
+
+pub(crate) fn mul_vec3(m: &Mat3, x: Vec3) -> Vec3 {
+	Vec3::new(
+		m[0][0] * x.x + m[0][1] * x.y + m[0][2] * x.z,
+		m[1][0] * x.x + m[1][1] * x.y + m[1][2] * x.z,
+		m[2][0] * x.x + m[2][1] * x.y + m[2][2] * x.z,
+	)
+}
+
+pub(crate) fn mul_mat3(a: &Mat3, b: &Mat3) -> Mat3 {
+	let mut m: Mat3 = [[0.0; 3]; 3];
+	for i in 0..3 {
+		for j in 0..3 {
+			m[i][j] = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+		}
+	}
+	m
+}
+
+/// Solves the 6x6 system m x = b by Gaussian elimination with partial
+/// pivoting. Returns None if m is singular to within `tolerance` times its
+/// largest entry.
+#[allow(clippy::needless_range_loop)]
+pub(crate) fn solve6(mut m: [[f64; 6]; 6], mut b: [f64; 6], tolerance: f64) -> Option<[f64; 6]> {
+	let scale = m.iter().flatten().fold(0.0f64, |acc, x| acc.max(x.abs()));
+	if scale == 0.0 {
+		return None;
+	}
+	for col in 0..6 {
+		let pivot = (col..6)
+			.max_by(|&i, &j| m[i][col].abs().total_cmp(&m[j][col].abs()))
+			.unwrap();
+		if m[pivot][col].abs() <= tolerance * scale {
+			return None;
+		}
+		m.swap(col, pivot);
+		b.swap(col, pivot);
+		for row in col + 1..6 {
+			let f = m[row][col] / m[col][col];
+			for k in col..6 {
+				m[row][k] -= f * m[col][k];
+			}
+			b[row] -= f * b[col];
+		}
+	}
+	let mut x = [0.0; 6];
+	for row in (0..6).rev() {
+		let rest: f64 = (row + 1..6).map(|k| m[row][k] * x[k]).sum();
+		x[row] = (b[row] - rest) / m[row][row];
+	}
+	Some(x)
+}
+
+/// Rotation by the angle |w| about the axis w.
+pub(crate) fn axis_angle(w: Vec3) -> Mat3 {
+	let angle = w.length();
+	if angle == 0.0 {
+		return [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+	}
+	let a = w / angle;
+	let (s, c) = angle.sin_cos();
+	let k: Mat3 = [[0.0, -a.z, a.y], [a.z, 0.0, -a.x], [-a.y, a.x, 0.0]];
+	let k2 = mul_mat3(&k, &k);
+	let mut r: Mat3 = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+	for i in 0..3 {
+		for j in 0..3 {
+			r[i][j] += s * k[i][j] + (1.0 - c) * k2[i][j];
+		}
+	}
+	r
+}
+
+/// Smallest rotation taking the unit vector `from` to the unit vector `to`
+/// (Rodrigues' formula). Opposite vectors give a half turn about an axis
+/// perpendicular to both.
+pub(crate) fn rotation_between(from: Vec3, to: Vec3) -> Mat3 {
+	let c = from.dot(to);
+	if c < -1.0 + 1e-12 {
+		let a = from.any_perpendicular();
+		let mut r: Mat3 = [[0.0; 3]; 3];
+		for i in 0..3 {
+			for j in 0..3 {
+				r[i][j] = 2.0 * a[i] * a[j] - if i == j { 1.0 } else { 0.0 };
+			}
+		}
+		return r;
+	}
+	let v = from.cross(to);
+	let k: Mat3 = [[0.0, -v.z, v.y], [v.z, 0.0, -v.x], [-v.y, v.x, 0.0]];
+	let mut r: Mat3 = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+	for i in 0..3 {
+		for j in 0..3 {
+			let k2: f64 = (0..3).map(|l| k[i][l] * k[l][j]).sum();
+			r[i][j] += k[i][j] + k2 / (1.0 + c);
+		}
+	}
+	r
+}
+
+/// Column-major 4x4 matrix, as used by `rotateMat4x4` and friends, that
+/// applies `r` and then moves by `t`.
+#[rustfmt::skip]
+pub(crate) fn to_mat4x4(r: &Mat3, t: Vec3) -> [f64; 16] {
+	[
+		r[0][0], r[1][0], r[2][0], 0.0,
+		r[0][1], r[1][1], r[2][1], 0.0,
+		r[0][2], r[1][2], r[2][2], 0.0,
+		t.x,     t.y,     t.z,     1.0,
+	]
+}
+
+/// Applies a column-major 4x4 matrix to a point.
+pub(crate) fn transform_point(m: &[f64], p: Vec3) -> Vec3 {
+	Vec3::new(
+		m[0] * p.x + m[4] * p.y + m[8] * p.z + m[12],
+		m[1] * p.x + m[5] * p.y + m[9] * p.z + m[13],
+		m[2] * p.x + m[6] * p.y + m[10] * p.z + m[14],
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn eigen_decomposition() {
+		let m: Mat3 = [[4.0, 1.0, 0.5], [1.0, 3.0, 0.2], [0.5, 0.2, 1.0]];
+		let (values, vectors) = sym_eigen3(&m);
+		assert!(values[0] <= values[1] && values[1] <= values[2]);
+		for k in 0..3 {
+			let x = vectors[k];
+			let mx = Vec3::new(
+				m[0][0] * x.x + m[0][1] * x.y + m[0][2] * x.z,
+				m[1][0] * x.x + m[1][1] * x.y + m[1][2] * x.z,
+				m[2][0] * x.x + m[2][1] * x.y + m[2][2] * x.z,
+			);
+			assert!((mx - x * values[k]).length() < 1e-12);
+			assert!((x.length() - 1.0).abs() < 1e-12);
+		}
+		assert!((values.iter().sum::<f64>() - 8.0).abs() < 1e-12);
+		let x = solve3(&m, Vec3::new(1.0, 2.0, 3.0), 1e-12).unwrap();
+		assert!((x.x * 4.0 + x.y + x.z * 0.5 - 1.0).abs() < 1e-12);
+
+		let mut m6 = [[0.0; 6]; 6];
+		for (i, row) in m6.iter_mut().enumerate() {
+			for (j, x) in row.iter_mut().enumerate() {
+				*x = 1.0 / (i + j + 1) as f64 + if i == j { 1.0 } else { 0.0 };
+			}
+		}
+		let b = [1.0, -2.0, 3.0, 0.5, 0.0, 4.0];
+		let x = solve6(m6, b, 1e-12).unwrap();
+		for i in 0..6 {
+			let row: f64 = (0..6).map(|j| m6[i][j] * x[j]).sum();
+			assert!((row - b[i]).abs() < 1e-12);
+		}
+		assert!(solve6([[0.0; 6]; 6], b, 1e-12).is_none());
+	}
+
+	#[test]
+	fn rotations() {
+		let from = Vec3::new(1.0, 2.0, -0.5).normalized();
+		for to in [Vec3::new(0.0, 0.0, -1.0), from, -from] {
+			let r = rotation_between(from, to);
+			assert!((det3(&r) - 1.0).abs() < 1e-12);
+			assert!((mul_vec3(&r, from) - to).length() < 1e-12);
+			let m = to_mat4x4(&r, Vec3::new(1.0, 2.0, 3.0));
+			assert!((transform_point(&m, from) - to - Vec3::new(1.0, 2.0, 3.0)).length() < 1e-12);
+		}
+		let r = axis_angle(Vec3::new(0.0, 0.0, std::f64::consts::FRAC_PI_2));
+		assert!((mul_vec3(&r, Vec3::new(1.0, 0.0, 0.0)) - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+		assert!((det3(&mul_mat3(&r, &r)) - 1.0).abs() < 1e-12);
+	}
+}
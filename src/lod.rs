@@ -0,0 +1,184 @@
+use wasm_bindgen::prelude::*;
+
+use crate::decimate::Decimator;
+use crate::mesh::{Mesh, MeshBuffers};
+
+/// Reduced-detail copies of a mesh, one per requested triangle ratio. Levels
+/// with a ratio of 1 are the buffers `parseSTLMeshLods` filled, which the
+/// chain doesn't copy, so their buffers here are empty.
+#[wasm_bindgen]
+pub struct LodChain {
+	ratios: Vec<f64>,
+	/// None for the full-detail levels
+	levels: Vec<Option<MeshBuffers>>,
+	full_triangles: u32,
+}
+
+impl LodChain {
+	fn buffers<T>(&self, i: u32, get: impl Fn(&MeshBuffers) -> Box<[T]>) -> Box<[T]> {
+		match self.levels.get(i as usize) {
+			Some(Some(level)) => get(level),
+			_ => Box::new([]),
+		}
+	}
+}
+
+#[wasm_bindgen]
+impl LodChain {
+	/// Number of levels. Matches the number of ratios passed in.
+	pub fn count(&self) -> u32 {
+		self.levels.len() as u32
+	}
+
+	/// The ratio level `i` was built for, clamped to [0, 1].
+	pub fn ratio(&self, i: u32) -> f64 {
+		self.ratios.get(i as usize).copied().unwrap_or(0.0)
+	}
+
+	/// Whether level `i` is the full-detail mesh, to be drawn from the
+	/// buffers `parseSTLMeshLods` filled.
+	#[wasm_bindgen(js_name = "isFullDetail")]
+	pub fn is_full_detail(&self, i: u32) -> bool {
+		matches!(self.levels.get(i as usize), Some(None))
+	}
+
+	/// Vertices of level `i`, in the order the ratios were passed in, laid
+	/// out as `parseSTLMesh` writes them. Levels stay in the chain, so they
+	/// can be fetched again when switching between them.
+	pub fn vertices(&self, i: u32) -> Box<[f32]> {
+		self.buffers(i, MeshBuffers::vertices)
+	}
+
+	pub fn normals(&self, i: u32) -> Box<[f32]> {
+		self.buffers(i, MeshBuffers::normals)
+	}
+
+	#[wasm_bindgen(js_name = "vIndices")]
+	pub fn v_indices(&self, i: u32) -> Box<[u32]> {
+		self.buffers(i, MeshBuffers::v_indices)
+	}
+
+	#[wasm_bindgen(js_name = "eIndices")]
+	pub fn e_indices(&self, i: u32) -> Box<[u32]> {
+		self.buffers(i, MeshBuffers::e_indices)
+	}
+
+	/// Triangles in level `i`, full-detail levels included.
+	#[wasm_bindgen(js_name = "triangleCount")]
+	pub fn triangle_count(&self, i: u32) -> u32 {
+		match self.levels.get(i as usize) {
+			Some(Some(level)) => level.triangle_count(),
+			Some(None) => self.full_triangles,
+			None => 0,
+		}
+	}
+}
+
+/// Builds one level per ratio. Levels are produced coarsening a single
+/// decimator from the finest level to the coarsest, so each collapse is only
+/// done once however many levels are asked for.
+pub(crate) fn build_lods(mesh: &Mesh, ratios: &[f64], feature_angle: f64) -> LodChain {
+	let ratios: Vec<f64> = ratios
+		.iter()
+		.map(|r| if r.is_nan() { 0.0 } else { r.clamp(0.0, 1.0) })
+		.collect();
+	let mut order: Vec<usize> = (0..ratios.len()).collect();
+	order.sort_by(|&a, &b| ratios[b].total_cmp(&ratios[a]));
+
+	let mut decimator = None;
+	let mut levels = vec![None; ratios.len()];
+	for i in order {
+		if ratios[i] >= 1.0 {
+			continue;
+		}
+		let target = (mesh.triangles.len() as f64 * ratios[i]).round() as usize;
+		let dec = decimator.get_or_insert_with(|| Decimator::new(mesh, feature_angle));
+		dec.run(target, 0.0);
+		levels[i] = Some(dec.to_mesh().to_buffers());
+	}
+	LodChain {
+		ratios,
+		levels,
+		full_triangles: mesh.triangles.len() as u32,
+	}
+}
+
+pub(crate) fn parse_stl_mesh_lods_impl(
+	buf: Vec<u8>,
+	vertices: &mut [f32],
+	normals: &mut [f32],
+	v_indices: &mut [u32],
+	e_indices: &mut [u32],
+	ratios: &[f64],
+	feature_angle: f64,
+) -> Result<LodChain, String> {
+	if let Some(e) = crate::parse_stl_mesh(buf, vertices, normals, v_indices, e_indices) {
+		return Err(e);
+	}
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	Ok(build_lods(&mesh, ratios, feature_angle))
+}
+
+/// Like `parseSTLMesh`, filling the given buffers with the full-detail mesh,
+/// but also returns a level of detail for each entry of `ratios`, e.g.
+/// [0.25, 0.05]. Each level has about that fraction of the triangles and is
+/// simplified as by `simplify` with the given `feature_angle`. A ratio of 1
+/// stands for the full-detail mesh in the given buffers, which isn't copied
+/// into the chain.
+#[wasm_bindgen(js_name = "parseSTLMeshLods")]
+pub fn parse_stl_mesh_lods(
+	buf: Vec<u8>,
+	vertices: &mut [f32],
+	normals: &mut [f32],
+	v_indices: &mut [u32],
+	e_indices: &mut [u32],
+	ratios: &[f64],
+	feature_angle: f64,
+) -> Result<LodChain, JsValue> {
+	parse_stl_mesh_lods_impl(buf, vertices, normals, v_indices, e_indices, ratios, feature_angle)
+		.map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vec3::Vec3;
+
+	#[test]
+	fn cube_levels() {
+		let mesh = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 1.0, 10);
+		let n = mesh.triangles.len();
+		let mut vertices = vec![0.0; 9 * n];
+		let mut normals = vec![0.0; 9 * n];
+		// Longer than the file fills, as callers allocate them
+		let mut v_indices = vec![0; n * 3 + 30];
+		let mut e_indices = vec![0; n * 3 + 30];
+		let lods = parse_stl_mesh_lods_impl(
+			mesh.to_stl(),
+			&mut vertices,
+			&mut normals,
+			&mut v_indices,
+			&mut e_indices,
+			&[0.05, 1.0, 0.25],
+			0.5,
+		)
+		.unwrap();
+
+		assert_eq!(lods.count(), 3);
+		assert_eq!(lods.ratio(1), 1.0);
+		assert!(lods.is_full_detail(1));
+		assert_eq!(lods.triangle_count(1) as usize, n);
+		assert!(lods.v_indices(1).is_empty());
+		assert!(lods.triangle_count(2) as usize <= n / 4);
+		assert!(lods.triangle_count(0) as usize <= n / 20);
+		for i in [0, 2] {
+			assert!(!lods.is_full_detail(i));
+			let level = Mesh::from_buffers(&lods.vertices(i), &lods.v_indices(i)).unwrap();
+			assert_eq!(level.triangles.len() as u32, lods.triangle_count(i));
+			assert!((level.volume() - 1.0).abs() < 1e-6);
+			// Still there the second time
+			assert_eq!(lods.v_indices(i), lods.v_indices(i));
+		}
+		assert!(lods.vertices(3).is_empty());
+	}
+}
@@ -0,0 +1,226 @@
+use wasm_bindgen::prelude::*;
+
+use crate::linalg::{solve3, sym_eigen3, Mat3};
+use crate::mesh_bvh::MeshBvh;
+use crate::vec3::Vec3;
+
+fn vec3_arg(a: &[f64]) -> Option<Vec3> {
+	if a.len() != 3 {
+		return None;
+	}
+	Some(Vec3::new(a[0], a[1], a[2]))
+}
+
+/// Distance between two points [x, y, z]. Returns NaN if either isn't a point.
+#[wasm_bindgen(js_name = "measureDistance")]
+pub fn measure_distance(a: &[f64], b: &[f64]) -> f64 {
+	match (vec3_arg(a), vec3_arg(b)) {
+		(Some(a), Some(b)) => (b - a).length(),
+		_ => f64::NAN,
+	}
+}
+
+/// Angle in radians at `vertex` between the directions to `a` and `c`.
+/// Returns NaN if any argument isn't a point or coincides with `vertex`.
+#[wasm_bindgen(js_name = "measureAngle")]
+pub fn measure_angle(a: &[f64], vertex: &[f64], c: &[f64]) -> f64 {
+	match (vec3_arg(a), vec3_arg(vertex), vec3_arg(c)) {
+		(Some(a), Some(v), Some(c)) => angle_between(a - v, c - v),
+		_ => f64::NAN,
+	}
+}
+
+fn angle_between(u: Vec3, v: Vec3) -> f64 {
+	let (lu, lv) = (u.length(), v.length());
+	if lu == 0.0 || lv == 0.0 {
+		return f64::NAN;
+	}
+	// atan2 stays accurate for nearly parallel vectors, unlike acos
+	u.cross(v).length().atan2(u.dot(v))
+}
+
+/// A cylinder fitted to part of a mesh.
+#[wasm_bindgen]
+pub struct CylinderFit {
+	radius: f64,
+	axis: Vec3,
+	center: Vec3,
+	rms_error: f64,
+}
+
+#[wasm_bindgen]
+impl CylinderFit {
+	pub fn radius(&self) -> f64 {
+		self.radius
+	}
+
+	/// Unit direction of the axis.
+	pub fn axis(&self) -> Box<[f64]> {
+		Box::new([self.axis.x, self.axis.y, self.axis.z])
+	}
+
+	/// A point on the axis, level with the middle of the fitted vertices.
+	pub fn center(&self) -> Box<[f64]> {
+		Box::new([self.center.x, self.center.y, self.center.z])
+	}
+
+	/// Root mean square distance of the fitted vertices from the cylinder.
+	#[wasm_bindgen(js_name = "rmsError")]
+	pub fn rms_error(&self) -> f64 {
+		self.rms_error
+	}
+}
+
+#[wasm_bindgen]
+impl MeshBvh {
+	/// Angle in radians between the normals of two triangles. 0 for parallel
+	/// faces, pi for opposite ones.
+	#[wasm_bindgen(js_name = "faceAngle")]
+	pub fn face_angle(&self, a: u32, b: u32) -> f64 {
+		let n = self.mesh().triangles.len() as u32;
+		if a >= n || b >= n {
+			return f64::NAN;
+		}
+		angle_between(self.mesh().face_cross(a as usize), self.mesh().face_cross(b as usize))
+	}
+
+	/// Fit a cylinder to the vertices of the given triangles, e.g. ones picked
+	/// on a hole or a boss. The axis is the direction the triangles' normals
+	/// are most perpendicular to. Returns nothing if the triangles are all
+	/// parallel, since they then don't determine an axis.
+	#[wasm_bindgen(js_name = "fitCylinder")]
+	pub fn fit_cylinder(&self, triangles: &[u32]) -> Option<CylinderFit> {
+		let mesh = self.mesh();
+		let mut normal_moments: Mat3 = [[0.0; 3]; 3];
+		let mut vertices = Vec::new();
+		for &t in triangles {
+			if t as usize >= mesh.triangles.len() {
+				return None;
+			}
+			// Area-weighted
+			let n = mesh.face_cross(t as usize);
+			let len = n.length();
+			if len == 0.0 {
+				continue;
+			}
+			for i in 0..3 {
+				for j in 0..3 {
+					normal_moments[i][j] += n[i] * n[j] / len;
+				}
+			}
+			vertices.extend(mesh.triangles[t as usize]);
+		}
+		// Sorted so the sums below come out the same on every call
+		vertices.sort_unstable();
+		vertices.dedup();
+		let (values, vectors) = sym_eigen3(&normal_moments);
+		if values[1] <= 1e-9 * values[2] {
+			return None;
+		}
+		let axis = vectors[0];
+
+		// Fit a circle to the vertices projected along the axis, by least
+		// squares on x^2 + y^2 + D x + E y + F = 0 (Kåsa's method)
+		let points: Vec<Vec3> = vertices.iter().map(|&v| mesh.positions[v as usize]).collect();
+		let mean = points.iter().fold(Vec3::ZERO, |acc, &p| acc + p) / points.len() as f64;
+		let u = vectors[1];
+		let w = axis.cross(u);
+		let mut m: Mat3 = [[0.0; 3]; 3];
+		let mut rhs = Vec3::ZERO;
+		for p in &points {
+			let d = *p - mean;
+			let row = [d.dot(u), d.dot(w), 1.0];
+			let r2 = row[0] * row[0] + row[1] * row[1];
+			for i in 0..3 {
+				for j in 0..3 {
+					m[i][j] += row[i] * row[j];
+				}
+			}
+			rhs -= Vec3::new(row[0], row[1], 1.0) * r2;
+		}
+		let coeffs = solve3(&m, rhs, 1e-12)?;
+		let (cx, cy) = (-coeffs.x / 2.0, -coeffs.y / 2.0);
+		let radius = (cx * cx + cy * cy - coeffs.z).max(0.0).sqrt();
+		let center = mean + u * cx + w * cy;
+
+		let sum_sq: f64 = points
+			.iter()
+			.map(|&p| {
+				let d = p - center;
+				let radial = d - axis * d.dot(axis);
+				(radial.length() - radius).powi(2)
+			})
+			.sum();
+		Some(CylinderFit {
+			radius,
+			axis,
+			center,
+			rms_error: (sum_sq / points.len() as f64).sqrt(),
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mesh::Mesh;
+	use crate::mesh_bvh::build_mesh_bvh_impl;
+	use std::f64::consts::PI;
+
+	#[test]
+	fn points_and_angles() {
+		assert_eq!(measure_distance(&[1.0, 2.0, 3.0], &[4.0, 6.0, 3.0]), 5.0);
+		assert!(measure_distance(&[1.0], &[4.0, 6.0, 3.0]).is_nan());
+		let angle = measure_angle(&[1.0, 0.0, 0.0], &[0.0, 0.0, 0.0], &[1.0, 1.0, 0.0]);
+		assert!((angle - PI / 4.0).abs() < 1e-12);
+		assert!(measure_angle(&[0.0; 3], &[0.0; 3], &[1.0, 1.0, 0.0]).is_nan());
+
+		let (vertices, _, v_indices, _) = Mesh::cube(Vec3::new(0.0, 0.0, 0.0), 1.0).parsed();
+		let bvh = build_mesh_bvh_impl(&vertices, &[], &v_indices).unwrap();
+		assert!((bvh.face_angle(0, 1)).abs() < 1e-12);
+		let angles: Vec<f64> = (0..12).map(|t| bvh.face_angle(0, t)).collect();
+		assert!(angles.iter().any(|a| (a - PI / 2.0).abs() < 1e-12));
+		assert!(angles.iter().any(|a| (a - PI).abs() < 1e-12));
+	}
+
+	#[test]
+	fn cylinder() {
+		// Side of a tilted cylinder of radius 2
+		let axis = Vec3::new(1.0, 0.0, 1.0).normalized();
+		let u = Vec3::new(0.0, 1.0, 0.0);
+		let w = axis.cross(u);
+		let base = Vec3::new(5.0, -3.0, 1.0);
+		let segments = 24;
+		let mut mesh = Mesh::default();
+		for i in 0..segments {
+			let a = 2.0 * PI * i as f64 / segments as f64;
+			let p = base + (u * a.cos() + w * a.sin()) * 2.0;
+			mesh.positions.push(p);
+			mesh.positions.push(p + axis * 4.0);
+		}
+		for i in 0..segments as u32 {
+			let j = (i + 1) % segments as u32;
+			mesh.triangles.push([2 * i, 2 * j, 2 * j + 1]);
+			mesh.triangles.push([2 * i, 2 * j + 1, 2 * i + 1]);
+		}
+		let (vertices, _, v_indices, _) = mesh.parsed();
+		let bvh = build_mesh_bvh_impl(&vertices, &[], &v_indices).unwrap();
+
+		// A quarter of the surface is enough
+		let picked: Vec<u32> = (0..segments as u32 / 2).collect();
+		let fit = bvh.fit_cylinder(&picked).unwrap();
+		assert!((fit.radius() - 2.0).abs() < 1e-5, "{}", fit.radius());
+		assert!(fit.rms_error() < 1e-5);
+		let fitted_axis = Vec3::new(fit.axis()[0], fit.axis()[1], fit.axis()[2]);
+		assert!(fitted_axis.cross(axis).length() < 1e-6);
+		let c = Vec3::new(fit.center()[0], fit.center()[1], fit.center()[2]) - base;
+		assert!(c.cross(axis).length() < 1e-5);
+		// The same bits every time
+		let again = bvh.fit_cylinder(&picked).unwrap();
+		assert_eq!(again.radius().to_bits(), fit.radius().to_bits());
+		assert_eq!(again.center(), fit.center());
+
+		// A single flat strip doesn't define an axis
+		assert!(bvh.fit_cylinder(&[0, 1]).is_none());
+	}
+}
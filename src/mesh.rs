@@ -0,0 +1,451 @@
+use std::collections::{HashMap, HashSet};
+
+use wasm_bindgen::prelude::*;
+
+use crate::vec3::Vec3;
+use crate::Edge;
+
+/// An indexed triangle mesh, as produced by `parse_stl_mesh`. Positions are
+/// widened to f64 so that the geometry code doesn't have to care about
+/// precision.
+#[derive(Clone, Default)]
+pub(crate) struct Mesh {
+	pub positions: Vec<Vec3>,
+	pub triangles: Vec<[u32; 3]>,
+}
+
+impl Mesh {
+	/// Builds a mesh from the vertex and index buffers filled by
+	/// `parse_stl_mesh`. The buffers are usually over-allocated, so only the
+	/// vertices up to the highest referenced index are read, and the
+	/// all-zero triangles that the unfilled tail of the index buffer holds
+	/// are left off.
+	pub fn from_buffers(vertices: &[f32], v_indices: &[u32]) -> Result<Mesh, String> {
+		if v_indices.len() % 3 != 0 {
+			return Err(format!(
+				"Vertex index buffer length must be a multiple of 3, but is {}",
+				v_indices.len()
+			));
+		}
+		let mut filled = v_indices.len();
+		while filled > 0 && v_indices[filled - 3..filled] == [0, 0, 0] {
+			filled -= 3;
+		}
+		let v_indices = &v_indices[..filled];
+		let num_vertices = match v_indices.iter().max() {
+			Some(&m) => m as usize + 1,
+			None => 0,
+		};
+		if num_vertices * 3 > vertices.len() {
+			return Err(format!(
+				"Vertex index {} out of bounds for vertex buffer of length {}",
+				num_vertices - 1,
+				vertices.len()
+			));
+		}
+
+		let positions = (0..num_vertices).map(|i| Vec3::from_f32(vertices, i)).collect();
+		let triangles = v_indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+		Ok(Mesh { positions, triangles })
+	}
+
+	pub fn corners(&self, t: usize) -> [Vec3; 3] {
+		let tri = &self.triangles[t];
+		[
+			self.positions[tri[0] as usize],
+			self.positions[tri[1] as usize],
+			self.positions[tri[2] as usize],
+		]
+	}
+
+	/// Cross product of two triangle edges. Its length is twice the area.
+	pub fn face_cross(&self, t: usize) -> Vec3 {
+		let [a, b, c] = self.corners(t);
+		(b - a).cross(c - a)
+	}
+
+	pub fn face_normal(&self, t: usize) -> Vec3 {
+		self.face_cross(t).normalized()
+	}
+
+	pub fn is_degenerate(&self, t: usize) -> bool {
+		let [a, b, c] = self.triangles[t];
+		a == b || b == c || c == a
+	}
+
+	/// Signed volume enclosed by the mesh. Only meaningful for closed meshes
+	/// with consistent outward-facing winding.
+	pub fn volume(&self) -> f64 {
+		let mut vol = 0.0;
+		for t in 0..self.triangles.len() {
+			let [a, b, c] = self.corners(t);
+			vol += a.dot(b.cross(c));
+		}
+		vol / 6.0
+	}
+
+	/// Bounds of the vertices referenced by a triangle, as (min, max).
+	pub fn bounds(&self) -> (Vec3, Vec3) {
+		let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+		let mut max = -min;
+		for tri in &self.triangles {
+			for &v in tri {
+				let p = self.positions[v as usize];
+				min = min.min(p);
+				max = max.max(p);
+			}
+		}
+		if self.triangles.is_empty() {
+			return (Vec3::ZERO, Vec3::ZERO);
+		}
+		(min, max)
+	}
+
+	/// Per-vertex normals, averaged from the normals of the surrounding faces
+	/// the same way `parse_stl_mesh` averages the STL facet normals.
+	pub fn vertex_normals(&self) -> Vec<Vec3> {
+		let mut normals = vec![Vec3::ZERO; self.positions.len()];
+		for t in 0..self.triangles.len() {
+			let n = self.face_normal(t);
+			for &v in &self.triangles[t] {
+				normals[v as usize] += n;
+			}
+		}
+		for n in normals.iter_mut() {
+			*n = n.normalized();
+		}
+		normals
+	}
+
+	/// The faces around every edge, keyed by its vertices in ascending order.
+	/// Degenerate triangles are left out.
+	pub fn edge_faces(&self) -> HashMap<(u32, u32), Vec<u32>> {
+		let mut edge_faces = HashMap::<(u32, u32), Vec<u32>>::with_capacity(self.triangles.len() * 3 / 2);
+		for (t, tri) in self.triangles.iter().enumerate() {
+			if self.is_degenerate(t) {
+				continue;
+			}
+			for j in 0..3 {
+				let (a, b) = (tri[j], tri[(j + 1) % 3]);
+				edge_faces.entry((a.min(b), a.max(b))).or_default().push(t as u32);
+			}
+		}
+		edge_faces
+	}
+
+	/// Extracts the given triangles into a new mesh, keeping only the vertices
+	/// they reference. Vertices keep their relative order.
+	pub fn submesh(&self, tris: &[u32]) -> Mesh {
+		let mut remap = vec![u32::MAX; self.positions.len()];
+		for &t in tris {
+			for &v in &self.triangles[t as usize] {
+				remap[v as usize] = 0;
+			}
+		}
+		let mut positions = Vec::new();
+		for (v, r) in remap.iter_mut().enumerate() {
+			if *r == 0 {
+				*r = positions.len() as u32;
+				positions.push(self.positions[v]);
+			}
+		}
+		let triangles = tris
+			.iter()
+			.map(|&t| {
+				let tri = &self.triangles[t as usize];
+				[remap[tri[0] as usize], remap[tri[1] as usize], remap[tri[2] as usize]]
+			})
+			.collect();
+		Mesh { positions, triangles }
+	}
+
+	/// `count` random points spread over the triangles in proportion to their
+	/// area.
+	pub fn random_surface_points(&self, count: usize, rng: &mut Rng) -> Vec<SurfacePoint> {
+		let mut cumulative = Vec::with_capacity(self.triangles.len());
+		let mut total = 0.0;
+		for t in 0..self.triangles.len() {
+			total += self.face_cross(t).length() / 2.0;
+			cumulative.push(total);
+		}
+		(0..count)
+			.map(|_| {
+				let target = rng.next_f64() * total;
+				// Zero area triangles never come first past the target
+				let t = cumulative.partition_point(|&a| a <= target).min(cumulative.len() - 1);
+				// Uniform over the triangle, after Osada et al., "Shape Distributions"
+				let (r1, r2) = (rng.next_f64().sqrt(), rng.next_f64());
+				SurfacePoint {
+					triangle: t as u32,
+					weights: [1.0 - r1, r1 * (1.0 - r2), r1 * r2],
+				}
+			})
+			.collect()
+	}
+
+	pub fn surface_position(&self, point: &SurfacePoint) -> Vec3 {
+		let [a, b, c] = self.corners(point.triangle as usize);
+		a * point.weights[0] + b * point.weights[1] + c * point.weights[2]
+	}
+
+	/// Converts to the buffer layout `parse_stl_mesh` produces: 3 floats per
+	/// vertex and normal, 3 indices per triangle and 2 indices per edge.
+	pub fn to_buffers(&self) -> MeshBuffers {
+		let normals = self.vertex_normals();
+		self.to_buffers_with_normals(&normals)
+	}
+
+	pub fn to_buffers_with_normals(&self, normals: &[Vec3]) -> MeshBuffers {
+		let mut vertices = Vec::with_capacity(self.positions.len() * 3);
+		for p in &self.positions {
+			vertices.extend_from_slice(&[p.x as f32, p.y as f32, p.z as f32]);
+		}
+		let mut flat_normals = Vec::with_capacity(normals.len() * 3);
+		for n in normals {
+			flat_normals.extend_from_slice(&[n.x as f32, n.y as f32, n.z as f32]);
+		}
+
+		let mut v_indices = Vec::with_capacity(self.triangles.len() * 3);
+		let mut e_indices = Vec::with_capacity(self.triangles.len() * 3);
+		let mut eset = HashSet::<Edge>::with_capacity((self.triangles.len() as f32 * 1.5) as usize);
+		for tri in &self.triangles {
+			v_indices.extend_from_slice(tri);
+			for j in 0..3 {
+				let edge = Edge {
+					a: tri[j],
+					b: tri[(j + 1) % 3],
+				};
+				if !eset.contains(&edge) {
+					e_indices.extend_from_slice(&[edge.a, edge.b]);
+					eset.insert(edge);
+				}
+			}
+		}
+
+		let (min, max) = self.bounds();
+		MeshBuffers {
+			vertices,
+			normals: flat_normals,
+			v_indices,
+			e_indices,
+			bounds: [min.x, min.y, min.z, max.x, max.y, max.z],
+			volume: self.volume(),
+		}
+	}
+}
+
+/// A point on triangle `triangle`, with barycentric coordinates for its corners.
+#[derive(Clone, Copy)]
+pub(crate) struct SurfacePoint {
+	pub triangle: u32,
+	pub weights: [f64; 3],
+}
+
+/// SplitMix64, small and good enough to place points, and the same on every
+/// platform for a given seed.
+pub(crate) struct Rng(pub u64);
+
+impl Rng {
+	pub fn next_u64(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		z ^ (z >> 31)
+	}
+
+	/// Uniform in [0, 1).
+	pub fn next_f64(&mut self) -> f64 {
+		(self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+	}
+}
+
+/// A mesh in the buffer layout `parseSTLMesh` writes, for operations that
+/// create new geometry and so can't write into caller-allocated arrays.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct MeshBuffers {
+	vertices: Vec<f32>,
+	normals: Vec<f32>,
+	v_indices: Vec<u32>,
+	e_indices: Vec<u32>,
+	bounds: [f64; 6],
+	volume: f64,
+}
+
+#[wasm_bindgen]
+impl MeshBuffers {
+	pub fn vertices(&self) -> Box<[f32]> {
+		self.vertices.clone().into_boxed_slice()
+	}
+
+	pub fn normals(&self) -> Box<[f32]> {
+		self.normals.clone().into_boxed_slice()
+	}
+
+	#[wasm_bindgen(js_name = "vIndices")]
+	pub fn v_indices(&self) -> Box<[u32]> {
+		self.v_indices.clone().into_boxed_slice()
+	}
+
+	#[wasm_bindgen(js_name = "eIndices")]
+	pub fn e_indices(&self) -> Box<[u32]> {
+		self.e_indices.clone().into_boxed_slice()
+	}
+
+	#[wasm_bindgen(js_name = "vertexCount")]
+	pub fn vertex_count(&self) -> u32 {
+		(self.vertices.len() / 3) as u32
+	}
+
+	#[wasm_bindgen(js_name = "triangleCount")]
+	pub fn triangle_count(&self) -> u32 {
+		(self.v_indices.len() / 3) as u32
+	}
+
+	/// Axis-aligned bounds as [minX, minY, minZ, maxX, maxY, maxZ].
+	pub fn bounds(&self) -> Box<[f64]> {
+		Box::new(self.bounds)
+	}
+
+	/// Signed volume. Only meaningful if the mesh is closed.
+	pub fn volume(&self) -> f64 {
+		self.volume
+	}
+}
+
+#[cfg(test)]
+impl Mesh {
+	/// Axis-aligned cube with outward-facing winding.
+	pub fn cube(min: Vec3, size: f64) -> Mesh {
+		let positions = (0..8)
+			.map(|i| {
+				min + Vec3::new(
+					(i & 1) as f64 * size,
+					((i >> 1) & 1) as f64 * size,
+					((i >> 2) & 1) as f64 * size,
+				)
+			})
+			.collect();
+		#[rustfmt::skip]
+		let triangles = vec![
+			[0, 2, 3], [0, 3, 1], [4, 5, 7], [4, 7, 6],
+			[0, 1, 5], [0, 5, 4], [2, 6, 7], [2, 7, 3],
+			[0, 4, 6], [0, 6, 2], [1, 3, 7], [1, 7, 5],
+		];
+		Mesh { positions, triangles }
+	}
+
+	/// Cube whose faces are each split into an n x n grid of quads, with
+	/// outward-facing winding.
+	pub fn tessellated_cube(min: Vec3, size: f64, n: usize) -> Mesh {
+		let mut index = std::collections::HashMap::<[usize; 3], u32>::new();
+		let mut mesh = Mesh::default();
+		let mut vertex = |mesh: &mut Mesh, p: [usize; 3]| -> u32 {
+			*index.entry(p).or_insert_with(|| {
+				let f = |i: usize| i as f64 / n as f64 * size;
+				mesh.positions.push(min + Vec3::new(f(p[0]), f(p[1]), f(p[2])));
+				mesh.positions.len() as u32 - 1
+			})
+		};
+		for axis in 0..3 {
+			for &side in &[0, n] {
+				let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+				for i in 0..n {
+					for j in 0..n {
+						let mut corners = [0; 4];
+						for (k, &(di, dj)) in [(0, 0), (1, 0), (1, 1), (0, 1)].iter().enumerate() {
+							let mut p = [0; 3];
+							p[axis] = side;
+							p[u] = i + di;
+							p[v] = j + dj;
+							corners[k] = vertex(&mut mesh, p);
+						}
+						// (u, v, axis) is right-handed, so this winding faces +axis
+						let [a, b, c, d] = corners;
+						if side == n {
+							mesh.triangles.push([a, b, c]);
+							mesh.triangles.push([a, c, d]);
+						} else {
+							mesh.triangles.push([a, c, b]);
+							mesh.triangles.push([a, d, c]);
+						}
+					}
+				}
+			}
+		}
+		mesh
+	}
+
+	/// Appends another mesh's triangles and vertices to this one.
+	pub fn append(&mut self, other: &Mesh) {
+		let offset = self.positions.len() as u32;
+		self.positions.extend_from_slice(&other.positions);
+		for t in &other.triangles {
+			self.triangles.push([t[0] + offset, t[1] + offset, t[2] + offset]);
+		}
+	}
+
+	/// Encodes the mesh as a binary STL file.
+	pub fn to_stl(&self) -> Vec<u8> {
+		let mut buf = vec![0u8; 80];
+		buf.extend_from_slice(&(self.triangles.len() as u32).to_le_bytes());
+		for t in 0..self.triangles.len() {
+			let n = self.face_normal(t);
+			let [a, b, c] = self.corners(t);
+			for v in &[n, a, b, c] {
+				for k in 0..3 {
+					buf.extend_from_slice(&(v[k] as f32).to_le_bytes());
+				}
+			}
+			buf.extend_from_slice(&[0, 0]);
+		}
+		buf
+	}
+
+	/// Runs the mesh through `parse_stl_mesh`, returning the welded mesh.
+	pub fn parsed(&self) -> (Vec<f32>, Vec<f32>, Vec<u32>, Vec<u32>) {
+		let n = self.triangles.len();
+		let mut vertices = vec![0.0; 9 * n];
+		let mut normals = vec![0.0; 9 * n];
+		let mut v_indices = vec![0; n * 3];
+		// Open meshes can have up to 3 unique edges per triangle
+		let mut e_indices = vec![0; n * 6];
+		let err = crate::parse_stl_mesh(
+			self.to_stl(),
+			&mut vertices,
+			&mut normals,
+			&mut v_indices,
+			&mut e_indices,
+		);
+		assert!(err.is_none(), "{:?}", err);
+		(vertices, normals, v_indices, e_indices)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cube_measurements() {
+		let cube = Mesh::cube(Vec3::new(1.0, 2.0, 3.0), 2.0);
+		assert!((cube.volume() - 8.0).abs() < 1e-12);
+
+		let (vertices, _, v_indices, _) = cube.parsed();
+		let parsed = Mesh::from_buffers(&vertices, &v_indices).unwrap();
+		assert_eq!(parsed.positions.len(), 8);
+		// The unfilled tail of an over-allocated index buffer isn't read as triangles
+		let mut padded = v_indices.clone();
+		padded.resize(v_indices.len() + 30, 0);
+		assert_eq!(
+			Mesh::from_buffers(&vertices, &padded).unwrap().triangles,
+			parsed.triangles
+		);
+		let buffers = parsed.to_buffers();
+		assert_eq!(buffers.triangle_count(), 12);
+		assert_eq!(buffers.e_indices().len(), 18 * 2);
+		assert_eq!(&*buffers.bounds(), &[1.0, 2.0, 3.0, 3.0, 4.0, 5.0]);
+		assert!((buffers.volume() - 8.0).abs() < 1e-12);
+	}
+}
@@ -0,0 +1,352 @@
+use wasm_bindgen::prelude::*;
+
+use crate::bvh::{Aabb, Bvh};
+use crate::mesh::Mesh;
+use crate::vec3::Vec3;
+
+/// A point on the surface of a mesh: where a ray hit it, or the point closest
+/// to a query point.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+	triangle: u32,
+	distance: f64,
+	barycentrics: [f64; 3],
+	point: Vec3,
+	normal: Vec3,
+}
+
+#[wasm_bindgen]
+impl RayHit {
+	/// Index of the triangle that was hit.
+	pub fn triangle(&self) -> u32 {
+		self.triangle
+	}
+
+	/// Distance from the ray origin to the hit, in units of the ray direction's
+	/// length, or from the query point to the closest point.
+	pub fn distance(&self) -> f64 {
+		self.distance
+	}
+
+	/// Weights of the triangle's three corners at the hit point.
+	pub fn barycentrics(&self) -> Box<[f64]> {
+		Box::new(self.barycentrics)
+	}
+
+	pub fn point(&self) -> Box<[f64]> {
+		Box::new([self.point.x, self.point.y, self.point.z])
+	}
+
+	/// The vertex normals interpolated at the point.
+	pub fn normal(&self) -> Box<[f64]> {
+		Box::new([self.normal.x, self.normal.y, self.normal.z])
+	}
+}
+
+/// A mesh with a bounding volume hierarchy over its triangles, for repeated
+/// queries such as picking.
+#[wasm_bindgen]
+pub struct MeshBvh {
+	mesh: Mesh,
+	normals: Vec<Vec3>,
+	bvh: Bvh,
+}
+
+impl MeshBvh {
+	pub(crate) fn new(mesh: Mesh, normals: Vec<Vec3>) -> MeshBvh {
+		let boxes: Vec<Aabb> = (0..mesh.triangles.len()).map(|t| Aabb::of_triangle(&mesh, t)).collect();
+		let bvh = Bvh::build(&boxes);
+		MeshBvh { mesh, normals, bvh }
+	}
+
+	pub(crate) fn mesh(&self) -> &Mesh {
+		&self.mesh
+	}
+
+	/// Nearest intersection of the ray `origin + t * dir` with the mesh for
+	/// t >= 0. Triangles are hit from either side.
+	pub(crate) fn cast(&self, origin: Vec3, dir: Vec3) -> Option<RayHit> {
+		self.cast_through(origin, dir, |_| false)
+	}
+
+	/// Like `cast`, but the ray passes through triangles for which `skip` is
+	/// true.
+	pub(crate) fn cast_through(&self, origin: Vec3, dir: Vec3, skip: impl Fn(u32) -> bool) -> Option<RayHit> {
+		if self.bvh.nodes.is_empty() {
+			return None;
+		}
+		let inv_dir = Vec3::new(1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z);
+		let mut best: Option<(f64, u32, f64, f64)> = None;
+		let mut stack = vec![0usize];
+		while let Some(node) = stack.pop() {
+			let max_t = best.map_or(f64::INFINITY, |b| b.0);
+			if self.bvh.nodes[node].bounds.ray_entry(origin, inv_dir, max_t).is_none() {
+				continue;
+			}
+			if self.bvh.nodes[node].is_leaf() {
+				for &t in self.bvh.leaf_items(node) {
+					if let Some((d, u, v)) = ray_triangle(origin, dir, self.mesh.corners(t as usize)) {
+						if d < best.map_or(f64::INFINITY, |b| b.0) && !skip(t) {
+							best = Some((d, t, u, v));
+						}
+					}
+				}
+				continue;
+			}
+			// Visit the nearer child first so that it can prune the other
+			let (l, r) = self.bvh.children(node);
+			let entry = |n: usize| self.bvh.nodes[n].bounds.ray_entry(origin, inv_dir, max_t);
+			match (entry(l), entry(r)) {
+				(Some(tl), Some(tr)) if tl < tr => stack.extend_from_slice(&[r, l]),
+				(Some(_), Some(_)) => stack.extend_from_slice(&[l, r]),
+				(Some(_), None) => stack.push(l),
+				(None, Some(_)) => stack.push(r),
+				(None, None) => {}
+			}
+		}
+
+		let (distance, t, u, v) = best?;
+		Some(self.hit(t, distance, [1.0 - u - v, u, v], origin + dir * distance))
+	}
+
+	/// The point of the mesh closest to `p`, if there is one within
+	/// `max_distance`.
+	pub(crate) fn closest(&self, p: Vec3, max_distance: f64) -> Option<RayHit> {
+		if self.bvh.nodes.is_empty() {
+			return None;
+		}
+		let mut best_d2 = max_distance * max_distance;
+		let mut best: Option<(u32, Vec3, [f64; 3])> = None;
+		let mut stack = vec![(0usize, self.bvh.nodes[0].bounds.distance_squared(p))];
+		while let Some((node, d2)) = stack.pop() {
+			if d2 > best_d2 {
+				continue;
+			}
+			if self.bvh.nodes[node].is_leaf() {
+				for &t in self.bvh.leaf_items(node) {
+					let (q, weights) = closest_on_triangle(p, self.mesh.corners(t as usize));
+					let d2 = (q - p).dot(q - p);
+					if d2 <= best_d2 {
+						best_d2 = d2;
+						best = Some((t, q, weights));
+					}
+				}
+				continue;
+			}
+			let (l, r) = self.bvh.children(node);
+			let dl = self.bvh.nodes[l].bounds.distance_squared(p);
+			let dr = self.bvh.nodes[r].bounds.distance_squared(p);
+			if dl < dr {
+				stack.extend_from_slice(&[(r, dr), (l, dl)]);
+			} else {
+				stack.extend_from_slice(&[(l, dl), (r, dr)]);
+			}
+		}
+		let (t, q, weights) = best?;
+		Some(self.hit(t, best_d2.sqrt(), weights, q))
+	}
+
+	fn hit(&self, t: u32, distance: f64, barycentrics: [f64; 3], point: Vec3) -> RayHit {
+		let tri = self.mesh.triangles[t as usize];
+		let normal = if self.normals.is_empty() {
+			self.mesh.face_normal(t as usize)
+		} else {
+			(0..3)
+				.fold(Vec3::ZERO, |acc, i| {
+					acc + self.normals[tri[i] as usize] * barycentrics[i]
+				})
+				.normalized()
+		};
+		RayHit {
+			triangle: t,
+			distance,
+			barycentrics,
+			point,
+			normal,
+		}
+	}
+}
+
+#[wasm_bindgen]
+impl MeshBvh {
+	#[wasm_bindgen(js_name = "triangleCount")]
+	pub fn triangle_count(&self) -> u32 {
+		self.mesh.triangles.len() as u32
+	}
+
+	/// Cast a ray from `origin` along `dir`, both [x, y, z], and return the
+	/// nearest hit, if any.
+	pub fn raycast(&self, origin: &[f64], dir: &[f64]) -> Option<RayHit> {
+		if origin.len() != 3 || dir.len() != 3 {
+			return None;
+		}
+		self.cast(
+			Vec3::new(origin[0], origin[1], origin[2]),
+			Vec3::new(dir[0], dir[1], dir[2]),
+		)
+	}
+
+	/// The point on the surface closest to `point` [x, y, z]. Only points
+	/// within `max_distance` are considered; pass Infinity for no limit.
+	#[wasm_bindgen(js_name = "closestPoint")]
+	pub fn closest_point(&self, point: &[f64], max_distance: f64) -> Option<RayHit> {
+		if point.len() != 3 {
+			return None;
+		}
+		self.closest(Vec3::new(point[0], point[1], point[2]), max_distance)
+	}
+}
+
+/// Möller–Trumbore ray/triangle intersection. Returns the ray parameter and
+/// the barycentric weights of the second and third corners.
+fn ray_triangle(origin: Vec3, dir: Vec3, [a, b, c]: [Vec3; 3]) -> Option<(f64, f64, f64)> {
+	let e1 = b - a;
+	let e2 = c - a;
+	let p = dir.cross(e2);
+	let det = e1.dot(p);
+	if det == 0.0 || !det.is_finite() {
+		return None;
+	}
+	let inv_det = 1.0 / det;
+	let s = origin - a;
+	let u = s.dot(p) * inv_det;
+	if !(0.0..=1.0).contains(&u) {
+		return None;
+	}
+	let q = s.cross(e1);
+	let v = dir.dot(q) * inv_det;
+	if v < 0.0 || u + v > 1.0 {
+		return None;
+	}
+	let t = e2.dot(q) * inv_det;
+	if t < 0.0 {
+		return None;
+	}
+	Some((t, u, v))
+}
+
+/// The point of triangle abc closest to p, with its barycentric weights
+/// (Ericson, "Real-Time Collision Detection", 5.1.5).
+pub(crate) fn closest_on_triangle(p: Vec3, [a, b, c]: [Vec3; 3]) -> (Vec3, [f64; 3]) {
+	let ab = b - a;
+	let ac = c - a;
+	let ap = p - a;
+	let d1 = ab.dot(ap);
+	let d2 = ac.dot(ap);
+	if d1 <= 0.0 && d2 <= 0.0 {
+		return (a, [1.0, 0.0, 0.0]);
+	}
+	let bp = p - b;
+	let d3 = ab.dot(bp);
+	let d4 = ac.dot(bp);
+	if d3 >= 0.0 && d4 <= d3 {
+		return (b, [0.0, 1.0, 0.0]);
+	}
+	let vc = d1 * d4 - d3 * d2;
+	if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+		let v = d1 / (d1 - d3);
+		return (a + ab * v, [1.0 - v, v, 0.0]);
+	}
+	let cp = p - c;
+	let d5 = ab.dot(cp);
+	let d6 = ac.dot(cp);
+	if d6 >= 0.0 && d5 <= d6 {
+		return (c, [0.0, 0.0, 1.0]);
+	}
+	let vb = d5 * d2 - d1 * d6;
+	if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+		let w = d2 / (d2 - d6);
+		return (a + ac * w, [1.0 - w, 0.0, w]);
+	}
+	let va = d3 * d6 - d5 * d4;
+	if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+		let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+		return (b + (c - b) * w, [0.0, 1.0 - w, w]);
+	}
+	let denom = va + vb + vc;
+	if denom == 0.0 {
+		// Degenerate triangle whose corners tests didn't catch
+		return (a, [1.0, 0.0, 0.0]);
+	}
+	let v = vb / denom;
+	let w = vc / denom;
+	(a + ab * v + ac * w, [1.0 - v - w, v, w])
+}
+
+pub(crate) fn build_mesh_bvh_impl(vertices: &[f32], normals: &[f32], v_indices: &[u32]) -> Result<MeshBvh, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	let n = mesh.positions.len();
+	let normals = if normals.is_empty() {
+		Vec::new()
+	} else if normals.len() < n * 3 {
+		return Err(format!(
+			"Normal buffer of length {} is too short for {} vertices",
+			normals.len(),
+			n
+		));
+	} else {
+		(0..n).map(|i| Vec3::from_f32(normals, i)).collect()
+	};
+	Ok(MeshBvh::new(mesh, normals))
+}
+
+/// Build a bounding volume hierarchy over a mesh from `parseSTLMesh` for
+/// picking and measurement. `normals` are interpolated at ray hits; pass an
+/// empty array to use face normals instead.
+#[wasm_bindgen(js_name = "buildMeshBvh")]
+pub fn build_mesh_bvh(vertices: &[f32], normals: &[f32], v_indices: &[u32]) -> Result<MeshBvh, JsValue> {
+	build_mesh_bvh_impl(vertices, normals, v_indices).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn picks_nearest_face() {
+		let mut mesh = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 1.0, 4);
+		mesh.append(&Mesh::cube(Vec3::new(0.0, 0.0, 3.0), 1.0));
+		let (vertices, normals, v_indices, _) = mesh.parsed();
+		let bvh = build_mesh_bvh_impl(&vertices, &normals, &v_indices).unwrap();
+
+		let hit = bvh.raycast(&[0.3, 0.6, 10.0], &[0.0, 0.0, -1.0]).unwrap();
+		assert!((hit.distance() - 6.0).abs() < 1e-12);
+		assert_eq!(&*hit.point(), &[0.3, 0.6, 4.0]);
+		assert!(hit.triangle() >= 192);
+		let weights = hit.barycentrics();
+		assert!((weights.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+		assert!(weights.iter().all(|&w| w >= 0.0));
+		let tri = mesh.triangles[hit.triangle() as usize];
+		let p = (0..3).fold(Vec3::ZERO, |acc, i| acc + mesh.positions[tri[i] as usize] * weights[i]);
+		assert!((p - Vec3::new(0.3, 0.6, 4.0)).length() < 1e-12);
+
+		// From inside the lower cube, straight down
+		let hit = bvh.raycast(&[0.3, 0.6, 0.5], &[0.0, 0.0, -1.0]).unwrap();
+		assert!((hit.distance() - 0.5).abs() < 1e-12);
+		assert!(hit.triangle() < 192);
+
+		assert!(bvh.raycast(&[2.0, 0.5, 0.5], &[0.0, 0.0, 1.0]).is_none());
+		assert!(bvh.raycast(&[0.5, 0.5, 5.0], &[0.0, 0.0, 1.0]).is_none());
+	}
+
+	#[test]
+	fn closest_points() {
+		let mesh = Mesh::cube(Vec3::new(0.0, 0.0, 0.0), 1.0);
+		let (vertices, _, v_indices, _) = mesh.parsed();
+		let bvh = build_mesh_bvh_impl(&vertices, &[], &v_indices).unwrap();
+
+		let c = bvh.closest_point(&[0.5, 0.25, 3.0], f64::INFINITY).unwrap();
+		assert!((c.distance() - 2.0).abs() < 1e-12);
+		assert_eq!(&*c.point(), &[0.5, 0.25, 1.0]);
+		assert_eq!(&*c.normal(), &[0.0, 0.0, 1.0]);
+
+		// Nearest to an edge, from inside
+		let c = bvh.closest_point(&[0.9, 0.5, 0.95], f64::INFINITY).unwrap();
+		assert!((c.distance() - 0.05).abs() < 1e-12);
+
+		let c = bvh.closest_point(&[2.0, 2.0, 2.0], f64::INFINITY).unwrap();
+		assert!((c.distance() - 3.0f64.sqrt()).abs() < 1e-12);
+		assert!(bvh.closest_point(&[2.0, 2.0, 2.0], 1.0).is_none());
+	}
+}
@@ -0,0 +1,398 @@
+use wasm_bindgen::prelude::*;
+
+use crate::hull::{convex_hull, face_directions};
+use crate::linalg::{sym_eigen3, transform_point, Mat3};
+use crate::mesh::Mesh;
+use crate::vec3::Vec3;
+
+/// How `orientedBoundingBox` picks the box's axes.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BoxFit {
+	/// Principal axes of the convex hull's surface. Fast, but can be
+	/// noticeably larger than the smallest box.
+	Pca = 0,
+	/// Smallest box with a face flush with a face of the convex hull, found by
+	/// rotating calipers around each hull face normal. Usually the smallest
+	/// box overall (O'Rourke, "Finding Minimal Enclosing Boxes").
+	Minimal = 1,
+}
+
+/// Hull face directions tried by `BoxFit::Minimal`, largest faces first. Keeps
+/// smooth, finely tessellated parts from taking quadratic time.
+const MAX_DIRECTIONS: usize = 256;
+
+/// A box with arbitrary orientation.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct OrientedBox {
+	center: Vec3,
+	/// Right-handed unit axes, longest side first
+	axes: [Vec3; 3],
+	half_extents: [f64; 3],
+}
+
+impl OrientedBox {
+	/// Tightest box around `points` with the given right-handed axes, which
+	/// are reordered longest side first.
+	fn around(points: &[Vec3], axes: [Vec3; 3]) -> OrientedBox {
+		let mut lo = [f64::INFINITY; 3];
+		let mut hi = [f64::NEG_INFINITY; 3];
+		for p in points {
+			for i in 0..3 {
+				let d = p.dot(axes[i]);
+				lo[i] = lo[i].min(d);
+				hi[i] = hi[i].max(d);
+			}
+		}
+		let mut order = [0, 1, 2];
+		order.sort_by(|&i, &j| (hi[j] - lo[j]).total_cmp(&(hi[i] - lo[i])));
+		let mut sorted = order.map(|i| axes[i]);
+		sorted[2] = sorted[0].cross(sorted[1]);
+		OrientedBox {
+			center: (0..3).fold(Vec3::ZERO, |acc, i| acc + axes[i] * ((lo[i] + hi[i]) / 2.0)),
+			axes: sorted,
+			half_extents: order.map(|i| (hi[i] - lo[i]) / 2.0),
+		}
+	}
+
+	fn box_volume(&self) -> f64 {
+		8.0 * self.half_extents.iter().product::<f64>()
+	}
+
+	/// Column-major 4x4 matrix taking box coordinates, with the origin at the
+	/// center and the sides along x, y and z, to world coordinates.
+	#[rustfmt::skip]
+	fn matrix(&self) -> [f64; 16] {
+		let [a, b, c] = self.axes;
+		let o = self.center;
+		[
+			a.x, a.y, a.z, 0.0,
+			b.x, b.y, b.z, 0.0,
+			c.x, c.y, c.z, 0.0,
+			o.x, o.y, o.z, 1.0,
+		]
+	}
+
+	/// The inverse of `matrix`, taking world coordinates to box coordinates.
+	#[rustfmt::skip]
+	fn inverse_matrix(&self) -> [f64; 16] {
+		let [a, b, c] = self.axes;
+		let o = self.center;
+		[
+			a.x, b.x, c.x, 0.0,
+			a.y, b.y, c.y, 0.0,
+			a.z, b.z, c.z, 0.0,
+			-a.dot(o), -b.dot(o), -c.dot(o), 1.0,
+		]
+	}
+}
+
+#[wasm_bindgen]
+impl OrientedBox {
+	pub fn center(&self) -> Box<[f64]> {
+		Box::new([self.center.x, self.center.y, self.center.z])
+	}
+
+	/// The three unit axes one after the other, longest side first.
+	pub fn axes(&self) -> Box<[f64]> {
+		self.axes.iter().flat_map(|a| [a.x, a.y, a.z]).collect()
+	}
+
+	/// Half the length of the side along each axis.
+	#[wasm_bindgen(js_name = "halfExtents")]
+	pub fn half_extents(&self) -> Box<[f64]> {
+		Box::new(self.half_extents)
+	}
+
+	pub fn volume(&self) -> f64 {
+		self.box_volume()
+	}
+
+	/// Column-major 4x4 matrix from box space to world space: it maps the
+	/// axis-aligned box centered on the origin with sides `2 * halfExtents`
+	/// onto this box. Invert it with `invertedMat4x4` to go the other way.
+	pub fn transform(&self) -> Box<[f64]> {
+		Box::new(self.matrix())
+	}
+}
+
+/// Centroid and right-handed principal axes, largest spread first, of a
+/// surface weighted by area, or of the points themselves if it has no area.
+pub(crate) fn principal_axes(surface: &Mesh, points: &[Vec3]) -> (Vec3, [Vec3; 3]) {
+	let mut area = 0.0;
+	let mut first = Vec3::ZERO;
+	let mut second: Mat3 = [[0.0; 3]; 3];
+	for t in 0..surface.triangles.len() {
+		let [a, b, c] = surface.corners(t);
+		let w = surface.face_cross(t).length() / 2.0;
+		let s = a + b + c;
+		area += w;
+		first += s * (w / 3.0);
+		// Integral of x x^T over the triangle
+		for i in 0..3 {
+			for j in 0..3 {
+				second[i][j] += w / 12.0 * (a[i] * a[j] + b[i] * b[j] + c[i] * c[j] + s[i] * s[j]);
+			}
+		}
+	}
+	if area == 0.0 {
+		area = points.len() as f64;
+		first = Vec3::ZERO;
+		second = [[0.0; 3]; 3];
+		for &p in points {
+			first += p;
+			for i in 0..3 {
+				for j in 0..3 {
+					second[i][j] += p[i] * p[j];
+				}
+			}
+		}
+	}
+	let mean = first / area;
+	let mut covariance = second;
+	for i in 0..3 {
+		for j in 0..3 {
+			covariance[i][j] = covariance[i][j] / area - mean[i] * mean[j];
+		}
+	}
+	let (_, vectors) = sym_eigen3(&covariance);
+	(mean, [vectors[2], vectors[1], vectors[2].cross(vectors[1])])
+}
+
+/// Smallest area rectangle around 2D points, by trying each edge direction of
+/// their convex hull. Returns the unit direction of one side and the area.
+fn min_rectangle(points: &mut [[f64; 2]]) -> Option<([f64; 2], f64)> {
+	let cross = |o: [f64; 2], a: [f64; 2], b: [f64; 2]| (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0]);
+	points.sort_by(|p, q| p[0].total_cmp(&q[0]).then(p[1].total_cmp(&q[1])));
+	let mut hull: Vec<[f64; 2]> = Vec::with_capacity(points.len() + 1);
+	for pass in 0..2 {
+		let start = hull.len();
+		let mut add = |p: [f64; 2]| {
+			while hull.len() >= start + 2 && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0.0 {
+				hull.pop();
+			}
+			hull.push(p);
+		};
+		if pass == 0 {
+			points.iter().for_each(|&p| add(p));
+		} else {
+			points.iter().rev().for_each(|&p| add(p));
+		}
+		hull.pop();
+	}
+
+	let mut best: Option<([f64; 2], f64)> = None;
+	for i in 0..hull.len() {
+		let (a, b) = (hull[i], hull[(i + 1) % hull.len()]);
+		let len = ((b[0] - a[0]).powi(2) + (b[1] - a[1]).powi(2)).sqrt();
+		if len == 0.0 {
+			continue;
+		}
+		let d = [(b[0] - a[0]) / len, (b[1] - a[1]) / len];
+		let (mut lo, mut hi, mut height) = (f64::INFINITY, f64::NEG_INFINITY, 0.0f64);
+		for p in &hull {
+			let (x, y) = (p[0] - a[0], p[1] - a[1]);
+			let along = x * d[0] + y * d[1];
+			lo = lo.min(along);
+			hi = hi.max(along);
+			height = height.max(x * d[1] - y * d[0]).max(y * d[0] - x * d[1]);
+		}
+		let area = (hi - lo) * height;
+		if best.map_or(true, |(_, a)| area < a) {
+			best = Some((d, area));
+		}
+	}
+	best
+}
+
+/// Oriented bounding box of a set of points.
+pub(crate) fn oriented_box(points: &[Vec3], fit: BoxFit) -> OrientedBox {
+	let hull = convex_hull(points);
+	let corners: &[Vec3] = if hull.positions.is_empty() {
+		points
+	} else {
+		&hull.positions
+	};
+	let mut best = OrientedBox::around(corners, principal_axes(&hull, points).1);
+	if fit == BoxFit::Pca {
+		return best;
+	}
+
+	// A box flush with one face is also flush with any face parallel to it
+	let mut directions = face_directions(&hull, true);
+	directions.truncate(MAX_DIRECTIONS);
+
+	let mut projected = Vec::with_capacity(corners.len());
+	for (n, _) in directions {
+		let u = n.any_perpendicular();
+		let w = n.cross(u);
+		projected.clear();
+		projected.extend(corners.iter().map(|p| [p.dot(u), p.dot(w)]));
+		let Some((d, area)) = min_rectangle(&mut projected) else {
+			continue;
+		};
+		let (lo, hi) = corners.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), p| {
+			(lo.min(p.dot(n)), hi.max(p.dot(n)))
+		});
+		if area * (hi - lo) < best.box_volume() {
+			let side = u * d[0] + w * d[1];
+			best = OrientedBox::around(corners, [side, n.cross(side), n]);
+		}
+	}
+	best
+}
+
+pub(crate) fn oriented_bounding_box_impl(
+	vertices: &[f32],
+	v_indices: &[u32],
+	fit: BoxFit,
+) -> Result<OrientedBox, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	if mesh.positions.is_empty() {
+		return Err(String::from("Mesh has no vertices"));
+	}
+	Ok(oriented_box(&mesh.positions, fit))
+}
+
+/// Oriented bounding box of a mesh from `parseSTLMesh`.
+#[wasm_bindgen(js_name = "orientedBoundingBox")]
+pub fn oriented_bounding_box(vertices: &[f32], v_indices: &[u32], fit: BoxFit) -> Result<OrientedBox, JsValue> {
+	oriented_bounding_box_impl(vertices, v_indices, fit).map_err(|e| JsValue::from_str(&e))
+}
+
+pub(crate) fn align_principal_axes_impl(
+	vertices: &mut [f32],
+	normals: &mut [f32],
+	v_indices: &[u32],
+) -> Result<Box<[f64]>, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	if mesh.positions.is_empty() {
+		return Err(String::from("Mesh has no vertices"));
+	}
+	let count = mesh.positions.len();
+	// Checked before anything moves, so the buffers are left as they were
+	if !normals.is_empty() && normals.len() < count * 3 {
+		return Err(format!(
+			"Normal buffer of length {} is too short for {} vertices",
+			normals.len(),
+			count
+		));
+	}
+	let obb = oriented_box(&mesh.positions, BoxFit::Pca);
+	let m = obb.inverse_matrix();
+	for (v, &p) in mesh.positions.iter().enumerate() {
+		let q = transform_point(&m, p);
+		vertices[v * 3..v * 3 + 3].copy_from_slice(&[q.x as f32, q.y as f32, q.z as f32]);
+	}
+	// Normals only rotate
+	if !normals.is_empty() {
+		for v in 0..count {
+			let n = Vec3::from_f32(normals, v);
+			for i in 0..3 {
+				normals[v * 3 + i] = (m[i] * n.x + m[4 + i] * n.y + m[8 + i] * n.z) as f32;
+			}
+		}
+	}
+	Ok(Box::new(m))
+}
+
+/// Re-pose a mesh from `parseSTLMesh` in place so that its principal axes lie
+/// along x, y and z, largest spread along x, with the center of its principal
+/// axis box at the origin. `normals` may be empty. Returns the column-major
+/// 4x4 matrix that was applied, so it can be undone with `invertedMat4x4`.
+#[wasm_bindgen(js_name = "alignPrincipalAxes")]
+pub fn align_principal_axes(
+	vertices: &mut [f32],
+	normals: &mut [f32],
+	v_indices: &[u32],
+) -> Result<Box<[f64]>, JsValue> {
+	align_principal_axes_impl(vertices, normals, v_indices).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A 4x2x1 box tessellated finely, turned about a skewed axis and moved.
+	fn turned_box() -> (Mesh, [f64; 16]) {
+		let mut mesh = Mesh::tessellated_cube(Vec3::new(-0.5, -0.5, -0.5), 1.0, 4);
+		let mut m = [
+			1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 3.0, -2.0, 7.0, 1.0,
+		];
+		crate::rotate_mat4x4(&mut m, 0.7, &[1.0, 2.0, 0.5]);
+		for p in mesh.positions.iter_mut() {
+			let s = Vec3::new(p.x * 4.0, p.y * 2.0, p.z);
+			*p = Vec3::new(
+				m[0] * s.x + m[4] * s.y + m[8] * s.z + m[12],
+				m[1] * s.x + m[5] * s.y + m[9] * s.z + m[13],
+				m[2] * s.x + m[6] * s.y + m[10] * s.z + m[14],
+			);
+		}
+		(mesh, m)
+	}
+
+	#[test]
+	fn boxes() {
+		let (mesh, m) = turned_box();
+		for fit in [BoxFit::Pca, BoxFit::Minimal] {
+			let obb = oriented_box(&mesh.positions, fit);
+			assert!((obb.box_volume() - 8.0).abs() < 1e-9, "{:?} {}", fit, obb.box_volume());
+			for (h, e) in obb.half_extents.iter().zip([2.0, 1.0, 0.5]) {
+				assert!((h - e).abs() < 1e-9);
+			}
+			let center = Vec3::new(m[12], m[13], m[14]);
+			assert!((obb.center - center).length() < 1e-9);
+			let x = Vec3::new(m[0], m[1], m[2]);
+			assert!(obb.axes[0].cross(x).length() < 1e-9);
+			assert!((obb.axes[0].cross(obb.axes[1]).dot(obb.axes[2]) - 1.0).abs() < 1e-12);
+		}
+
+		// A tetrahedron has no symmetry for PCA to find, but the minimal box
+		// is still flush with a face
+		let tetra = [
+			Vec3::new(0.0, 0.0, 0.0),
+			Vec3::new(3.0, 0.0, 0.0),
+			Vec3::new(0.5, 2.0, 0.0),
+			Vec3::new(1.0, 0.7, 1.5),
+		];
+		let pca = oriented_box(&tetra, BoxFit::Pca);
+		let min = oriented_box(&tetra, BoxFit::Minimal);
+		assert!(min.box_volume() <= pca.box_volume() + 1e-12);
+		assert!(min.box_volume() <= 3.0 * 2.0 * 1.5 + 1e-12);
+	}
+
+	#[test]
+	fn alignment() {
+		let (mesh, _) = turned_box();
+		let (mut vertices, mut normals, v_indices, _) = mesh.parsed();
+		let original = vertices.clone();
+		// A short normal buffer is an error, before either buffer is touched
+		let count = Mesh::from_buffers(&vertices, &v_indices).unwrap().positions.len();
+		let mut short = normals[..count * 3 - 1].to_vec();
+		assert!(align_principal_axes_impl(&mut vertices, &mut short, &v_indices).is_err());
+		assert_eq!(vertices, original);
+		let m = align_principal_axes_impl(&mut vertices, &mut normals, &v_indices).unwrap();
+		let aligned = Mesh::from_buffers(&vertices, &v_indices).unwrap();
+		let (lo, hi) = aligned.bounds();
+		for (i, e) in [2.0, 1.0, 0.5].iter().enumerate() {
+			assert!(
+				(hi[i] - e).abs() < 1e-5 && (lo[i] + e).abs() < 1e-5,
+				"{:?} {:?}",
+				lo,
+				hi
+			);
+		}
+		// Normals turned with the mesh still point out of the box
+		for (v, p) in aligned.positions.iter().enumerate() {
+			let n = Vec3::from_f32(&normals, v);
+			for i in 0..3 {
+				assert!(n[i] * p[i] > -1e-5, "{:?} {:?}", n, p);
+			}
+		}
+		let back = crate::inverted_mat4x4(&m);
+		let p = Vec3::from_f32(&vertices, 5);
+		let x = back[0] * p.x + back[4] * p.y + back[8] * p.z + back[12];
+		assert!((x - original[15] as f64).abs() < 1e-4);
+	}
+}
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+
+use crate::boolean::{boolean, BooleanOperation};
+use crate::isosurface::{contour, surface_nets};
+use crate::mesh::{Mesh, MeshBuffers};
+use crate::mesh_bvh::MeshBvh;
+use crate::sdf::distance_field;
+use crate::vec3::Vec3;
+
+/// Most drain holes drilled into one mesh, as each takes a mesh boolean.
+const MAX_DRAIN_HOLES: usize = 32;
+
+/// A solid with a closed cavity inside it.
+#[wasm_bindgen]
+pub struct HollowedMesh {
+	mesh: MeshBuffers,
+	cavity_volume: f64,
+}
+
+#[wasm_bindgen]
+impl HollowedMesh {
+	/// The original surface and the cavity's, which faces inwards, together
+	/// in one mesh.
+	pub fn mesh(&self) -> MeshBuffers {
+		self.mesh.clone()
+	}
+
+	/// Volume of the material left, the original volume less the cavity.
+	#[wasm_bindgen(js_name = "materialVolume")]
+	pub fn material_volume(&self) -> f64 {
+		self.mesh.volume()
+	}
+
+	/// Volume of the cavity.
+	#[wasm_bindgen(js_name = "cavityVolume")]
+	pub fn cavity_volume(&self) -> f64 {
+		self.cavity_volume
+	}
+}
+
+/// A round hole drilled from a point on the surface along `direction` until
+/// it's through the wall, to let resin out of the cavity.
+pub(crate) struct DrainHole {
+	pub position: Vec3,
+	pub direction: Vec3,
+	pub radius: f64,
+}
+
+/// A closed cylinder from `start` to `end`, its round sides approximated
+/// with `sides` flat ones.
+fn cylinder(start: Vec3, end: Vec3, radius: f64, sides: usize) -> Mesh {
+	let axis = (end - start).normalized();
+	let u = axis.any_perpendicular();
+	let v = axis.cross(u);
+	let mut mesh = Mesh::default();
+	for center in [start, end] {
+		for k in 0..sides {
+			let angle = std::f64::consts::TAU * k as f64 / sides as f64;
+			mesh
+				.positions
+				.push(center + (u * angle.cos() + v * angle.sin()) * radius);
+		}
+	}
+	mesh.positions.extend([start, end]);
+	let (bottom, top, n) = (2 * sides as u32, 2 * sides as u32 + 1, sides as u32);
+	for k in 0..n {
+		let next = (k + 1) % n;
+		mesh.triangles.extend([
+			[k, next, n + next],
+			[k, n + next, n + k],
+			[bottom, next, k],
+			[top, n + k, n + next],
+		]);
+	}
+	mesh
+}
+
+/// The surface at signed distance `distance` from a closed mesh, remeshed on
+/// a grid of the given spacing.
+pub(crate) fn offset(mesh: &Mesh, distance: f64, spacing: f64) -> Result<MeshBuffers, String> {
+	if !distance.is_finite() {
+		return Err(format!("Offset must be finite, but is {}", distance));
+	}
+	// Only samples next to the offset surface need exact distances
+	let band = distance.abs() + 2.0 * spacing;
+	let grid = distance_field(mesh, spacing, distance.max(0.0), band)?;
+	Ok(contour(&grid, distance))
+}
+
+/// The surface at distance `wall` inside a closed mesh, and its normals.
+fn cavity(mesh: &Mesh, wall: f64, spacing: f64) -> Result<(Mesh, Vec<Vec3>), String> {
+	if !wall.is_finite() || wall <= 0.0 {
+		return Err(format!("Wall thickness must be positive, but is {}", wall));
+	}
+	let grid = distance_field(mesh, spacing, 0.0, wall + 2.0 * spacing)?;
+	Ok(surface_nets(&grid, -wall))
+}
+
+/// The mesh with the cavity's triangles turned around added to it, so it
+/// still has the material on the back of every face, and its normals.
+fn with_cavity(mesh: &Mesh, cavity: &Mesh, cavity_normals: &[Vec3]) -> (Mesh, Vec<Vec3>) {
+	let mut hollowed = mesh.clone();
+	let mut normals = mesh.vertex_normals();
+	let first = hollowed.positions.len() as u32;
+	hollowed.positions.extend_from_slice(&cavity.positions);
+	normals.extend(cavity_normals.iter().map(|&n| -n));
+	hollowed.triangles.extend(
+		cavity
+			.triangles
+			.iter()
+			.map(|t| [t[0] + first, t[2] + first, t[1] + first]),
+	);
+	(hollowed, normals)
+}
+
+/// Adds the surface at distance `wall` inside a closed mesh as a cavity. The cavity's
+/// surface is the distance field's contour with its triangles turned around,
+/// so the result still has the material on the back of every face.
+pub(crate) fn hollow(mesh: &Mesh, wall: f64, spacing: f64) -> Result<HollowedMesh, String> {
+	let (cavity, cavity_normals) = cavity(mesh, wall, spacing)?;
+	let (hollowed, normals) = with_cavity(mesh, &cavity, &cavity_normals);
+	Ok(HollowedMesh {
+		mesh: hollowed.to_buffers_with_normals(&normals),
+		cavity_volume: cavity.volume(),
+	})
+}
+
+/// Hollows a closed mesh like `hollow`, then cuts the drain holes out of it
+/// with cylinders that reach a little way into the cavity.
+pub(crate) fn hollow_with_drain_holes(
+	mesh: &Mesh,
+	wall: f64,
+	spacing: f64,
+	holes: &[DrainHole],
+) -> Result<HollowedMesh, String> {
+	if holes.len() > MAX_DRAIN_HOLES {
+		return Err(format!(
+			"Can drill at most {} drain holes, but {} were asked for",
+			MAX_DRAIN_HOLES,
+			holes.len()
+		));
+	}
+	let (cavity, cavity_normals) = cavity(mesh, wall, spacing)?;
+	let cavity_volume = cavity.volume();
+	let (mut hollowed, normals) = with_cavity(mesh, &cavity, &cavity_normals);
+	// The boolean keeps the positions of the vertices it doesn't cut away,
+	// so their normals are found again by position
+	let key = |p: &Vec3| [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()];
+	let normals: HashMap<[u64; 3], Vec3> = hollowed.positions.iter().map(key).zip(normals).collect();
+	let cavity = MeshBvh::new(cavity, cavity_normals);
+	for (i, hole) in holes.iter().enumerate() {
+		if !hole.radius.is_finite() || hole.radius <= 0.0 {
+			return Err(format!("Drain hole radius must be positive, but is {}", hole.radius));
+		}
+		// NaN and infinite components make the length NaN or infinite
+		let length = hole.direction.length();
+		if !length.is_finite() || length == 0.0 {
+			return Err(format!("Drain hole {} has no direction", i));
+		}
+		let axis = hole.direction * (1.0 / length);
+		let depth = cavity
+			.cast(hole.position, axis)
+			.ok_or_else(|| format!("Drain hole {} doesn't reach the cavity", i))?
+			.distance();
+		// From outside the surface, which may curve away from the point, to
+		// past where the rim of the hole meets the cavity
+		let start = hole.position - axis * wall;
+		let end = hole.position + axis * (depth + hole.radius + spacing);
+		let sides = ((std::f64::consts::TAU * hole.radius / spacing).ceil() as usize).clamp(12, 64);
+		hollowed = boolean(
+			&hollowed,
+			&cylinder(start, end, hole.radius, sides),
+			BooleanOperation::Difference,
+		)?;
+	}
+	// Only the walls of the holes get normals averaged from their faces
+	let normals: Vec<Vec3> = hollowed
+		.positions
+		.iter()
+		.zip(hollowed.vertex_normals())
+		.map(|(p, n)| normals.get(&key(p)).copied().unwrap_or(n))
+		.collect();
+	Ok(HollowedMesh {
+		mesh: hollowed.to_buffers_with_normals(&normals),
+		cavity_volume,
+	})
+}
+
+pub(crate) fn offset_mesh_impl(
+	vertices: &[f32],
+	v_indices: &[u32],
+	distance: f64,
+	spacing: f64,
+) -> Result<MeshBuffers, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	if mesh.triangles.is_empty() {
+		return Err(String::from("Mesh has no triangles"));
+	}
+	offset(&mesh, distance, spacing)
+}
+
+/// Grow a closed mesh from `parseSTLMesh` by `distance`, or shrink it for
+/// negative distances, by remeshing its signed distance field sampled every
+/// `spacing`. Grown corners and edges come out rounded. Details smaller than
+/// the spacing are lost.
+#[wasm_bindgen(js_name = "offsetMesh")]
+pub fn offset_mesh(vertices: &[f32], v_indices: &[u32], distance: f64, spacing: f64) -> Result<MeshBuffers, JsValue> {
+	offset_mesh_impl(vertices, v_indices, distance, spacing).map_err(|e| JsValue::from_str(&e))
+}
+
+pub(crate) fn hollow_mesh_impl(
+	vertices: &[f32],
+	v_indices: &[u32],
+	wall_thickness: f64,
+	spacing: f64,
+) -> Result<HollowedMesh, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	if mesh.triangles.is_empty() {
+		return Err(String::from("Mesh has no triangles"));
+	}
+	hollow(&mesh, wall_thickness, spacing)
+}
+
+/// Hollow out a closed mesh from `parseSTLMesh`, leaving walls
+/// `wallThickness` thick, e.g. to save resin. The cavity is the offset
+/// surface `wallThickness` inside, remeshed every `spacing`, which should be
+/// well below the wall thickness. Parts thinner than twice the wall stay
+/// solid. The cavity is sealed; drain holes still have to be cut into it.
+#[wasm_bindgen(js_name = "hollowMesh")]
+pub fn hollow_mesh(
+	vertices: &[f32],
+	v_indices: &[u32],
+	wall_thickness: f64,
+	spacing: f64,
+) -> Result<HollowedMesh, JsValue> {
+	hollow_mesh_impl(vertices, v_indices, wall_thickness, spacing).map_err(|e| JsValue::from_str(&e))
+}
+
+pub(crate) fn hollow_mesh_with_drain_holes_impl(
+	vertices: &[f32],
+	v_indices: &[u32],
+	wall_thickness: f64,
+	spacing: f64,
+	drain_holes: &[f64],
+) -> Result<HollowedMesh, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	if mesh.triangles.is_empty() {
+		return Err(String::from("Mesh has no triangles"));
+	}
+	if drain_holes.len() % 7 != 0 {
+		return Err(format!(
+			"Drain holes take 7 numbers each, but {} were given",
+			drain_holes.len()
+		));
+	}
+	let holes: Vec<DrainHole> = drain_holes
+		.chunks_exact(7)
+		.map(|h| DrainHole {
+			position: Vec3::new(h[0], h[1], h[2]),
+			direction: Vec3::new(h[3], h[4], h[5]),
+			radius: h[6],
+		})
+		.collect();
+	hollow_with_drain_holes(&mesh, wall_thickness, spacing, &holes)
+}
+
+/// Hollow out a closed mesh from `parseSTLMesh` like `hollowMesh`, and drill
+/// drain holes through the wall so resin can drain from the cavity.
+/// `drainHoles` has 7 numbers per hole: a point on the surface, the
+/// direction into the part, and the radius. Holes that don't reach the
+/// cavity are an error.
+#[wasm_bindgen(js_name = "hollowMeshWithDrainHoles")]
+pub fn hollow_mesh_with_drain_holes(
+	vertices: &[f32],
+	v_indices: &[u32],
+	wall_thickness: f64,
+	spacing: f64,
+	drain_holes: &[f64],
+) -> Result<HollowedMesh, JsValue> {
+	hollow_mesh_with_drain_holes_impl(vertices, v_indices, wall_thickness, spacing, drain_holes)
+		.map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::sdf::WindingNumbers;
+
+	#[test]
+	fn cube() {
+		let mesh = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 2.0, 4);
+		let (vertices, _, v_indices, _) = mesh.parsed();
+
+		// Rounded over the edges and corners
+		let grown = offset_mesh_impl(&vertices, &v_indices, 0.2, 0.05).unwrap();
+		let expected = 8.0 + 24.0 * 0.2 + 6.0 * std::f64::consts::PI * 0.04 + 4.0 / 3.0 * std::f64::consts::PI * 0.008;
+		assert!(
+			(grown.volume() - expected).abs() < 0.01 * expected,
+			"{}",
+			grown.volume()
+		);
+		let bounds = grown.bounds();
+		assert!(bounds
+			.iter()
+			.zip([-0.2, -0.2, -0.2, 2.2, 2.2, 2.2])
+			.all(|(b, e)| (b - e).abs() < 0.01));
+
+		let hollowed = hollow_mesh_impl(&vertices, &v_indices, 0.4, 0.1).unwrap();
+		let buffers = hollowed.mesh();
+		let result = Mesh::from_buffers(&buffers.vertices(), &buffers.v_indices()).unwrap();
+		assert!(result.edge_faces().values().all(|faces| faces.len() == 2));
+		// A 1.2 cube, with its edges slightly cut off
+		assert!(
+			(hollowed.cavity_volume() - 1.728).abs() < 0.03,
+			"{}",
+			hollowed.cavity_volume()
+		);
+		assert!((hollowed.material_volume() + hollowed.cavity_volume() - 8.0).abs() < 1e-9);
+		// The cavity faces inwards
+		let normals = buffers.normals();
+		let first = vertices.len() / 3;
+		assert!(first < result.positions.len());
+		for (v, &p) in result.positions.iter().enumerate().skip(first) {
+			assert!(Vec3::from_f32(&normals, v).dot(Vec3::new(1.0, 1.0, 1.0) - p) > 0.0);
+		}
+
+		assert!(hollow_mesh_impl(&vertices, &v_indices, 0.0, 0.1).is_err());
+		assert!(offset_mesh_impl(&vertices, &v_indices, 0.1, -1.0).is_err());
+	}
+
+	#[test]
+	fn drain_hole() {
+		let mesh = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 2.0, 4);
+		let (vertices, _, v_indices, _) = mesh.parsed();
+		let sealed = hollow_mesh_impl(&vertices, &v_indices, 0.4, 0.1).unwrap();
+		// Down through the middle of the top
+		let hole = [1.0, 1.0, 2.0, 0.0, 0.0, -1.0, 0.2];
+		let drained = hollow_mesh_with_drain_holes_impl(&vertices, &v_indices, 0.4, 0.1, &hole).unwrap();
+		let buffers = drained.mesh();
+		let result = Mesh::from_buffers(&buffers.vertices(), &buffers.v_indices()).unwrap();
+		assert!(result.edge_faces().values().all(|faces| faces.len() == 2));
+
+		// The wall is open where the hole goes through it, and only there
+		let winding = WindingNumbers::new(&result);
+		assert!(winding.at(Vec3::new(1.0, 1.0, 1.8)) < 0.5);
+		assert!(winding.at(Vec3::new(1.5, 1.5, 1.8)) > 0.5);
+		// A 13 sided prism through the 0.4 wall
+		let removed = 0.5 * 13.0 * 0.04 * (std::f64::consts::TAU / 13.0).sin() * 0.4;
+		let lost = sealed.material_volume() - drained.material_volume();
+		assert!((lost - removed).abs() < 0.005, "{}", lost);
+		assert_eq!(drained.cavity_volume(), sealed.cavity_volume());
+		// Vertices away from the hole keep the normals hollowMesh gives them
+		let normals_at = |buffers: &MeshBuffers| -> HashMap<[u32; 3], [u32; 3]> {
+			let (vertices, normals) = (buffers.vertices(), buffers.normals());
+			let bits = |c: &[f32]| [c[0].to_bits(), c[1].to_bits(), c[2].to_bits()];
+			vertices
+				.chunks_exact(3)
+				.map(bits)
+				.zip(normals.chunks_exact(3).map(bits))
+				.collect()
+		};
+		let sealed_normals = normals_at(&sealed.mesh());
+		let drained_normals = normals_at(&buffers);
+		let mut kept = 0;
+		for (p, n) in &drained_normals {
+			if let Some(m) = sealed_normals.get(p) {
+				assert_eq!(m, n);
+				kept += 1;
+			}
+		}
+		assert!(kept > drained_normals.len() / 2);
+
+		let drill = |holes: &[f64]| hollow_mesh_with_drain_holes_impl(&vertices, &v_indices, 0.4, 0.1, holes);
+		// Pointing out of the part
+		assert!(drill(&[1.0, 1.0, 2.0, 0.0, 0.0, 1.0, 0.2]).is_err());
+		assert!(drill(&hole[..6]).is_err());
+		assert!(drill(&[1.0, 1.0, 2.0, 0.0, 0.0, -1.0, 0.0]).is_err());
+		assert!(drill(&[1.0, 1.0, 2.0, 0.0, 0.0, 0.0, 0.2]).is_err());
+		assert!(drill(&[1.0, 1.0, 2.0, f64::NAN, 0.0, -1.0, 0.2]).is_err());
+		assert!(drill(&hole.repeat(MAX_DRAIN_HOLES + 1)).is_err());
+	}
+}
@@ -0,0 +1,240 @@
+use wasm_bindgen::prelude::*;
+
+use crate::hull::{convex_hull, face_directions};
+use crate::linalg::{rotation_between, to_mat4x4};
+use crate::mesh::Mesh;
+use crate::obb::principal_axes;
+use crate::overhang::{find_overhangs, FaceSupport, Overhangs};
+use crate::vec3::Vec3;
+
+/// Largest hull faces tried as the face to stand a part on.
+const MAX_CANDIDATES: usize = 64;
+
+/// How well a part prints standing one way up.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct PrintOrientation {
+	rotation: [f64; 16],
+	overhang_area: f64,
+	support_volume: f64,
+	contact_area: f64,
+	height: f64,
+	score: f64,
+}
+
+#[wasm_bindgen]
+impl PrintOrientation {
+	/// Column-major 4x4 rotation matrix that turns the part this way up, with
+	/// the build direction along +z. It doesn't move the part onto the bed.
+	pub fn rotation(&self) -> Box<[f64]> {
+		Box::new(self.rotation)
+	}
+
+	/// Area of downward facing triangles steeper than the critical angle,
+	/// leaving out those resting on the bed.
+	#[wasm_bindgen(js_name = "overhangArea")]
+	pub fn overhang_area(&self) -> f64 {
+		self.overhang_area
+	}
+
+	/// Volume between the overhangs and the bed. Doesn't account for parts of
+	/// the mesh in between, so it overestimates for overhangs above the part
+	/// itself.
+	#[wasm_bindgen(js_name = "supportVolume")]
+	pub fn support_volume(&self) -> f64 {
+		self.support_volume
+	}
+
+	/// Area of the triangles lying flat on the bed.
+	#[wasm_bindgen(js_name = "contactArea")]
+	pub fn contact_area(&self) -> f64 {
+		self.contact_area
+	}
+
+	/// Height of the part along the build direction.
+	pub fn height(&self) -> f64 {
+		self.height
+	}
+
+	/// Weighted score the orientation was chosen by. Lower is better.
+	pub fn score(&self) -> f64 {
+		self.score
+	}
+}
+
+struct Weights {
+	overhang: f64,
+	support: f64,
+	contact: f64,
+	height: f64,
+}
+
+/// Scores the mesh standing with `down` pointing at the bed. Areas are
+/// divided by the total surface area and lengths by `size` so the weights
+/// don't depend on the part's scale.
+fn evaluate(mesh: &Mesh, down: Vec3, sin_critical: f64, size: f64, weights: &Weights) -> PrintOrientation {
+	let up = -down;
+	let Overhangs { faces, bed, top } = find_overhangs(mesh, up, sin_critical, 1e-6 * size);
+	let (mut total_area, mut overhang_area, mut support_volume, mut contact_area) = (0.0, 0.0, 0.0, 0.0);
+	for (t, tri) in mesh.triangles.iter().enumerate() {
+		let cross = mesh.face_cross(t);
+		let area = cross.length() / 2.0;
+		total_area += area;
+		match faces[t] {
+			FaceSupport::Bed => contact_area += area,
+			FaceSupport::Overhang => {
+				let facing_down = -cross.dot(up) / (2.0 * area);
+				let centroid = tri.iter().map(|&v| mesh.positions[v as usize].dot(up)).sum::<f64>() / 3.0;
+				overhang_area += area;
+				support_volume += area * facing_down * (centroid - bed);
+			}
+			FaceSupport::SelfSupporting => {}
+		}
+	}
+
+	let height = top - bed;
+	let area = total_area.max(f64::MIN_POSITIVE);
+	let score = weights.overhang * overhang_area / area + weights.support * support_volume / (area * size)
+		- weights.contact * contact_area / area
+		+ weights.height * height / size;
+	PrintOrientation {
+		rotation: to_mat4x4(&rotation_between(down, Vec3::new(0.0, 0.0, -1.0)), Vec3::ZERO),
+		overhang_area,
+		support_volume,
+		contact_area,
+		height,
+		score,
+	}
+}
+
+/// Tries standing the mesh on each of its largest convex hull faces, on the
+/// ends of its principal axes and as it is, and returns the best scoring.
+fn orient(mesh: &Mesh, critical_angle: f64, weights: &Weights) -> PrintOrientation {
+	let hull = convex_hull(&mesh.positions);
+	let (lo, hi) = mesh.bounds();
+	let size = (hi - lo).length().max(f64::MIN_POSITIVE);
+
+	// The current orientation first, so ties don't turn the part needlessly
+	let mut candidates = vec![Vec3::new(0.0, 0.0, -1.0)];
+	candidates.extend(
+		face_directions(&hull, false)
+			.into_iter()
+			.take(MAX_CANDIDATES)
+			.map(|(n, _)| n),
+	);
+	for axis in principal_axes(&hull, &mesh.positions).1 {
+		candidates.push(axis);
+		candidates.push(-axis);
+	}
+
+	let sin_critical = critical_angle.sin();
+	candidates
+		.into_iter()
+		.map(|down| evaluate(mesh, down, sin_critical, size, weights))
+		.reduce(|best, o| if o.score < best.score { o } else { best })
+		.unwrap()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn orient_for_print_impl(
+	vertices: &[f32],
+	v_indices: &[u32],
+	critical_angle: f64,
+	overhang_weight: f64,
+	support_weight: f64,
+	contact_weight: f64,
+	height_weight: f64,
+) -> Result<PrintOrientation, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	if mesh.triangles.is_empty() {
+		return Err(String::from("Mesh has no triangles"));
+	}
+	let weights = Weights {
+		overhang: overhang_weight,
+		support: support_weight,
+		contact: contact_weight,
+		height: height_weight,
+	};
+	Ok(orient(&mesh, critical_angle, &weights))
+}
+
+/// Find a good way up to print a mesh from `parseSTLMesh`. Overhangs are
+/// downward facing triangles tilted more than `criticalAngle` radians from
+/// vertical, e.g. pi / 4; 0 counts every downward facing triangle.
+///
+/// Candidates are scored by the weighted sum of the overhang area and the
+/// contact area as fractions of the surface area, and of the support volume
+/// and height relative to the size of the part, with contact counting in
+/// favour. Weights of 1, 1, 1 and 0.1 are a reasonable start.
+#[allow(clippy::too_many_arguments)]
+#[wasm_bindgen(js_name = "orientForPrint")]
+pub fn orient_for_print(
+	vertices: &[f32],
+	v_indices: &[u32],
+	critical_angle: f64,
+	overhang_weight: f64,
+	support_weight: f64,
+	contact_weight: f64,
+	height_weight: f64,
+) -> Result<PrintOrientation, JsValue> {
+	orient_for_print_impl(
+		vertices,
+		v_indices,
+		critical_angle,
+		overhang_weight,
+		support_weight,
+		contact_weight,
+		height_weight,
+	)
+	.map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::linalg::transform_point;
+	use std::f64::consts::PI;
+
+	#[test]
+	fn pyramid() {
+		// Square base 4 wide, 3 high, standing on its base as loaded
+		let mesh = Mesh {
+			positions: vec![
+				Vec3::new(0.0, 0.0, 0.0),
+				Vec3::new(4.0, 0.0, 0.0),
+				Vec3::new(4.0, 4.0, 0.0),
+				Vec3::new(0.0, 4.0, 0.0),
+				Vec3::new(2.0, 2.0, 3.0),
+			],
+			triangles: vec![[0, 2, 1], [0, 3, 2], [0, 1, 4], [1, 2, 4], [2, 3, 4], [3, 0, 4]],
+		};
+		let weights = Weights {
+			overhang: 1.0,
+			support: 1.0,
+			contact: 1.0,
+			height: 0.1,
+		};
+		let best = orient(&mesh, PI / 4.0, &weights);
+		assert_eq!(best.rotation[0], 1.0);
+		assert_eq!(best.contact_area, 16.0);
+		assert_eq!(best.overhang_area, 0.0);
+		assert_eq!(best.height, 3.0);
+
+		// Turned over, it should be turned back
+		let (vertices, _, v_indices, _) = mesh.parsed();
+		let mut flipped = vertices.clone();
+		for v in flipped.chunks_mut(3) {
+			v[1] = -v[1];
+			v[2] = -v[2];
+		}
+		let o = orient_for_print_impl(&flipped, &v_indices, PI / 4.0, 1.0, 1.0, 1.0, 0.1).unwrap();
+		assert_eq!(o.contact_area(), 16.0);
+		let up = transform_point(&o.rotation(), Vec3::new(0.0, 0.0, 1.0));
+		assert!((up - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-12);
+
+		// Scored on overhangs alone, any way up without them will do
+		let o = orient_for_print_impl(&flipped, &v_indices, PI / 4.0, 1.0, 0.0, 0.0, 0.0).unwrap();
+		assert_eq!(o.overhang_area(), 0.0);
+		assert!(orient_for_print_impl(&[], &[], PI / 4.0, 1.0, 1.0, 1.0, 0.1).is_err());
+	}
+}
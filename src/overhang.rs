@@ -0,0 +1,163 @@
+use wasm_bindgen::prelude::*;
+
+use crate::mesh::Mesh;
+use crate::vec3::Vec3;
+
+/// How a triangle is held up during printing. The values are the flags
+/// returned by `OverhangAnalysis::flags`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub(crate) enum FaceSupport {
+	/// Facing up, sideways, or down at a gentle enough angle to print on the
+	/// layers below. Also degenerate triangles.
+	SelfSupporting = 0,
+	/// Facing down more steeply than the critical angle
+	Overhang = 1,
+	/// Lying on the bed
+	Bed = 2,
+}
+
+pub(crate) struct Overhangs {
+	pub faces: Vec<FaceSupport>,
+	/// Lowest and highest height of the mesh along the build direction
+	pub bed: f64,
+	pub top: f64,
+}
+
+/// Classifies the triangles of a mesh built along the unit vector `up`.
+/// Overhangs are downward facing triangles tilted more than the critical
+/// angle from vertical, given by its sine. Triangles with every vertex within
+/// `tolerance` of the lowest point are on the bed rather than overhangs.
+pub(crate) fn find_overhangs(mesh: &Mesh, up: Vec3, sin_critical: f64, tolerance: f64) -> Overhangs {
+	let heights: Vec<f64> = mesh.positions.iter().map(|p| p.dot(up)).collect();
+	let (bed, top) = heights.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &h| {
+		(lo.min(h), hi.max(h))
+	});
+	let faces = (0..mesh.triangles.len())
+		.map(|t| {
+			let cross = mesh.face_cross(t);
+			let len = cross.length();
+			if len == 0.0 {
+				return FaceSupport::SelfSupporting;
+			}
+			let facing_down = -cross.dot(up) / len;
+			if mesh.triangles[t]
+				.iter()
+				.all(|&v| heights[v as usize] - bed <= tolerance)
+				&& facing_down > 0.0
+			{
+				FaceSupport::Bed
+			} else if facing_down > sin_critical {
+				FaceSupport::Overhang
+			} else {
+				FaceSupport::SelfSupporting
+			}
+		})
+		.collect();
+	Overhangs { faces, bed, top }
+}
+
+/// Triangles of a mesh that need support.
+#[wasm_bindgen]
+pub struct OverhangAnalysis {
+	faces: Vec<FaceSupport>,
+	support_area: f64,
+}
+
+#[wasm_bindgen]
+impl OverhangAnalysis {
+	/// One flag per triangle, in the order of the triangles in `vIndices`: 1
+	/// for overhangs, 2 for triangles lying on the bed, 0 for the rest.
+	pub fn flags(&self) -> Box<[u8]> {
+		self.faces.iter().map(|&f| f as u8).collect()
+	}
+
+	/// Number of overhanging triangles.
+	#[wasm_bindgen(js_name = "overhangCount")]
+	pub fn overhang_count(&self) -> u32 {
+		self.faces.iter().filter(|&&f| f == FaceSupport::Overhang).count() as u32
+	}
+
+	/// Total area of the overhanging triangles.
+	#[wasm_bindgen(js_name = "supportArea")]
+	pub fn support_area(&self) -> f64 {
+		self.support_area
+	}
+}
+
+pub(crate) fn analyze_overhangs_impl(
+	vertices: &[f32],
+	v_indices: &[u32],
+	build_direction: &[f64],
+	critical_angle: f64,
+) -> Result<OverhangAnalysis, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	if build_direction.len() != 3 {
+		return Err(format!(
+			"Build direction must have 3 components, but has {}",
+			build_direction.len()
+		));
+	}
+	let up = Vec3::new(build_direction[0], build_direction[1], build_direction[2]);
+	if up.length() == 0.0 {
+		return Err(String::from("Build direction must be nonzero"));
+	}
+	let (lo, hi) = mesh.bounds();
+	let overhangs = find_overhangs(&mesh, up.normalized(), critical_angle.sin(), 1e-6 * (hi - lo).length());
+	let support_area = (0..mesh.triangles.len())
+		.filter(|&t| overhangs.faces[t] == FaceSupport::Overhang)
+		.map(|t| mesh.face_cross(t).length() / 2.0)
+		.sum();
+	Ok(OverhangAnalysis {
+		faces: overhangs.faces,
+		support_area,
+	})
+}
+
+/// Find the triangles of a mesh from `parseSTLMesh` that need support when
+/// printed along `buildDirection`: those facing down at more than
+/// `criticalAngle` radians from vertical, e.g. pi / 4. Triangles lying on
+/// the bed don't count.
+#[wasm_bindgen(js_name = "analyzeOverhangs")]
+pub fn analyze_overhangs(
+	vertices: &[f32],
+	v_indices: &[u32],
+	build_direction: &[f64],
+	critical_angle: f64,
+) -> Result<OverhangAnalysis, JsValue> {
+	analyze_overhangs_impl(vertices, v_indices, build_direction, critical_angle).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::f64::consts::PI;
+
+	#[test]
+	fn box_with_ledge() {
+		// A 2x2x2 box with a 1x1x1 ledge sticking out of one side, half way up
+		let mut mesh = Mesh::cube(Vec3::new(0.0, 0.0, 0.0), 2.0);
+		mesh.append(&Mesh::cube(Vec3::new(2.0, 0.0, 0.5), 1.0));
+		let (vertices, _, v_indices, _) = mesh.parsed();
+
+		let up = analyze_overhangs_impl(&vertices, &v_indices, &[0.0, 0.0, 2.0], PI / 4.0).unwrap();
+		let flags = up.flags();
+		assert_eq!(flags.len(), 24);
+		assert_eq!(flags.iter().filter(|&&f| f == 2).count(), 2);
+		assert_eq!(up.overhang_count(), 2);
+		assert_eq!(up.support_area(), 1.0);
+
+		// Built along y both parts lie on their sides, and nothing overhangs
+		let side = analyze_overhangs_impl(&vertices, &v_indices, &[0.0, 1.0, 0.0], PI / 4.0).unwrap();
+		assert_eq!(side.flags().iter().filter(|&&f| f == 2).count(), 4);
+		assert_eq!(side.overhang_count(), 0);
+		// Upside down, the top of the box is on the bed and the top of the
+		// ledge hangs over
+		let down = analyze_overhangs_impl(&vertices, &v_indices, &[0.0, 0.0, -1.0], PI / 4.0).unwrap();
+		assert_eq!(down.support_area(), 1.0);
+		// Nothing is steep enough for a critical angle of 90 degrees
+		let none = analyze_overhangs_impl(&vertices, &v_indices, &[0.0, 0.0, 1.0], PI / 2.0).unwrap();
+		assert_eq!(none.overhang_count(), 0);
+		assert!(analyze_overhangs_impl(&vertices, &v_indices, &[0.0, 0.0], PI / 4.0).is_err());
+	}
+}
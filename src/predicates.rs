@@ -0,0 +1,416 @@
+//! Adaptive-precision geometric predicates.
+//!
+//! Each predicate is first evaluated in plain floating point together with an
+//! error bound (Shewchuk, "Adaptive Precision Floating-Point Arithmetic and
+//! Fast Robust Geometric Predicates"). Only when the result is too close to
+//! zero to trust is it recomputed exactly with floating-point expansions, so
+//! the sign returned is always correct for the given inputs.
+
+use crate::vec3::Vec3;
+
+const EPSILON: f64 = f64::EPSILON * 0.5;
+const O2D_BOUND: f64 = (3.0 + 16.0 * EPSILON) * EPSILON;
+const O3D_BOUND: f64 = (7.0 + 56.0 * EPSILON) * EPSILON;
+
+/// Positive if d lies below the plane through a, b and c, where "above" is the
+/// side from which a, b, c appear in counter-clockwise order. Zero if the four
+/// points are coplanar.
+pub(crate) fn orient3d(a: Vec3, b: Vec3, c: Vec3, d: Vec3) -> f64 {
+	let adx = a.x - d.x;
+	let bdx = b.x - d.x;
+	let cdx = c.x - d.x;
+	let ady = a.y - d.y;
+	let bdy = b.y - d.y;
+	let cdy = c.y - d.y;
+	let adz = a.z - d.z;
+	let bdz = b.z - d.z;
+	let cdz = c.z - d.z;
+
+	let bdxcdy = bdx * cdy;
+	let cdxbdy = cdx * bdy;
+	let cdxady = cdx * ady;
+	let adxcdy = adx * cdy;
+	let adxbdy = adx * bdy;
+	let bdxady = bdx * ady;
+
+	let det = adz * (bdxcdy - cdxbdy) + bdz * (cdxady - adxcdy) + cdz * (adxbdy - bdxady);
+	let permanent = (bdxcdy.abs() + cdxbdy.abs()) * adz.abs()
+		+ (cdxady.abs() + adxcdy.abs()) * bdz.abs()
+		+ (adxbdy.abs() + bdxady.abs()) * cdz.abs();
+	if det.abs() > O3D_BOUND * permanent {
+		return det;
+	}
+	orient3d_exact(a, b, c, d)
+}
+
+/// Positive if (ax, ay), (bx, by), (cx, cy) are in counter-clockwise order,
+/// negative if clockwise and zero if collinear.
+pub(crate) fn orient2d(ax: f64, ay: f64, bx: f64, by: f64, cx: f64, cy: f64) -> f64 {
+	let left = (ax - cx) * (by - cy);
+	let right = (ay - cy) * (bx - cx);
+	let det = left - right;
+	if det.abs() > O2D_BOUND * (left.abs() + right.abs()) {
+		return det;
+	}
+
+	let acx = two_diff(ax, cx);
+	let bcx = two_diff(bx, cx);
+	let acy = two_diff(ay, cy);
+	let bcy = two_diff(by, cy);
+	let mut left = [0.0; 8];
+	let mut right = [0.0; 8];
+	let mut det = [0.0; 16];
+	let l = mul(&acx, &bcy, &mut left);
+	let r = mul(&acy, &bcx, &mut right);
+	let n = diff(&left[..l], &right[..r], &mut det);
+	sign_of(&det[..n])
+}
+
+fn orient3d_exact(a: Vec3, b: Vec3, c: Vec3, d: Vec3) -> f64 {
+	let adx = two_diff(a.x, d.x);
+	let bdx = two_diff(b.x, d.x);
+	let cdx = two_diff(c.x, d.x);
+	let ady = two_diff(a.y, d.y);
+	let bdy = two_diff(b.y, d.y);
+	let cdy = two_diff(c.y, d.y);
+	let adz = two_diff(a.z, d.z);
+	let bdz = two_diff(b.z, d.z);
+	let cdz = two_diff(c.z, d.z);
+
+	let minor = |x1: &[f64; 2], y1: &[f64; 2], x2: &[f64; 2], y2: &[f64; 2], out: &mut [f64; 16]| -> usize {
+		let mut left = [0.0; 8];
+		let mut right = [0.0; 8];
+		let l = mul(x1, y1, &mut left);
+		let r = mul(x2, y2, &mut right);
+		diff(&left[..l], &right[..r], out)
+	};
+	let mut bc = [0.0; 16];
+	let mut ca = [0.0; 16];
+	let mut ab = [0.0; 16];
+	let nbc = minor(&bdx, &cdy, &cdx, &bdy, &mut bc);
+	let nca = minor(&cdx, &ady, &adx, &cdy, &mut ca);
+	let nab = minor(&adx, &bdy, &bdx, &ady, &mut ab);
+
+	let mut adet = [0.0; 64];
+	let mut bdet = [0.0; 64];
+	let mut cdet = [0.0; 64];
+	let na = mul(&bc[..nbc], &adz, &mut adet);
+	let nb = mul(&ca[..nca], &bdz, &mut bdet);
+	let nc = mul(&ab[..nab], &cdz, &mut cdet);
+
+	let mut abdet = [0.0; 128];
+	let mut det = [0.0; 192];
+	let nab = expansion_sum(&adet[..na], &bdet[..nb], &mut abdet);
+	let n = expansion_sum(&abdet[..nab], &cdet[..nc], &mut det);
+	sign_of(&det[..n])
+}
+
+// Expansions are lists of non-overlapping doubles in increasing order of
+// magnitude whose exact sum is the represented value. The functions below
+// write their result into `h` and return its length.
+
+fn fast_two_sum(a: f64, b: f64) -> (f64, f64) {
+	let x = a + b;
+	(x, b - (x - a))
+}
+
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+	let x = a + b;
+	let bv = x - a;
+	let av = x - bv;
+	(x, (a - av) + (b - bv))
+}
+
+fn two_diff(a: f64, b: f64) -> [f64; 2] {
+	let x = a - b;
+	let bv = a - x;
+	let av = x + bv;
+	[(a - av) + (bv - b), x]
+}
+
+fn two_product(a: f64, b: f64) -> (f64, f64) {
+	let x = a * b;
+	(x, a.mul_add(b, -x))
+}
+
+fn expansion_sum(e: &[f64], f: &[f64], h: &mut [f64]) -> usize {
+	let mut ei = 0;
+	let mut fi = 0;
+	let mut hi = 0;
+	// Merge by increasing magnitude, carrying the running sum in q
+	let next = |ei: &mut usize, fi: &mut usize| -> f64 {
+		if *fi >= f.len() || (*ei < e.len() && e[*ei].abs() < f[*fi].abs()) {
+			*ei += 1;
+			e[*ei - 1]
+		} else {
+			*fi += 1;
+			f[*fi - 1]
+		}
+	};
+	let mut q = next(&mut ei, &mut fi);
+	let mut first = true;
+	while ei < e.len() || fi < f.len() {
+		let x = next(&mut ei, &mut fi);
+		let (sum, err) = if first { fast_two_sum(x, q) } else { two_sum(q, x) };
+		first = false;
+		q = sum;
+		if err != 0.0 {
+			h[hi] = err;
+			hi += 1;
+		}
+	}
+	if q != 0.0 || hi == 0 {
+		h[hi] = q;
+		hi += 1;
+	}
+	hi
+}
+
+fn diff(e: &[f64], f: &[f64], h: &mut [f64]) -> usize {
+	let mut neg = [0.0; 16];
+	for (n, x) in neg.iter_mut().zip(f) {
+		*n = -x;
+	}
+	expansion_sum(e, &neg[..f.len()], h)
+}
+
+fn scale_expansion(e: &[f64], b: f64, h: &mut [f64]) -> usize {
+	let mut hi = 0;
+	let (mut q, err) = two_product(e[0], b);
+	if err != 0.0 {
+		h[hi] = err;
+		hi += 1;
+	}
+	for &x in &e[1..] {
+		let (p1, p0) = two_product(x, b);
+		let (sum, err) = two_sum(q, p0);
+		if err != 0.0 {
+			h[hi] = err;
+			hi += 1;
+		}
+		let (sum, err) = fast_two_sum(p1, sum);
+		q = sum;
+		if err != 0.0 {
+			h[hi] = err;
+			hi += 1;
+		}
+	}
+	if q != 0.0 || hi == 0 {
+		h[hi] = q;
+		hi += 1;
+	}
+	hi
+}
+
+/// Product of an expansion with a two-component expansion.
+fn mul(e: &[f64], f: &[f64; 2], h: &mut [f64]) -> usize {
+	let mut lo = [0.0; 32];
+	let mut hi = [0.0; 32];
+	let nl = scale_expansion(e, f[0], &mut lo);
+	let nh = scale_expansion(e, f[1], &mut hi);
+	expansion_sum(&lo[..nl], &hi[..nh], h)
+}
+
+fn sign_of(e: &[f64]) -> f64 {
+	match e.iter().rev().find(|x| **x != 0.0) {
+		Some(&x) => x,
+		None => 0.0,
+	}
+}
+
+/// A real number held exactly as an expansion, for the few constructions whose
+/// signs have to come out right however close they are to zero. Much slower
+/// than the predicates above, which should be used where they suffice.
+#[derive(Clone, Debug)]
+pub(crate) struct Exact(Vec<f64>);
+
+impl Exact {
+	pub(crate) fn new(x: f64) -> Exact {
+		Exact(vec![x])
+	}
+
+	/// a - b, exactly.
+	pub(crate) fn diff(a: f64, b: f64) -> Exact {
+		Exact(two_diff(a, b).to_vec())
+	}
+
+	/// Positive, negative or zero like the number.
+	pub(crate) fn sign(&self) -> f64 {
+		sign_of(&self.0)
+	}
+
+	/// The number rounded to about double precision.
+	pub(crate) fn approx(&self) -> f64 {
+		self.0.iter().sum()
+	}
+
+	/// Drops zero and overlapping components (Shewchuk's Compress), to keep
+	/// long chains of products from growing out of hand.
+	fn compressed(e: Vec<f64>) -> Exact {
+		let m = e.len();
+		let mut g = vec![0.0; m];
+		let mut bottom = m - 1;
+		let mut q = e[m - 1];
+		for &x in e[..m - 1].iter().rev() {
+			let (sum, err) = fast_two_sum(q, x);
+			if err != 0.0 {
+				g[bottom] = sum;
+				bottom -= 1;
+				q = err;
+			} else {
+				q = sum;
+			}
+		}
+		g[bottom] = q;
+		let mut h = Vec::with_capacity(m - bottom);
+		for &x in &g[bottom + 1..] {
+			let (sum, err) = fast_two_sum(x, q);
+			if err != 0.0 {
+				h.push(err);
+			}
+			q = sum;
+		}
+		if q != 0.0 || h.is_empty() {
+			h.push(q);
+		}
+		Exact(h)
+	}
+}
+
+impl std::ops::Add for &Exact {
+	type Output = Exact;
+
+	fn add(self, other: &Exact) -> Exact {
+		let mut h = vec![0.0; self.0.len() + other.0.len()];
+		let n = expansion_sum(&self.0, &other.0, &mut h);
+		h.truncate(n);
+		Exact::compressed(h)
+	}
+}
+
+impl std::ops::Neg for &Exact {
+	type Output = Exact;
+
+	fn neg(self) -> Exact {
+		Exact(self.0.iter().map(|x| -x).collect())
+	}
+}
+
+impl std::ops::Sub for &Exact {
+	type Output = Exact;
+
+	fn sub(self, other: &Exact) -> Exact {
+		self + &-other
+	}
+}
+
+impl std::ops::Mul for &Exact {
+	type Output = Exact;
+
+	fn mul(self, other: &Exact) -> Exact {
+		let mut product = Exact::new(0.0);
+		let mut h = vec![0.0; 2 * self.0.len()];
+		for &b in &other.0 {
+			let n = scale_expansion(&self.0, b, &mut h);
+			product = &product + &Exact(h[..n].to_vec());
+		}
+		product
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn near_degenerate() {
+		// Points on the plane z = x, perturbed by less than the rounding error
+		// of the naive determinant.
+		let a = Vec3::new(0.1, 0.2, 0.1);
+		let b = Vec3::new(1e10 + 0.3, 0.7, 1e10 + 0.3);
+		let c = Vec3::new(0.5, 1e10 + 0.1, 0.5);
+		assert_eq!(orient3d(a, b, c, Vec3::new(7.0, 3.0, 7.0)), 0.0);
+		let above = Vec3::new(7.0, 3.0, 7.0 + 7.0 * f64::EPSILON);
+		let below = Vec3::new(7.0, 3.0, 7.0 - 7.0 * f64::EPSILON);
+		assert!(orient3d(a, b, c, above) * orient3d(a, b, c, below) < 0.0);
+
+		assert_eq!(orient2d(0.5, 0.5, 12.0, 12.0, 24.0, 24.0), 0.0);
+		assert!(orient2d(0.5, 0.5 + f64::EPSILON, 12.0, 12.0, 24.0, 24.0) > 0.0);
+		assert!(orient2d(0.0, 0.0, 1.0, 0.0, 0.0, 1.0) > 0.0);
+		assert!(
+			orient3d(
+				Vec3::new(0.0, 0.0, 0.0),
+				Vec3::new(1.0, 0.0, 0.0),
+				Vec3::new(0.0, 1.0, 0.0),
+				Vec3::new(0.0, 0.0, -1.0)
+			) > 0.0
+		);
+	}
+
+	#[test]
+	fn matches_integer_arithmetic() {
+		let mut seed = 12345u64;
+		let mut next = |range: i64| -> i64 {
+			seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+			((seed >> 33) as i64 % (2 * range + 1)) - range
+		};
+		for _ in 0..2000 {
+			let big = 1 << 34;
+			let a = [next(big), next(big), next(big)];
+			let u = [next(1 << 10), next(1 << 10), next(1 << 10)];
+			let v = [next(1 << 10), next(1 << 10), next(1 << 10)];
+			let b = [a[0] + u[0] * 4096, a[1] + u[1] * 4096, a[2] + u[2] * 4096];
+			let c = [a[0] + v[0] * 4096, a[1] + v[1] * 4096, a[2] + v[2] * 4096];
+			// d is nearly on the plane through a, b and c
+			let (s, t) = (next(1 << 8), next(1 << 8));
+			let d = [
+				a[0] + (b[0] - a[0]) * s + (c[0] - a[0]) * t + next(1),
+				a[1] + (b[1] - a[1]) * s + (c[1] - a[1]) * t + next(1),
+				a[2] + (b[2] - a[2]) * s + (c[2] - a[2]) * t + next(1),
+			];
+
+			let sub = |p: [i64; 3]| [(p[0] - d[0]) as i128, (p[1] - d[1]) as i128, (p[2] - d[2]) as i128];
+			let (ad, bd, cd) = (sub(a), sub(b), sub(c));
+			let exact = ad[2] * (bd[0] * cd[1] - cd[0] * bd[1])
+				+ bd[2] * (cd[0] * ad[1] - ad[0] * cd[1])
+				+ cd[2] * (ad[0] * bd[1] - bd[0] * ad[1]);
+
+			let f = |p: [i64; 3]| Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64);
+			let res = orient3d(f(a), f(b), f(c), f(d));
+			assert_eq!(
+				res.partial_cmp(&0.0),
+				exact.partial_cmp(&0),
+				"{:?} {:?} {:?} {:?}",
+				a,
+				b,
+				c,
+				d
+			);
+
+			let exact2 = (ad[0] * bd[1]) - (ad[1] * bd[0]);
+			let res2 = orient2d(
+				a[0] as f64,
+				a[1] as f64,
+				b[0] as f64,
+				b[1] as f64,
+				d[0] as f64,
+				d[1] as f64,
+			);
+			assert_eq!(res2.partial_cmp(&0.0), exact2.partial_cmp(&0));
+		}
+	}
+
+	#[test]
+	fn exact_arithmetic() {
+		// (2^60 + 1)^2 - 2^120 - 2^61 = 1, which doubles round to 0
+		let big = Exact::new(2f64.powi(60));
+		let x = &big + &Exact::new(1.0);
+		let rest = &(&x * &x) - &(&(&big * &big) + &(&Exact::new(2.0) * &big));
+		assert_eq!(rest.sign(), 1.0);
+		assert_eq!(rest.approx(), 1.0);
+		assert_eq!((&rest - &Exact::new(1.0)).sign(), 0.0);
+		assert_eq!((-&rest).sign(), -1.0);
+		assert_eq!(Exact::diff(1.0, 1e-30).approx(), 1.0);
+		assert!((&Exact::diff(1.0, 1e-30) - &Exact::new(1.0)).sign() < 0.0);
+	}
+}
@@ -0,0 +1,285 @@
+use std::collections::{BinaryHeap, HashMap};
+
+use wasm_bindgen::prelude::*;
+
+use crate::mesh::{Mesh, Rng, SurfacePoint};
+use crate::vec3::Vec3;
+
+/// Most points `sample_surface` places. Poisson disk sampling starts from five
+/// times as many.
+const MAX_SAMPLES: u32 = 1 << 20;
+
+/// How points are spread over the surface.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleDistribution {
+	/// Independent uniformly random points. Fast, but clumps and gaps are
+	/// common.
+	Random = 0,
+	/// Points no closer to each other than needed, for an even spread.
+	PoissonDisk = 1,
+}
+
+/// Points on the surface of a mesh.
+#[wasm_bindgen]
+pub struct SurfaceSamples {
+	points: Vec<f32>,
+	normals: Vec<f32>,
+	triangle_ids: Vec<u32>,
+}
+
+#[wasm_bindgen]
+impl SurfaceSamples {
+	/// Positions, 3 values per point.
+	pub fn points(&self) -> Box<[f32]> {
+		self.points.clone().into_boxed_slice()
+	}
+
+	/// Unit normals interpolated from the vertex normals, 3 values per point.
+	pub fn normals(&self) -> Box<[f32]> {
+		self.normals.clone().into_boxed_slice()
+	}
+
+	/// Index of the triangle each point is on.
+	#[wasm_bindgen(js_name = "triangleIds")]
+	pub fn triangle_ids(&self) -> Box<[u32]> {
+		self.triangle_ids.clone().into_boxed_slice()
+	}
+
+	/// Number of points.
+	pub fn count(&self) -> u32 {
+		self.triangle_ids.len() as u32
+	}
+}
+
+/// Weighted sample elimination after Yuksel, "Sample Elimination for
+/// Generating Poisson Disk Sample Sets": starting from several times as many
+/// random points, the point most crowded by its neighbours is removed until
+/// `count` are left. Unlike dart throwing it always gives exactly `count`
+/// points.
+fn poisson_disk_samples(mesh: &Mesh, area: f64, count: usize, rng: &mut Rng) -> Vec<SurfacePoint> {
+	let candidates = mesh.random_surface_points(5 * count, rng);
+	if count == 0 {
+		return candidates;
+	}
+	let points: Vec<Vec3> = candidates.iter().map(|s| mesh.surface_position(s)).collect();
+	// Twice the radius of the densest packing of `count` disks over the area
+	let reach = 2.0 * (area / (2.0 * 3f64.sqrt() * count as f64)).sqrt();
+	let cell = |p: Vec3| {
+		[
+			(p.x / reach).floor() as i64,
+			(p.y / reach).floor() as i64,
+			(p.z / reach).floor() as i64,
+		]
+	};
+	let mut grid = HashMap::<[i64; 3], Vec<u32>>::new();
+	for (i, &p) in points.iter().enumerate() {
+		grid.entry(cell(p)).or_default().push(i as u32);
+	}
+
+	let weight = |i: usize, j: usize| {
+		let d = (points[i] - points[j]).length();
+		(1.0 - d / reach).powi(8)
+	};
+	let mut neighbours = vec![Vec::new(); points.len()];
+	for (i, &p) in points.iter().enumerate() {
+		let [x, y, z] = cell(p);
+		for dz in -1..=1 {
+			for dy in -1..=1 {
+				for dx in -1..=1 {
+					for &j in grid.get(&[x + dx, y + dy, z + dz]).map_or(&[][..], |v| v.as_slice()) {
+						if j as usize != i && (points[j as usize] - p).length() < reach {
+							neighbours[i].push(j);
+						}
+					}
+				}
+			}
+		}
+	}
+	let mut weights: Vec<f64> = (0..points.len())
+		.map(|i| neighbours[i].iter().map(|&j| weight(i, j as usize)).sum())
+		.collect();
+
+	// Weights are non-negative, so their bits order like them. Stale entries
+	// are skipped when their weight no longer matches.
+	let mut heap: BinaryHeap<(u64, u32)> = weights
+		.iter()
+		.enumerate()
+		.map(|(i, w)| (w.to_bits(), i as u32))
+		.collect();
+	let mut removed = vec![false; points.len()];
+	let mut left = points.len();
+	while left > count {
+		let Some((bits, i)) = heap.pop() else {
+			break;
+		};
+		let i = i as usize;
+		if removed[i] || bits != weights[i].to_bits() {
+			continue;
+		}
+		removed[i] = true;
+		left -= 1;
+		for &j in &neighbours[i] {
+			let j = j as usize;
+			if !removed[j] {
+				weights[j] = (weights[j] - weight(i, j)).max(0.0);
+				heap.push((weights[j].to_bits(), j as u32));
+			}
+		}
+	}
+	candidates
+		.into_iter()
+		.zip(removed)
+		.filter(|&(_, r)| !r)
+		.map(|(s, _)| s)
+		.collect()
+}
+
+pub(crate) fn sample_surface(
+	mesh: &Mesh,
+	normals: &[Vec3],
+	count: usize,
+	distribution: SampleDistribution,
+	seed: u32,
+) -> Result<SurfaceSamples, String> {
+	let area: f64 = (0..mesh.triangles.len())
+		.map(|t| mesh.face_cross(t).length() / 2.0)
+		.sum();
+	if area <= 0.0 || !area.is_finite() {
+		return Err(String::from("Mesh has no area to sample"));
+	}
+	let mut rng = Rng(seed as u64);
+	let samples = match distribution {
+		SampleDistribution::Random => mesh.random_surface_points(count, &mut rng),
+		SampleDistribution::PoissonDisk => poisson_disk_samples(mesh, area, count, &mut rng),
+	};
+
+	let mut result = SurfaceSamples {
+		points: Vec::with_capacity(samples.len() * 3),
+		normals: Vec::with_capacity(samples.len() * 3),
+		triangle_ids: Vec::with_capacity(samples.len()),
+	};
+	for sample in &samples {
+		let t = sample.triangle as usize;
+		let p = mesh.surface_position(sample);
+		let tri = mesh.triangles[t];
+		let mut n = (0..3)
+			.fold(Vec3::ZERO, |n, k| n + normals[tri[k] as usize] * sample.weights[k])
+			.normalized();
+		if n == Vec3::ZERO {
+			n = mesh.face_normal(t);
+		}
+		result.points.extend_from_slice(&[p.x as f32, p.y as f32, p.z as f32]);
+		result.normals.extend_from_slice(&[n.x as f32, n.y as f32, n.z as f32]);
+		result.triangle_ids.push(sample.triangle);
+	}
+	Ok(result)
+}
+
+pub(crate) fn sample_surface_impl(
+	vertices: &[f32],
+	normals: &[f32],
+	v_indices: &[u32],
+	count: u32,
+	distribution: SampleDistribution,
+	seed: u32,
+) -> Result<SurfaceSamples, String> {
+	if count > MAX_SAMPLES {
+		return Err(format!(
+			"Can sample at most {} points, but {} were asked for",
+			MAX_SAMPLES, count
+		));
+	}
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	let n = mesh.positions.len();
+	let normals = if normals.is_empty() {
+		mesh.vertex_normals()
+	} else if normals.len() < n * 3 {
+		return Err(format!(
+			"Normal buffer of length {} is too short for {} vertices",
+			normals.len(),
+			n
+		));
+	} else {
+		(0..n).map(|i| Vec3::from_f32(normals, i)).collect()
+	};
+	sample_surface(&mesh, &normals, count as usize, distribution, seed)
+}
+
+/// Sample `count` points on the surface of a mesh from `parseSTLMesh`, e.g.
+/// for point cloud previews or comparing meshes. Points are spread in
+/// proportion to area, either independently at random or evenly as a Poisson
+/// disk set. The same seed always gives the same points. `normals` are
+/// interpolated at the points; pass an empty array to average them from the
+/// faces.
+#[wasm_bindgen(js_name = "sampleSurface")]
+pub fn sample_surface_export(
+	vertices: &[f32],
+	normals: &[f32],
+	v_indices: &[u32],
+	count: u32,
+	distribution: SampleDistribution,
+	seed: u32,
+) -> Result<SurfaceSamples, JsValue> {
+	sample_surface_impl(vertices, normals, v_indices, count, distribution, seed).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Smallest distance between any two points.
+	fn spacing(points: &[f32]) -> f64 {
+		let n = points.len() / 3;
+		let mut min = f64::INFINITY;
+		for i in 0..n {
+			for j in i + 1..n {
+				min = min.min((Vec3::from_f32(points, i) - Vec3::from_f32(points, j)).length());
+			}
+		}
+		min
+	}
+
+	#[test]
+	fn cube() {
+		let mesh = Mesh::tessellated_cube(Vec3::ZERO, 1.0, 3);
+		let (vertices, normals, v_indices, _) = mesh.parsed();
+		let parsed = Mesh::from_buffers(&vertices, &v_indices).unwrap();
+
+		let random = sample_surface_impl(&vertices, &[], &v_indices, 600, SampleDistribution::Random, 7).unwrap();
+		assert_eq!(random.count(), 600);
+		let (points, ids) = (random.points(), random.triangle_ids());
+		let mut per_side = [0; 6];
+		for i in 0..600 {
+			let p = Vec3::from_f32(&points, i);
+			let face = parsed.face_normal(ids[i] as usize);
+			// On the plane of its triangle
+			assert!((p - parsed.corners(ids[i] as usize)[0]).dot(face).abs() < 1e-6);
+			let axis = (0..3).find(|&k| face[k] != 0.0).unwrap();
+			per_side[axis * 2 + (face[axis] > 0.0) as usize] += 1;
+			assert!(Vec3::from_f32(&random.normals(), i).length() > 0.999);
+		}
+		// 100 expected on each side
+		assert!(per_side.iter().all(|&c| c > 60 && c < 140), "{:?}", per_side);
+
+		// Deterministic for a seed
+		let again = sample_surface_impl(&vertices, &normals, &v_indices, 600, SampleDistribution::Random, 7).unwrap();
+		assert_eq!(again.points(), points);
+		let other = sample_surface_impl(&vertices, &normals, &v_indices, 600, SampleDistribution::Random, 8).unwrap();
+		assert_ne!(other.points(), points);
+
+		let even = sample_surface_impl(&vertices, &normals, &v_indices, 600, SampleDistribution::PoissonDisk, 7).unwrap();
+		assert_eq!(even.count(), 600);
+		assert!(spacing(&even.points()) > 4.0 * spacing(&points));
+		assert!(sample_surface_impl(&vertices, &normals, &v_indices[..0], 10, SampleDistribution::Random, 7).is_err());
+		assert!(sample_surface_impl(
+			&vertices,
+			&normals,
+			&v_indices,
+			u32::MAX,
+			SampleDistribution::PoissonDisk,
+			7
+		)
+		.is_err());
+	}
+}
@@ -0,0 +1,311 @@
+use std::f64::consts::PI;
+
+use wasm_bindgen::prelude::*;
+
+use crate::bvh::{Aabb, Bvh};
+use crate::isosurface::{contour, ScalarGrid};
+use crate::mesh::{Mesh, MeshBuffers};
+use crate::mesh_bvh::MeshBvh;
+use crate::vec3::Vec3;
+
+/// Largest grid `distance_field` samples. Each sample is a closest point and
+/// a winding number query, so this is lower than for voxels.
+const MAX_SAMPLES: u64 = 1 << 24;
+
+/// How many times farther than its radius a cluster of triangles must be for
+/// its dipole to stand in for them, as in Barill et al.
+const ACCURACY_SCALE: f64 = 2.0;
+
+/// The first order far field of a cluster of triangles.
+#[derive(Clone, Copy)]
+struct Dipole {
+	/// Area-weighted centroid
+	center: Vec3,
+	/// Sum of the area vectors, pointing out of the surface
+	area: Vec3,
+	/// Total unsigned area
+	weight: f64,
+	/// Distance from the center to the farthest corner of the cluster's box
+	radius: f64,
+}
+
+/// Generalized winding numbers (Jacobson et al., "Robust Inside-Outside
+/// Segmentation using Generalized Winding Numbers"), approximated far from
+/// the surface by a dipole per BVH node (Barill et al., "Fast Winding Numbers
+/// for Soups and Clouds"). Close to 1 inside a closed mesh and 0 outside, and
+/// still a sensible guess where the mesh has holes or overlapping parts.
+pub(crate) struct WindingNumbers<'a> {
+	mesh: &'a Mesh,
+	bvh: Bvh,
+	dipoles: Vec<Dipole>,
+}
+
+impl<'a> WindingNumbers<'a> {
+	pub(crate) fn new(mesh: &'a Mesh) -> WindingNumbers<'a> {
+		let boxes: Vec<Aabb> = (0..mesh.triangles.len()).map(|t| Aabb::of_triangle(mesh, t)).collect();
+		let bvh = Bvh::build(&boxes);
+		let empty = Dipole {
+			center: Vec3::ZERO,
+			area: Vec3::ZERO,
+			weight: 0.0,
+			radius: 0.0,
+		};
+		let mut dipoles = vec![empty; bvh.nodes.len()];
+		// Children come after their parent
+		for node in (0..bvh.nodes.len()).rev() {
+			let bounds = bvh.nodes[node].bounds;
+			let parts: Vec<Dipole> = if bvh.nodes[node].is_leaf() {
+				bvh.leaf_items(node)
+					.iter()
+					.map(|&t| {
+						let [a, b, c] = mesh.corners(t as usize);
+						let area = mesh.face_cross(t as usize) * 0.5;
+						Dipole {
+							center: (a + b + c) / 3.0,
+							area,
+							weight: area.length(),
+							radius: 0.0,
+						}
+					})
+					.collect()
+			} else {
+				let (l, r) = bvh.children(node);
+				vec![dipoles[l], dipoles[r]]
+			};
+			let weight: f64 = parts.iter().map(|d| d.weight).sum();
+			let center = if weight > 0.0 {
+				parts.iter().fold(Vec3::ZERO, |c, d| c + d.center * d.weight) / weight
+			} else {
+				bounds.center()
+			};
+			let far = (bounds.max - center).max(center - bounds.min);
+			dipoles[node] = Dipole {
+				center,
+				area: parts.iter().fold(Vec3::ZERO, |a, d| a + d.area),
+				weight,
+				radius: far.length(),
+			};
+		}
+		WindingNumbers { mesh, bvh, dipoles }
+	}
+
+	pub(crate) fn at(&self, q: Vec3) -> f64 {
+		if self.bvh.nodes.is_empty() {
+			return 0.0;
+		}
+		let mut solid_angle = 0.0;
+		let mut stack = vec![0usize];
+		while let Some(node) = stack.pop() {
+			let dipole = &self.dipoles[node];
+			let r = dipole.center - q;
+			let distance = r.length();
+			if distance > ACCURACY_SCALE * dipole.radius {
+				solid_angle += dipole.area.dot(r) / distance.powi(3);
+			} else if self.bvh.nodes[node].is_leaf() {
+				for &t in self.bvh.leaf_items(node) {
+					solid_angle += triangle_solid_angle(self.mesh.corners(t as usize).map(|p| p - q));
+				}
+			} else {
+				let (l, r) = self.bvh.children(node);
+				stack.extend_from_slice(&[l, r]);
+			}
+		}
+		solid_angle / (4.0 * PI)
+	}
+}
+
+/// Signed solid angle of a triangle seen from the origin (Van Oosterom and
+/// Strackee), positive from behind its front face.
+fn triangle_solid_angle([a, b, c]: [Vec3; 3]) -> f64 {
+	let (la, lb, lc) = (a.length(), b.length(), c.length());
+	let det = a.dot(b.cross(c));
+	let div = la * lb * lc + a.dot(b) * lc + b.dot(c) * la + c.dot(a) * lb;
+	2.0 * det.atan2(div)
+}
+
+/// Signed distance to the surface of a mesh, sampled on a regular grid.
+#[wasm_bindgen]
+pub struct DistanceField {
+	grid: ScalarGrid,
+}
+
+#[wasm_bindgen]
+impl DistanceField {
+	/// Number of samples along x, y and z.
+	pub fn dimensions(&self) -> Box<[u32]> {
+		Box::new(self.grid.dims.map(|d| d as u32))
+	}
+
+	/// Position of the first sample, at the minimum corner of the grid.
+	pub fn origin(&self) -> Box<[f64]> {
+		Box::new([self.grid.origin.x, self.grid.origin.y, self.grid.origin.z])
+	}
+
+	/// Distance between neighbouring samples.
+	pub fn spacing(&self) -> f64 {
+		self.grid.spacing
+	}
+
+	/// Signed distance at each sample, negative inside. The sample at
+	/// (x, y, z) is at index x + nx * (y + ny * z).
+	pub fn values(&self) -> Box<[f32]> {
+		self.grid.values.clone().into_boxed_slice()
+	}
+
+	/// Signed distance at `point` [x, y, z], interpolated between samples. NaN
+	/// outside the grid.
+	#[wasm_bindgen(js_name = "distanceAt")]
+	pub fn distance_at(&self, point: &[f64]) -> f64 {
+		if point.len() < 3 {
+			return f64::NAN;
+		}
+		self
+			.grid
+			.interpolate(Vec3::new(point[0], point[1], point[2]))
+			.unwrap_or(f64::NAN)
+	}
+
+	/// The surface at signed distance `offset` from the mesh, grown for
+	/// positive offsets and shrunk for negative ones, in the layout
+	/// `parseSTLMesh` produces. Details smaller than the spacing are lost.
+	#[wasm_bindgen(js_name = "toMesh")]
+	pub fn to_mesh(&self, offset: f64) -> MeshBuffers {
+		contour(&self.grid, offset)
+	}
+}
+
+/// Samples the signed distance to `mesh` on a grid of the given spacing
+/// covering its bounds and at least `padding` beyond them. Points with a
+/// winding number of a half or more are inside. Distances are clamped to
+/// `max_distance`, which saves searching for the closest point far from the
+/// surface when only a band around it is needed.
+pub(crate) fn distance_field(mesh: &Mesh, spacing: f64, padding: f64, max_distance: f64) -> Result<ScalarGrid, String> {
+	if !spacing.is_finite() || spacing <= 0.0 {
+		return Err(format!("Spacing must be positive, but is {}", spacing));
+	}
+	if !padding.is_finite() || padding < 0.0 {
+		return Err(format!("Padding must not be negative, but is {}", padding));
+	}
+	// A sample to spare keeps the surface off the edge of the grid
+	let padding = padding.max(spacing);
+	let (min, max) = mesh.bounds();
+	let extent = max - min;
+	let dims = [0, 1, 2].map(|i| ((extent[i] + 2.0 * padding) / spacing).ceil() + 1.0);
+	let count = dims.iter().product::<f64>();
+	if count > MAX_SAMPLES as f64 {
+		return Err(format!(
+			"A spacing of {} needs {} samples, more than the {} allowed",
+			spacing, count, MAX_SAMPLES
+		));
+	}
+	let dims = dims.map(|d| d as usize);
+	// Center the grid on the mesh
+	let span = Vec3::new(dims[0] as f64 - 1.0, dims[1] as f64 - 1.0, dims[2] as f64 - 1.0) * spacing;
+	let mut grid = ScalarGrid {
+		dims,
+		origin: (min + max - span) * 0.5,
+		spacing,
+		values: Vec::with_capacity(count as usize),
+	};
+
+	let bvh = MeshBvh::new(mesh.clone(), Vec::new());
+	let winding = WindingNumbers::new(mesh);
+	for z in 0..dims[2] {
+		for y in 0..dims[1] {
+			for x in 0..dims[0] {
+				let p = grid.point([x, y, z]);
+				let distance = bvh.closest(p, max_distance).map_or(max_distance, |hit| hit.distance());
+				let value = if winding.at(p) >= 0.5 { -distance } else { distance };
+				grid.values.push(value as f32);
+			}
+		}
+	}
+	Ok(grid)
+}
+
+pub(crate) fn signed_distance_field_impl(
+	vertices: &[f32],
+	v_indices: &[u32],
+	spacing: f64,
+	padding: f64,
+) -> Result<DistanceField, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	if mesh.triangles.is_empty() {
+		return Err(String::from("Mesh has no triangles"));
+	}
+	Ok(DistanceField {
+		grid: distance_field(&mesh, spacing, padding, f64::INFINITY)?,
+	})
+}
+
+/// Sample the signed distance to a mesh from `parseSTLMesh` on a grid with
+/// `spacing` between samples, extending at least `padding` beyond the mesh,
+/// e.g. to make room for offsetting it outwards. Distances are negative
+/// inside, which is decided by the generalized winding number, so small holes
+/// and overlapping parts do no harm.
+#[wasm_bindgen(js_name = "signedDistanceField")]
+pub fn signed_distance_field(
+	vertices: &[f32],
+	v_indices: &[u32],
+	spacing: f64,
+	padding: f64,
+) -> Result<DistanceField, JsValue> {
+	signed_distance_field_impl(vertices, v_indices, spacing, padding).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn winding_numbers() {
+		let mut mesh = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 1.0, 8);
+		let winding = WindingNumbers::new(&mesh);
+		// The dipoles are a first order approximation, good to a few percent
+		assert!((winding.at(Vec3::new(0.5, 0.5, 0.5)) - 1.0).abs() < 0.02);
+		assert!((winding.at(Vec3::new(0.9, 0.1, 0.5)) - 1.0).abs() < 0.02);
+		assert!(winding.at(Vec3::new(1.1, 0.5, 0.5)).abs() < 0.02);
+		assert!(winding.at(Vec3::new(30.0, -20.0, 10.0)).abs() < 1e-6);
+
+		// Without the top face, the center sees 5 of 6 faces
+		let faces = mesh.triangles.len();
+		mesh.triangles = (0..faces)
+			.filter(|&t| mesh.face_normal(t).z < 0.5)
+			.map(|t| mesh.triangles[t])
+			.collect();
+		assert_eq!(mesh.triangles.len(), faces * 5 / 6);
+		let open = WindingNumbers::new(&mesh);
+		assert!((open.at(Vec3::new(0.5, 0.5, 0.5)) - 5.0 / 6.0).abs() < 0.02);
+	}
+
+	#[test]
+	fn sphere() {
+		let mut mesh = Mesh::tessellated_cube(Vec3::new(-1.0, -1.0, -1.0), 2.0, 12);
+		mesh.positions.iter_mut().for_each(|p| *p = p.normalized());
+		let (vertices, _, v_indices, _) = mesh.parsed();
+		let field = signed_distance_field_impl(&vertices, &v_indices, 0.1, 0.3).unwrap();
+		let [nx, ny, nz] = field.grid.dims;
+		assert!(field.origin().iter().all(|&o| o <= -1.3));
+		let values = field.values();
+		for z in 0..nz {
+			for y in 0..ny {
+				for x in 0..nx {
+					let p = field.grid.point([x, y, z]);
+					let d = values[field.grid.index([x, y, z])] as f64;
+					assert!((d - (p.length() - 1.0)).abs() < 0.02, "{:?} {}", p, d);
+				}
+			}
+		}
+		assert!((field.distance_at(&[0.0, 0.0, 0.05]) + 0.95).abs() < 0.02);
+		assert!(field.distance_at(&[5.0, 0.0, 0.0]).is_nan());
+
+		let grown = field.to_mesh(0.2);
+		let expected = 4.0 / 3.0 * PI * 1.2f64.powi(3);
+		assert!(
+			(grown.volume() - expected).abs() < 0.03 * expected,
+			"{}",
+			grown.volume()
+		);
+		assert!(signed_distance_field_impl(&vertices, &v_indices, 1e-4, 0.0).is_err());
+	}
+}
@@ -0,0 +1,594 @@
+use std::collections::{HashMap, HashSet};
+
+use wasm_bindgen::prelude::*;
+
+use crate::mesh::Mesh;
+use crate::vec3::Vec3;
+
+/// Most layers `slice_layers` cuts, to keep memory bounded.
+const MAX_LAYERS: u64 = 1 << 20;
+
+/// A plane `dot(normal, x) == offset` with a unit normal, and an orthonormal
+/// basis (u, v) of the plane such that u x v = normal.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Plane {
+	pub normal: Vec3,
+	pub offset: f64,
+	pub u: Vec3,
+	pub v: Vec3,
+}
+
+impl Plane {
+	/// Returns None if `normal` is zero.
+	pub fn new(normal: Vec3, offset: f64) -> Option<Plane> {
+		let len = normal.length();
+		if len == 0.0 || !len.is_finite() {
+			return None;
+		}
+		let normal = normal / len;
+		let u = normal.any_perpendicular();
+		Some(Plane {
+			normal,
+			offset: offset / len,
+			u,
+			v: normal.cross(u),
+		})
+	}
+
+	pub fn signed_distance(&self, p: Vec3) -> f64 {
+		self.normal.dot(p) - self.offset
+	}
+
+	/// Coordinates of `p` projected onto the plane, in the (u, v) basis.
+	pub fn project(&self, p: Vec3) -> [f64; 2] {
+		[self.u.dot(p), self.v.dot(p)]
+	}
+}
+
+/// A polyline where a plane cuts a mesh.
+#[derive(Clone, Debug)]
+pub(crate) struct Contour {
+	pub points: Vec<Vec3>,
+	/// The mesh edge each point lies on, as its vertices in ascending order.
+	pub edges: Vec<(u32, u32)>,
+	/// The points in the plane's (u, v) coordinates.
+	pub points2d: Vec<[f64; 2]>,
+	/// Whether the last point connects back to the first. Only open meshes
+	/// produce open contours.
+	pub closed: bool,
+	/// For holes, the index of the contour directly enclosing them.
+	pub parent: Option<u32>,
+	/// Area enclosed, positive for outer contours and negative for holes.
+	/// Zero for open contours.
+	pub area: f64,
+}
+
+impl Contour {
+	pub fn is_hole(&self) -> bool {
+		self.parent.is_some()
+	}
+}
+
+/// Twice the signed area of a polygon, positive if counter-clockwise.
+pub(crate) fn signed_area2(points: &[[f64; 2]]) -> f64 {
+	let n = points.len();
+	(0..n)
+		.map(|i| {
+			let (p, q) = (points[i], points[(i + 1) % n]);
+			p[0] * q[1] - q[0] * p[1]
+		})
+		.sum()
+}
+
+/// Crossing-number test of whether `p` is inside a polygon.
+pub(crate) fn point_in_polygon(p: [f64; 2], points: &[[f64; 2]]) -> bool {
+	let mut inside = false;
+	let mut j = points.len() - 1;
+	for i in 0..points.len() {
+		let (a, b) = (points[i], points[j]);
+		if (a[1] > p[1]) != (b[1] > p[1]) && p[0] < (b[0] - a[0]) * (p[1] - a[1]) / (b[1] - a[1]) + a[0] {
+			inside = !inside;
+		}
+		j = i;
+	}
+	inside
+}
+
+/// A piece of a contour crossing one triangle, from where the triangle's
+/// boundary enters the half-space below the plane to where it leaves it.
+/// Crossings are identified by their mesh edge so that the pieces of
+/// neighbouring triangles can be chained without comparing coordinates.
+struct Segment {
+	start: (u32, u32),
+	end: (u32, u32),
+}
+
+/// Cuts the given triangles with a plane. Vertices exactly on the plane
+/// count as above it, so every crossing lies on an edge and contours never
+/// branch on a manifold mesh. Closed contours are oriented counter-clockwise
+/// seen from the positive side of the plane if they are outer boundaries of
+/// the section, and clockwise if they are holes.
+pub(crate) fn section<I: IntoIterator<Item = u32>>(mesh: &Mesh, triangles: I, plane: &Plane) -> Vec<Contour> {
+	let above = |v: u32| plane.signed_distance(mesh.positions[v as usize]) >= 0.0;
+	let key = |a: u32, b: u32| (a.min(b), a.max(b));
+
+	let mut segments = Vec::new();
+	for t in triangles {
+		let tri = mesh.triangles[t as usize];
+		let sides = [above(tri[0]), above(tri[1]), above(tri[2])];
+		if sides[0] == sides[1] && sides[1] == sides[2] {
+			continue;
+		}
+		let mut start = (0, 0);
+		let mut end = (0, 0);
+		for i in 0..3 {
+			let j = (i + 1) % 3;
+			if sides[i] && !sides[j] {
+				start = key(tri[i], tri[j]);
+			} else if !sides[i] && sides[j] {
+				end = key(tri[i], tri[j]);
+			}
+		}
+		// Walking around the triangle's boundary this way keeps the inside of
+		// a consistently wound mesh to the left.
+		segments.push(Segment { start, end });
+	}
+
+	let mut by_start = HashMap::with_capacity(segments.len());
+	let mut ends = HashSet::with_capacity(segments.len());
+	for (i, s) in segments.iter().enumerate() {
+		by_start.entry(s.start).or_insert(i);
+		ends.insert(s.end);
+	}
+	let crossing = |(a, b): (u32, u32)| -> Vec3 {
+		let (pa, pb) = (mesh.positions[a as usize], mesh.positions[b as usize]);
+		let (da, db) = (plane.signed_distance(pa), plane.signed_distance(pb));
+		pa.lerp(pb, da / (da - db))
+	};
+
+	// Chains that start at an open boundary first, so that they aren't cut
+	// in two, then the closed loops.
+	let mut used = vec![false; segments.len()];
+	let mut contours = Vec::new();
+	let open_first = (0..segments.len())
+		.filter(|&i| !ends.contains(&segments[i].start))
+		.chain(0..segments.len());
+	for first in open_first {
+		if used[first] {
+			continue;
+		}
+		let mut edges = Vec::new();
+		let mut i = first;
+		let closed = loop {
+			used[i] = true;
+			edges.push(segments[i].start);
+			let end = segments[i].end;
+			match by_start.get(&end) {
+				Some(_) if end == segments[first].start => break true,
+				Some(&next) if !used[next] => i = next,
+				_ => {
+					edges.push(end);
+					break false;
+				}
+			}
+		};
+		// Vertices on the plane produce repeated points
+		let mut points: Vec<Vec3> = Vec::with_capacity(edges.len());
+		edges.retain(|&e| {
+			let p = crossing(e);
+			let repeated = points.last() == Some(&p);
+			if !repeated {
+				points.push(p);
+			}
+			!repeated
+		});
+		if closed && points.len() > 1 && points[0] == points[points.len() - 1] {
+			points.pop();
+			edges.pop();
+		}
+		let points2d = points.iter().map(|&p| plane.project(p)).collect();
+		contours.push(Contour {
+			points,
+			edges,
+			points2d,
+			closed,
+			parent: None,
+			area: 0.0,
+		});
+	}
+
+	classify(&mut contours);
+	contours
+}
+
+/// Works out which closed contours are holes from how deeply they are nested,
+/// and orients them accordingly.
+fn classify(contours: &mut [Contour]) {
+	let areas: Vec<f64> = contours
+		.iter()
+		.map(|c| if c.closed { signed_area2(&c.points2d) / 2.0 } else { 0.0 })
+		.collect();
+	let boxes: Vec<[f64; 4]> = contours
+		.iter()
+		.map(|c| {
+			c.points2d.iter().fold(
+				[f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY],
+				|b, p| [b[0].min(p[0]), b[1].min(p[1]), b[2].max(p[0]), b[3].max(p[1])],
+			)
+		})
+		.collect();
+
+	for i in 0..contours.len() {
+		if !contours[i].closed || contours[i].points2d.len() < 3 {
+			continue;
+		}
+		let p = contours[i].points2d[0];
+		let mut depth = 0;
+		let mut parent: Option<usize> = None;
+		for j in 0..contours.len() {
+			let b = boxes[j];
+			if j == i || !contours[j].closed || p[0] < b[0] || p[1] < b[1] || p[0] > b[2] || p[1] > b[3] {
+				continue;
+			}
+			if areas[j].abs() > areas[i].abs() && point_in_polygon(p, &contours[j].points2d) {
+				depth += 1;
+				if parent.map_or(true, |k| areas[j].abs() < areas[k].abs()) {
+					parent = Some(j);
+				}
+			}
+		}
+		let hole = depth % 2 == 1;
+		let c = &mut contours[i];
+		if hole {
+			c.parent = parent.map(|k| k as u32);
+		}
+		if (areas[i] < 0.0) != hole {
+			c.points.reverse();
+			c.edges.reverse();
+			c.points2d.reverse();
+		}
+		c.area = if hole { -areas[i].abs() } else { areas[i].abs() };
+	}
+}
+
+/// The contours where a plane cuts a mesh.
+#[wasm_bindgen]
+pub struct Contours {
+	contours: Vec<Contour>,
+}
+
+#[wasm_bindgen]
+impl Contours {
+	pub fn count(&self) -> u32 {
+		self.contours.len() as u32
+	}
+
+	/// Points of contour `i` as [x0, y0, z0, x1, y1, z1, ...]. The last point
+	/// is not repeated for closed contours.
+	pub fn points(&self, i: u32) -> Option<Box<[f64]>> {
+		let c = self.contours.get(i as usize)?;
+		Some(c.points.iter().flat_map(|p| [p.x, p.y, p.z]).collect())
+	}
+
+	#[wasm_bindgen(js_name = "isClosed")]
+	pub fn is_closed(&self, i: u32) -> bool {
+		self.contours.get(i as usize).is_some_and(|c| c.closed)
+	}
+
+	/// Whether contour `i` is the outer boundary of a region of the section.
+	/// Outer contours are counter-clockwise seen from the side the plane
+	/// normal points to.
+	#[wasm_bindgen(js_name = "isOuter")]
+	pub fn is_outer(&self, i: u32) -> bool {
+		self.contours.get(i as usize).is_some_and(|c| c.closed && !c.is_hole())
+	}
+
+	/// For a hole, the index of the outer contour around it.
+	pub fn parent(&self, i: u32) -> Option<u32> {
+		self.contours.get(i as usize)?.parent
+	}
+
+	/// Area enclosed by contour `i`: positive for outer contours, negative for
+	/// holes and zero for open ones.
+	pub fn area(&self, i: u32) -> f64 {
+		self.contours.get(i as usize).map_or(0.0, |c| c.area)
+	}
+}
+
+pub(crate) fn slice_mesh_impl(
+	vertices: &[f32],
+	v_indices: &[u32],
+	plane_normal: &[f64],
+	plane_offset: f64,
+) -> Result<Contours, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	if plane_normal.len() != 3 {
+		return Err(format!(
+			"Plane normal must have 3 components, but has {}",
+			plane_normal.len()
+		));
+	}
+	let plane = Plane::new(
+		Vec3::new(plane_normal[0], plane_normal[1], plane_normal[2]),
+		plane_offset,
+	)
+	.ok_or_else(|| String::from("Plane normal must be nonzero"))?;
+	let contours = section(&mesh, 0..mesh.triangles.len() as u32, &plane);
+	Ok(Contours { contours })
+}
+
+/// Intersect a mesh from `parseSTLMesh` with the plane of points x where
+/// dot(planeNormal, x) == planeOffset.
+#[wasm_bindgen(js_name = "sliceMesh")]
+pub fn slice_mesh(
+	vertices: &[f32],
+	v_indices: &[u32],
+	plane_normal: &[f64],
+	plane_offset: f64,
+) -> Result<Contours, JsValue> {
+	slice_mesh_impl(vertices, v_indices, plane_normal, plane_offset).map_err(|e| JsValue::from_str(&e))
+}
+
+/// One layer of a sliced print: closed rings in the XY plane, grouped into
+/// polygons by their outer ring.
+struct Layer {
+	z: f64,
+	area: f64,
+	points: Vec<f64>,
+	ring_starts: Vec<u32>,
+	ring_parents: Vec<i32>,
+}
+
+/// Horizontal sections of a mesh at regular heights.
+#[wasm_bindgen]
+pub struct Layers {
+	layers: Vec<Layer>,
+}
+
+#[wasm_bindgen]
+impl Layers {
+	pub fn count(&self) -> u32 {
+		self.layers.len() as u32
+	}
+
+	/// Height of layer `i`'s section.
+	pub fn z(&self, i: u32) -> f64 {
+		self.layers.get(i as usize).map_or(f64::NAN, |l| l.z)
+	}
+
+	/// Area of layer `i`: the area of its outer rings minus that of its holes.
+	pub fn area(&self, i: u32) -> f64 {
+		self.layers.get(i as usize).map_or(0.0, |l| l.area)
+	}
+
+	/// The points of every ring of layer `i` as [x0, y0, x1, y1, ...].
+	pub fn points(&self, i: u32) -> Option<Box<[f64]>> {
+		Some(self.layers.get(i as usize)?.points.clone().into_boxed_slice())
+	}
+
+	/// Where each ring of layer `i` starts in its points, counted in points
+	/// rather than floats, followed by the total number of points.
+	#[wasm_bindgen(js_name = "ringStarts")]
+	pub fn ring_starts(&self, i: u32) -> Option<Box<[u32]>> {
+		Some(self.layers.get(i as usize)?.ring_starts.clone().into_boxed_slice())
+	}
+
+	/// For each ring of layer `i`, -1 if it is the outer boundary of a
+	/// polygon, or the index of the outer ring a hole belongs to. Outer rings
+	/// are counter-clockwise and holes clockwise.
+	#[wasm_bindgen(js_name = "ringParents")]
+	pub fn ring_parents(&self, i: u32) -> Option<Box<[i32]>> {
+		Some(self.layers.get(i as usize)?.ring_parents.clone().into_boxed_slice())
+	}
+
+	/// Number of polygons, i.e. outer rings, in layer `i`.
+	#[wasm_bindgen(js_name = "polygonCount")]
+	pub fn polygon_count(&self, i: u32) -> u32 {
+		self
+			.layers
+			.get(i as usize)
+			.map_or(0, |l| l.ring_parents.iter().filter(|&&p| p < 0).count() as u32)
+	}
+}
+
+/// Slices a mesh every `layer_height` along Z, in the middle of each layer.
+/// Each triangle is only handed to the layers it spans.
+fn slice_layers(mesh: &Mesh, layer_height: f64) -> Vec<Layer> {
+	let (min, max) = mesh.bounds();
+	if mesh.triangles.is_empty() || max.z <= min.z {
+		return Vec::new();
+	}
+	let z0 = min.z + layer_height / 2.0;
+	let count = ((max.z - z0) / layer_height).floor() as usize + 1;
+
+	// Triangles bucketed by layer, in compressed form
+	let layer_range = |t: usize| {
+		let [a, b, c] = mesh.corners(t);
+		let lo = ((a.z.min(b.z).min(c.z) - z0) / layer_height).floor().max(0.0) as usize;
+		let hi = (((a.z.max(b.z).max(c.z) - z0) / layer_height).ceil().max(0.0) as usize).min(count - 1);
+		lo..=hi
+	};
+	let mut offsets = vec![0usize; count + 1];
+	for t in 0..mesh.triangles.len() {
+		for k in layer_range(t) {
+			offsets[k + 1] += 1;
+		}
+	}
+	for k in 0..count {
+		offsets[k + 1] += offsets[k];
+	}
+	let mut fill = offsets.clone();
+	let mut buckets = vec![0u32; offsets[count]];
+	for t in 0..mesh.triangles.len() {
+		for k in layer_range(t) {
+			buckets[fill[k]] = t as u32;
+			fill[k] += 1;
+		}
+	}
+
+	(0..count)
+		.map(|k| {
+			let z = z0 + k as f64 * layer_height;
+			let plane = Plane {
+				normal: Vec3::new(0.0, 0.0, 1.0),
+				offset: z,
+				u: Vec3::new(1.0, 0.0, 0.0),
+				v: Vec3::new(0.0, 1.0, 0.0),
+			};
+			let contours = section(mesh, buckets[offsets[k]..offsets[k + 1]].iter().copied(), &plane);
+
+			// Renumber the closed contours as rings
+			let mut ring_index = vec![-1i32; contours.len()];
+			let mut layer = Layer {
+				z,
+				area: 0.0,
+				points: Vec::new(),
+				ring_starts: Vec::new(),
+				ring_parents: Vec::new(),
+			};
+			for (i, c) in contours.iter().enumerate() {
+				if c.closed && c.points2d.len() >= 3 {
+					ring_index[i] = layer.ring_starts.len() as i32;
+					layer.ring_starts.push((layer.points.len() / 2) as u32);
+					layer.points.extend(c.points2d.iter().flatten());
+					layer.area += c.area;
+				}
+			}
+			layer.ring_starts.push((layer.points.len() / 2) as u32);
+			layer.ring_parents = contours
+				.iter()
+				.zip(&ring_index)
+				.filter(|(_, &r)| r >= 0)
+				.map(|(c, _)| c.parent.map_or(-1, |p| ring_index[p as usize]))
+				.collect();
+			layer
+		})
+		.collect()
+}
+
+pub(crate) fn slice_layers_impl(vertices: &[f32], v_indices: &[u32], layer_height: f64) -> Result<Layers, String> {
+	if !layer_height.is_finite() || layer_height <= 0.0 {
+		return Err(format!("Layer height must be positive, but is {}", layer_height));
+	}
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	let (min, max) = mesh.bounds();
+	let count = ((max.z - min.z) / layer_height).ceil();
+	if count > MAX_LAYERS as f64 {
+		return Err(format!(
+			"A layer height of {} needs {} layers, more than the {} allowed",
+			layer_height, count, MAX_LAYERS
+		));
+	}
+	Ok(Layers {
+		layers: slice_layers(&mesh, layer_height),
+	})
+}
+
+/// Slice a mesh from `parseSTLMesh` into layers of `layerHeight` along Z, as
+/// for 3D printing. Layer `i` is cut at minZ + (i + 0.5) * layerHeight. Only
+/// closed rings are returned, so open parts of the mesh are left out.
+#[wasm_bindgen(js_name = "sliceLayers")]
+pub fn slice_layers_export(vertices: &[f32], v_indices: &[u32], layer_height: f64) -> Result<Layers, JsValue> {
+	slice_layers_impl(vertices, v_indices, layer_height).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The side walls of an axis-aligned box, open at the top and bottom.
+	fn tube(min: Vec3, size: f64, n: usize) -> Mesh {
+		let mut mesh = Mesh::tessellated_cube(min, size, n);
+		let sides: Vec<u32> = (0..mesh.triangles.len() as u32)
+			.filter(|&t| mesh.face_normal(t as usize).z == 0.0)
+			.collect();
+		mesh = mesh.submesh(&sides);
+		mesh
+	}
+
+	#[test]
+	fn tube_with_hole() {
+		let mut mesh = tube(Vec3::new(0.0, 0.0, 0.0), 3.0, 3);
+		let mut inner = tube(Vec3::new(1.0, 1.0, 0.0), 1.0, 1);
+		for t in inner.triangles.iter_mut() {
+			t.swap(1, 2);
+		}
+		mesh.append(&inner);
+		let (vertices, _, v_indices, _) = mesh.parsed();
+
+		let contours = slice_mesh_impl(&vertices, &v_indices, &[0.0, 0.0, 2.0], 1.0).unwrap();
+		assert_eq!(contours.count(), 2);
+		let outer = (0..2).find(|&i| contours.is_outer(i)).unwrap();
+		let hole = 1 - outer;
+		assert!(contours.is_closed(hole) && !contours.is_outer(hole));
+		assert!((contours.area(outer) - 9.0).abs() < 1e-9);
+		assert!((contours.area(hole) + 1.0).abs() < 1e-9);
+		assert_eq!(contours.parent(hole), Some(outer));
+		assert_eq!(contours.parent(outer), None);
+		let points = contours.points(hole).unwrap();
+		assert_eq!(points.len(), 4 * 2 * 3);
+		assert!(points.chunks(3).all(|p| p[2] == 0.5));
+
+		// Flipping the plane flips the orientation but not the classification
+		let flipped = slice_mesh_impl(&vertices, &v_indices, &[0.0, 0.0, -1.0], -0.5).unwrap();
+		let outer = (0..2).find(|&i| flipped.is_outer(i)).unwrap();
+		assert!((flipped.area(outer) - 9.0).abs() < 1e-9);
+
+		// Through a row of vertices
+		let contours = slice_mesh_impl(&vertices, &v_indices, &[0.0, 0.0, 1.0], 1.0).unwrap();
+		assert_eq!(contours.count(), 2);
+
+		// Without two of the walls nothing is closed
+		let walls: Vec<u32> = (0..mesh.triangles.len() as u32)
+			.filter(|&t| mesh.face_normal(t as usize).x != 0.0)
+			.collect();
+		let open = mesh.submesh(&walls);
+		let plane = Plane::new(Vec3::new(0.0, 0.0, 1.0), 0.5).unwrap();
+		let contours = section(&open, 0..open.triangles.len() as u32, &plane);
+		assert_eq!(contours.len(), 4);
+		assert!(contours.iter().all(|c| !c.closed && c.area == 0.0));
+	}
+
+	#[test]
+	fn layers() {
+		// A 2x2x1 slab with a 1x1x2 tower on it
+		let mut mesh = Mesh::cube(Vec3::new(0.0, 0.0, 0.0), 1.0);
+		for p in mesh.positions.iter_mut() {
+			*p = Vec3::new(p.x * 2.0, p.y * 2.0, p.z);
+		}
+		mesh.append(&Mesh::cube(Vec3::new(0.5, 0.5, 1.0), 1.0));
+		let (vertices, _, v_indices, _) = mesh.parsed();
+
+		let layers = slice_layers_impl(&vertices, &v_indices, 0.1).unwrap();
+		assert_eq!(layers.count(), 20);
+		assert!((layers.z(0) - 0.05).abs() < 1e-12);
+		for i in 0..20 {
+			let expected = if i < 10 { 4.0 } else { 1.0 };
+			assert!((layers.area(i) - expected).abs() < 1e-9, "{} {}", i, layers.area(i));
+			assert_eq!(layers.polygon_count(i), 1);
+			assert_eq!(&*layers.ring_parents(i).unwrap(), &[-1]);
+			let starts = layers.ring_starts(i).unwrap();
+			assert_eq!(starts[0], 0);
+			assert_eq!(starts[1] as usize * 2, layers.points(i).unwrap().len());
+		}
+
+		let mut walls = tube(Vec3::new(0.0, 0.0, 0.0), 3.0, 3);
+		let mut inner = tube(Vec3::new(1.0, 1.0, 0.0), 1.0, 1);
+		for t in inner.triangles.iter_mut() {
+			t.swap(1, 2);
+		}
+		walls.append(&inner);
+		let (vertices, _, v_indices, _) = walls.parsed();
+		let layers = slice_layers_impl(&vertices, &v_indices, 0.25).unwrap();
+		assert_eq!(layers.count(), 12);
+		assert!((layers.area(1) - 8.0).abs() < 1e-9);
+		assert_eq!(layers.polygon_count(1), 1);
+		let parents = layers.ring_parents(1).unwrap();
+		assert_eq!(parents.len(), 2);
+		assert!(parents.contains(&-1));
+
+		assert!(slice_layers_impl(&vertices, &v_indices, 0.0).is_err());
+		assert!(slice_layers_impl(&vertices, &v_indices, f64::INFINITY).is_err());
+		assert!(slice_layers_impl(&vertices, &v_indices, 1e-9).is_err());
+	}
+}
@@ -0,0 +1,256 @@
+use wasm_bindgen::prelude::*;
+
+use crate::mesh::{Mesh, MeshBuffers};
+use crate::vec3::Vec3;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SmoothingMethod {
+	/// Moves every vertex towards the average of its neighbours. Shrinks the
+	/// mesh.
+	Laplacian = 0,
+	/// Alternates a shrinking Laplacian step with an inflating one (Taubin,
+	/// "A Signal Processing Approach to Fair Surface Design").
+	Taubin = 1,
+	/// Laplacian steps followed by pushing vertices back towards their
+	/// original and previous positions (Vollmer et al., "Improved Laplacian
+	/// Smoothing of Noisy Surface Meshes").
+	Hc = 2,
+}
+
+/// The neighbours of every vertex in compressed form: the neighbours of `v`
+/// are `indices[offsets[v]..offsets[v + 1]]`.
+struct Neighbours {
+	offsets: Vec<usize>,
+	indices: Vec<u32>,
+}
+
+impl Neighbours {
+	fn of(&self, v: usize) -> &[u32] {
+		&self.indices[self.offsets[v]..self.offsets[v + 1]]
+	}
+}
+
+/// Finds the neighbours of every vertex, and which vertices must stay in
+/// place because they lie on a boundary (when `lock_boundary` is set) or on
+/// an edge whose faces meet at more than `feature_angle` radians (when it is
+/// positive).
+fn topology(mesh: &Mesh, lock_boundary: bool, feature_angle: f64) -> (Neighbours, Vec<bool>) {
+	let n = mesh.positions.len();
+	let edge_faces = mesh.edge_faces();
+	let mut edges: Vec<(u32, u32)> = edge_faces.keys().copied().collect();
+	edges.sort_unstable();
+
+	let mut locked = vec![false; n];
+	let cos_feature = feature_angle.cos();
+	for (&(a, b), faces) in &edge_faces {
+		let lock = match faces.len() {
+			2 => {
+				let (f, g) = (faces[0] as usize, faces[1] as usize);
+				feature_angle > 0.0 && mesh.face_normal(f).dot(mesh.face_normal(g)) < cos_feature
+			}
+			_ => lock_boundary,
+		};
+		if lock {
+			locked[a as usize] = true;
+			locked[b as usize] = true;
+		}
+	}
+
+	let mut offsets = vec![0; n + 1];
+	for &(a, b) in &edges {
+		offsets[a as usize + 1] += 1;
+		offsets[b as usize + 1] += 1;
+	}
+	for v in 0..n {
+		offsets[v + 1] += offsets[v];
+	}
+	let mut fill = offsets.clone();
+	let mut indices = vec![0; offsets[n]];
+	for &(a, b) in &edges {
+		indices[fill[a as usize]] = b;
+		fill[a as usize] += 1;
+		indices[fill[b as usize]] = a;
+		fill[b as usize] += 1;
+	}
+	(Neighbours { offsets, indices }, locked)
+}
+
+/// Moves every unlocked vertex by `factor` times the vector to the average of
+/// its neighbours.
+fn laplacian_step(positions: &mut [Vec3], neighbours: &Neighbours, locked: &[bool], factor: f64) {
+	let old = positions.to_vec();
+	for (v, p) in positions.iter_mut().enumerate() {
+		let ns = neighbours.of(v);
+		if locked[v] || ns.is_empty() {
+			continue;
+		}
+		let avg = ns.iter().fold(Vec3::ZERO, |acc, &u| acc + old[u as usize]) / ns.len() as f64;
+		*p += (avg - old[v]) * factor;
+	}
+}
+
+fn hc_step(positions: &mut [Vec3], original: &[Vec3], neighbours: &Neighbours, locked: &[bool], alpha: f64, beta: f64) {
+	let previous = positions.to_vec();
+	laplacian_step(positions, neighbours, locked, 1.0);
+	// How far each vertex drifted from a blend of its original and previous
+	// positions
+	let drift: Vec<Vec3> = (0..positions.len())
+		.map(|v| positions[v] - (original[v] * alpha + previous[v] * (1.0 - alpha)))
+		.collect();
+	for (v, p) in positions.iter_mut().enumerate() {
+		let ns = neighbours.of(v);
+		if locked[v] || ns.is_empty() {
+			continue;
+		}
+		let avg = ns.iter().fold(Vec3::ZERO, |acc, &u| acc + drift[u as usize]) / ns.len() as f64;
+		*p -= drift[v] * beta + avg * (1.0 - beta);
+	}
+}
+
+pub(crate) fn smooth(
+	mesh: &mut Mesh,
+	method: SmoothingMethod,
+	iterations: u32,
+	factor: f64,
+	second: f64,
+	lock_boundary: bool,
+	feature_angle: f64,
+) {
+	let (neighbours, locked) = topology(mesh, lock_boundary, feature_angle);
+	let original = mesh.positions.clone();
+	for _ in 0..iterations {
+		match method {
+			SmoothingMethod::Laplacian => laplacian_step(&mut mesh.positions, &neighbours, &locked, factor),
+			SmoothingMethod::Taubin => {
+				laplacian_step(&mut mesh.positions, &neighbours, &locked, factor);
+				laplacian_step(&mut mesh.positions, &neighbours, &locked, second);
+			}
+			SmoothingMethod::Hc => hc_step(&mut mesh.positions, &original, &neighbours, &locked, factor, second),
+		}
+	}
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn smooth_mesh_impl(
+	vertices: &[f32],
+	v_indices: &[u32],
+	method: SmoothingMethod,
+	iterations: u32,
+	factor: f64,
+	second: f64,
+	lock_boundary: bool,
+	feature_angle: f64,
+) -> Result<MeshBuffers, String> {
+	let mut mesh = Mesh::from_buffers(vertices, v_indices)?;
+	smooth(
+		&mut mesh,
+		method,
+		iterations,
+		factor,
+		second,
+		lock_boundary,
+		feature_angle,
+	);
+	Ok(mesh.to_buffers())
+}
+
+/// Smooth a mesh from `parseSTLMesh`. Normals are recomputed from the
+/// smoothed faces.
+///
+/// `factor` and `second` depend on the method:
+/// - Laplacian: `factor` is the step size λ in (0, 1]; `second` is unused.
+/// - Taubin: `factor` is λ > 0 and `second` is μ < -λ, e.g. 0.5 and -0.53.
+/// - HC: `factor` is α, the pull towards the original positions, and
+///   `second` is β, e.g. 0 and 0.5.
+///
+/// Vertices on open boundaries are kept in place if `lock_boundary` is set,
+/// as are vertices on edges whose faces meet at more than `feature_angle`
+/// radians; pass 0 to not lock any features.
+#[allow(clippy::too_many_arguments)]
+#[wasm_bindgen(js_name = "smoothMesh")]
+pub fn smooth_mesh(
+	vertices: &[f32],
+	v_indices: &[u32],
+	method: SmoothingMethod,
+	iterations: u32,
+	factor: f64,
+	second: f64,
+	lock_boundary: bool,
+	feature_angle: f64,
+) -> Result<MeshBuffers, JsValue> {
+	smooth_mesh_impl(
+		vertices,
+		v_indices,
+		method,
+		iterations,
+		factor,
+		second,
+		lock_boundary,
+		feature_angle,
+	)
+	.map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Unit grid in the xy plane with every interior vertex pushed up or down.
+	fn noisy_grid(n: usize) -> Mesh {
+		let mut mesh = Mesh::default();
+		for j in 0..=n {
+			for i in 0..=n {
+				let interior = i > 0 && j > 0 && i < n && j < n;
+				let z = if interior {
+					0.01 * if (i * 7 + j * 3) % 5 < 2 { 1.0 } else { -1.0 }
+				} else {
+					0.0
+				};
+				mesh
+					.positions
+					.push(Vec3::new(i as f64 / n as f64, j as f64 / n as f64, z));
+			}
+		}
+		let idx = |i: usize, j: usize| (j * (n + 1) + i) as u32;
+		for j in 0..n {
+			for i in 0..n {
+				mesh.triangles.push([idx(i, j), idx(i + 1, j), idx(i + 1, j + 1)]);
+				mesh.triangles.push([idx(i, j), idx(i + 1, j + 1), idx(i, j + 1)]);
+			}
+		}
+		mesh
+	}
+
+	fn max_height(mesh: &Mesh) -> f64 {
+		mesh.positions.iter().fold(0.0, |acc, p| acc.max(p.z.abs()))
+	}
+
+	#[test]
+	fn removes_noise() {
+		let methods = [
+			(SmoothingMethod::Laplacian, 0.5, 0.0),
+			(SmoothingMethod::Taubin, 0.5, -0.53),
+			(SmoothingMethod::Hc, 0.0, 0.5),
+		];
+		for &(method, factor, second) in &methods {
+			let mut mesh = noisy_grid(10);
+			smooth(&mut mesh, method, 10, factor, second, true, 0.0);
+			assert!(max_height(&mesh) < 0.005, "{:?}: {}", method, max_height(&mesh));
+			// The locked border stays put
+			assert_eq!(mesh.positions[5], Vec3::new(0.5, 0.0, 0.0));
+		}
+	}
+
+	#[test]
+	fn features_stay_sharp() {
+		let cube = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 1.0, 4);
+		let mut locked = cube.clone();
+		smooth(&mut locked, SmoothingMethod::Laplacian, 5, 0.5, 0.0, true, 0.5);
+		assert!((locked.volume() - 1.0).abs() < 1e-12);
+
+		let mut free = cube.clone();
+		smooth(&mut free, SmoothingMethod::Laplacian, 5, 0.5, 0.0, true, 0.0);
+		assert!(free.volume() < 0.9);
+	}
+}
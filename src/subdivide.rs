@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+
+use wasm_bindgen::prelude::*;
+
+use crate::mesh::{Mesh, MeshBuffers};
+use crate::vec3::Vec3;
+
+/// Subdivision refuses to produce more triangles than this.
+const MAX_TRIANGLES: usize = 1 << 26;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubdivisionScheme {
+	/// Loop subdivision, which converges to a smooth surface.
+	Loop = 0,
+	/// Splits every triangle into four at its edge midpoints without moving
+	/// anything, so the shape is unchanged.
+	Midpoint = 1,
+}
+
+/// Splits every triangle into four. Edges in `creases` are kept sharp: points
+/// on them only depend on the crease itself, and vertices where more than two
+/// creases meet stay in place. Returns the refined mesh and its creases.
+fn subdivide_once(
+	mesh: &Mesh,
+	creases: &HashSet<(u32, u32)>,
+	scheme: SubdivisionScheme,
+) -> (Mesh, HashSet<(u32, u32)>) {
+	let n = mesh.positions.len();
+	let edge_faces = mesh.edge_faces();
+	let mut edges: Vec<(u32, u32)> = edge_faces.keys().copied().collect();
+	edges.sort_unstable();
+
+	let mut positions = mesh.positions.clone();
+	let mut valence = vec![0usize; n];
+	let mut sums = vec![Vec3::ZERO; n];
+	let mut crease_count = vec![0usize; n];
+	let mut crease_sums = vec![Vec3::ZERO; n];
+	let mut next_creases = HashSet::with_capacity(creases.len() * 2);
+
+	for &(a, b) in &edges {
+		let (pa, pb) = (mesh.positions[a as usize], mesh.positions[b as usize]);
+		let faces = &edge_faces[&(a, b)];
+		let crease = creases.contains(&(a, b));
+		let mid = positions.len() as u32;
+		let p = if scheme == SubdivisionScheme::Midpoint || crease {
+			(pa + pb) * 0.5
+		} else {
+			let opposite = |f: u32| -> Vec3 {
+				let tri = mesh.triangles[f as usize];
+				let c = tri.iter().find(|&&v| v != a && v != b).unwrap();
+				mesh.positions[*c as usize]
+			};
+			(pa + pb) * 0.375 + (opposite(faces[0]) + opposite(faces[1])) * 0.125
+		};
+		positions.push(p);
+
+		valence[a as usize] += 1;
+		valence[b as usize] += 1;
+		sums[a as usize] += pb;
+		sums[b as usize] += pa;
+		if crease {
+			crease_count[a as usize] += 1;
+			crease_count[b as usize] += 1;
+			crease_sums[a as usize] += pb;
+			crease_sums[b as usize] += pa;
+			next_creases.insert((a, mid));
+			next_creases.insert((b, mid));
+		}
+	}
+
+	if scheme == SubdivisionScheme::Loop {
+		for v in 0..n {
+			let p = mesh.positions[v];
+			positions[v] = match crease_count[v] {
+				0 | 1 if valence[v] > 0 => {
+					let k = valence[v] as f64;
+					let beta = if valence[v] == 3 { 3.0 / 16.0 } else { 3.0 / (8.0 * k) };
+					p * (1.0 - k * beta) + sums[v] * beta
+				}
+				2 => p * 0.75 + crease_sums[v] * 0.125,
+				_ => p,
+			};
+		}
+	}
+
+	let edge_point = |a: u32, b: u32| -> u32 {
+		let key = (a.min(b), a.max(b));
+		n as u32 + edges.binary_search(&key).unwrap() as u32
+	};
+	let mut triangles = Vec::with_capacity(mesh.triangles.len() * 4);
+	for (t, &[a, b, c]) in mesh.triangles.iter().enumerate() {
+		if mesh.is_degenerate(t) {
+			continue;
+		}
+		let (ab, bc, ca) = (edge_point(a, b), edge_point(b, c), edge_point(c, a));
+		triangles.extend_from_slice(&[[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]);
+	}
+	(Mesh { positions, triangles }, next_creases)
+}
+
+/// Edges that aren't shared by exactly two faces, or whose faces meet at more
+/// than `crease_angle` radians.
+fn find_creases(mesh: &Mesh, crease_angle: f64) -> HashSet<(u32, u32)> {
+	let cos_crease = crease_angle.cos();
+	mesh
+		.edge_faces()
+		.into_iter()
+		.filter(|(_, faces)| match faces.len() {
+			2 => {
+				let (f, g) = (faces[0] as usize, faces[1] as usize);
+				crease_angle > 0.0 && mesh.face_normal(f).dot(mesh.face_normal(g)) < cos_crease
+			}
+			_ => true,
+		})
+		.map(|(edge, _)| edge)
+		.collect()
+}
+
+pub(crate) fn subdivide_impl(
+	vertices: &[f32],
+	v_indices: &[u32],
+	scheme: SubdivisionScheme,
+	levels: u32,
+	crease_angle: f64,
+) -> Result<MeshBuffers, String> {
+	let mut mesh = Mesh::from_buffers(vertices, v_indices)?;
+	let result_triangles = (mesh.triangles.len() as u64).saturating_mul(4u64.saturating_pow(levels));
+	if result_triangles > MAX_TRIANGLES as u64 {
+		return Err(format!(
+			"Subdividing {} triangles {} times would produce {} triangles, more than the limit of {}",
+			mesh.triangles.len(),
+			levels,
+			result_triangles,
+			MAX_TRIANGLES
+		));
+	}
+	let mut creases = find_creases(&mesh, crease_angle);
+	for _ in 0..levels {
+		let (next, next_creases) = subdivide_once(&mesh, &creases, scheme);
+		mesh = next;
+		creases = next_creases;
+	}
+	Ok(mesh.to_buffers())
+}
+
+/// Subdivide a mesh from `parseSTLMesh` `levels` times, each time splitting
+/// every triangle into four. Edges whose faces meet at more than
+/// `crease_angle` radians, and open or non-manifold edges, are kept as sharp
+/// creases by the Loop scheme; pass 0 to only keep the latter.
+#[wasm_bindgen]
+pub fn subdivide(
+	vertices: &[f32],
+	v_indices: &[u32],
+	scheme: SubdivisionScheme,
+	levels: u32,
+	crease_angle: f64,
+) -> Result<MeshBuffers, JsValue> {
+	subdivide_impl(vertices, v_indices, scheme, levels, crease_angle).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cube() {
+		let cube = Mesh::cube(Vec3::new(0.0, 0.0, 0.0), 1.0);
+		let (vertices, _, v_indices, _) = cube.parsed();
+
+		let midpoint = subdivide_impl(&vertices, &v_indices, SubdivisionScheme::Midpoint, 1, 0.0).unwrap();
+		assert_eq!(midpoint.triangle_count(), 48);
+		assert_eq!(midpoint.vertex_count(), 8 + 18);
+		assert!((midpoint.volume() - 1.0).abs() < 1e-9);
+
+		// Without creases the cube rounds off
+		let smooth = subdivide_impl(&vertices, &v_indices, SubdivisionScheme::Loop, 2, 0.0).unwrap();
+		assert_eq!(smooth.triangle_count(), 12 * 16);
+		assert!(smooth.volume() > 0.3 && smooth.volume() < 0.9, "{}", smooth.volume());
+
+		// With them its faces stay flat
+		let sharp = subdivide_impl(&vertices, &v_indices, SubdivisionScheme::Loop, 2, 0.5).unwrap();
+		assert!((sharp.volume() - 1.0).abs() < 1e-6, "{}", sharp.volume());
+		assert_eq!(&*sharp.bounds(), &[0.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+
+		assert!(subdivide_impl(&vertices, &v_indices, SubdivisionScheme::Loop, 20, 0.0).is_err());
+	}
+}
@@ -0,0 +1,231 @@
+use wasm_bindgen::prelude::*;
+
+use crate::mesh::Mesh;
+use crate::mesh_bvh::MeshBvh;
+use crate::vec3::Vec3;
+
+/// Half the opening angle of the cone of rays used for more than one ray per
+/// vertex, as in Shapira et al., "Consistent Mesh Partitioning and
+/// Skeletonisation using the Shape Diameter Function".
+const CONE_HALF_ANGLE: f64 = std::f64::consts::PI / 3.0;
+
+/// Most rays cast per vertex.
+const MAX_RAYS: u32 = 256;
+
+/// Wall thickness at every vertex of a mesh.
+#[wasm_bindgen]
+pub struct WallThickness {
+	thickness: Vec<f32>,
+	/// Vertex with the thinnest wall, and the ends of the ray measuring it
+	thinnest: Option<(u32, Vec3, Vec3)>,
+}
+
+#[wasm_bindgen]
+impl WallThickness {
+	/// Thickness per vertex, aligned with the vertices from `parseSTLMesh`. NaN
+	/// where no ray hit the inside of the mesh, e.g. at holes.
+	pub fn thickness(&self) -> Box<[f32]> {
+		self.thickness.clone().into_boxed_slice()
+	}
+
+	/// The smallest thickness, or NaN if there is none.
+	#[wasm_bindgen(js_name = "minThickness")]
+	pub fn min_thickness(&self) -> f64 {
+		self.thinnest.map_or(f64::NAN, |(_, a, b)| (b - a).length())
+	}
+
+	/// Index of the vertex with the smallest thickness.
+	#[wasm_bindgen(js_name = "minVertex")]
+	pub fn min_vertex(&self) -> Option<u32> {
+		self.thinnest.map(|(v, _, _)| v)
+	}
+
+	/// Where the wall is thinnest: the vertex and the point on the other side
+	/// of the wall, as [x0, y0, z0, x1, y1, z1]. Empty if there is none.
+	#[wasm_bindgen(js_name = "minLocation")]
+	pub fn min_location(&self) -> Box<[f64]> {
+		match self.thinnest {
+			Some((_, a, b)) => Box::new([a.x, a.y, a.z, b.x, b.y, b.z]),
+			None => Box::new([]),
+		}
+	}
+}
+
+/// Directions spread evenly over the cone of half angle `CONE_HALF_ANGLE`
+/// around `axis`, on a golden angle spiral. A single ray goes along the axis.
+fn cone_directions(axis: Vec3, rays: u32) -> Vec<Vec3> {
+	if rays <= 1 {
+		return vec![axis];
+	}
+	let u = axis.any_perpendicular();
+	let w = axis.cross(u);
+	let golden_angle = std::f64::consts::PI * (3.0 - 5f64.sqrt());
+	let min_cos = CONE_HALF_ANGLE.cos();
+	(0..rays)
+		.map(|k| {
+			// Uniform in solid angle
+			let cos = 1.0 - (1.0 - min_cos) * (k as f64 + 0.5) / rays as f64;
+			let sin = (1.0 - cos * cos).sqrt();
+			let phi = golden_angle * k as f64;
+			axis * cos + (u * phi.cos() + w * phi.sin()) * sin
+		})
+		.collect()
+}
+
+/// Thickness at each vertex as the distance along the inverted normal to the
+/// inside of the opposite wall, or the median over a cone of rays. Hits on
+/// triangles facing the ray, which a ray leaving the solid can't reach first
+/// on a consistently oriented mesh, are ignored.
+fn wall_thickness(bvh: &MeshBvh, normals: &[Vec3], rays: u32) -> WallThickness {
+	let mesh = bvh.mesh();
+	let (lo, hi) = mesh.bounds();
+	// Start rays just inside the surface so they don't hit the triangles
+	// around their own vertex
+	let offset = 1e-7 * (hi - lo).length();
+	let mut thickness = Vec::with_capacity(mesh.positions.len());
+	let mut thinnest: Option<(u32, Vec3, Vec3)> = None;
+	let mut distances = Vec::with_capacity(rays as usize);
+	for (v, &p) in mesh.positions.iter().enumerate() {
+		let n = normals[v];
+		if n == Vec3::ZERO {
+			thickness.push(f32::NAN);
+			continue;
+		}
+		distances.clear();
+		for dir in cone_directions(-n, rays) {
+			let origin = p + dir * offset;
+			let facing = |t: u32| mesh.face_cross(t as usize).dot(dir) <= 0.0;
+			if let Some(hit) = bvh.cast_through(origin, dir, facing) {
+				distances.push((hit.distance() + offset, dir));
+			}
+		}
+		if distances.is_empty() {
+			thickness.push(f32::NAN);
+			continue;
+		}
+		distances.sort_by(|a, b| a.0.total_cmp(&b.0));
+		let (d, dir) = distances[distances.len() / 2];
+		thickness.push(d as f32);
+		if thinnest.map_or(true, |(_, a, b)| d < (b - a).length()) {
+			thinnest = Some((v as u32, p, p + dir * d));
+		}
+	}
+	WallThickness { thickness, thinnest }
+}
+
+pub(crate) fn wall_thickness_impl(
+	vertices: &[f32],
+	normals: &[f32],
+	v_indices: &[u32],
+	rays: u32,
+) -> Result<WallThickness, String> {
+	if rays > MAX_RAYS {
+		return Err(format!(
+			"Can cast at most {} rays per vertex, but {} were asked for",
+			MAX_RAYS, rays
+		));
+	}
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	let n = mesh.positions.len();
+	let normals = if normals.is_empty() {
+		mesh.vertex_normals()
+	} else if normals.len() < n * 3 {
+		return Err(format!(
+			"Normal buffer of length {} is too short for {} vertices",
+			normals.len(),
+			n
+		));
+	} else {
+		(0..n).map(|i| Vec3::from_f32(normals, i).normalized()).collect()
+	};
+	Ok(wall_thickness(&MeshBvh::new(mesh, Vec::new()), &normals, rays))
+}
+
+/// Estimate the wall thickness at every vertex of a closed mesh from
+/// `parseSTLMesh` by casting rays inwards, against the vertex normals. With
+/// `rays` = 1 a single ray is cast; with more, the median distance over a
+/// cone of rays is used (the shape diameter function), which is less thrown
+/// by sharp corners and small holes. 16 to 30 rays is typical, and at most
+/// 256 are allowed. Pass empty `normals` to average them from the faces.
+#[wasm_bindgen(js_name = "wallThickness")]
+pub fn wall_thickness_export(
+	vertices: &[f32],
+	normals: &[f32],
+	v_indices: &[u32],
+	rays: u32,
+) -> Result<WallThickness, JsValue> {
+	wall_thickness_impl(vertices, normals, v_indices, rays).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn slab_and_block() {
+		// A 4x4x0.5 slab next to a 2x2x2 block
+		let mut mesh = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 1.0, 4);
+		mesh
+			.positions
+			.iter_mut()
+			.for_each(|p| *p = Vec3::new(p.x * 4.0, p.y * 4.0, p.z * 0.5));
+		mesh.append(&Mesh::tessellated_cube(Vec3::new(10.0, 0.0, 0.0), 2.0, 4));
+		let (vertices, normals, v_indices, _) = mesh.parsed();
+
+		let walls = wall_thickness_impl(&vertices, &normals, &v_indices, 1).unwrap();
+		let thickness = walls.thickness();
+		let positions = Mesh::from_buffers(&vertices, &v_indices).unwrap().positions;
+		for (v, (p, t)) in positions.iter().zip(thickness.iter()).enumerate() {
+			// Vertices on edges have slanted normals
+			if normals[v * 3..v * 3 + 3].iter().filter(|&&c| c != 0.0).count() != 1 {
+				assert!(t.is_finite());
+				continue;
+			}
+			// Faces of the slab are 4 or 0.5 apart, faces of the block 2
+			let expected: &[f32] = if p.x < 5.0 { &[4.0, 0.5] } else { &[2.0] };
+			assert!(expected.iter().any(|e| (t - e).abs() < 1e-5), "{:?} {}", p, t);
+		}
+		assert!((walls.min_thickness() - 0.5).abs() < 1e-9);
+		let location = walls.min_location();
+		assert!((location[2] - location[5]).abs() > 0.49);
+		assert!(positions[walls.min_vertex().unwrap() as usize].x < 5.0);
+
+		// The median over a cone sees mostly the slab's faces
+		let cone = wall_thickness_impl(&vertices, &[], &v_indices, 25).unwrap();
+		let top = positions.iter().position(|p| *p == Vec3::new(2.0, 2.0, 0.5)).unwrap();
+		assert!(cone.thickness()[top] < 1.0, "{}", cone.thickness()[top]);
+
+		// An open surface has nothing to measure
+		let triangle = Mesh {
+			positions: vec![
+				Vec3::new(0.0, 0.0, 0.0),
+				Vec3::new(1.0, 0.0, 0.0),
+				Vec3::new(1.0, 1.0, 0.0),
+			],
+			triangles: vec![[0, 1, 2]],
+		};
+		let (vertices, normals, v_indices, _) = triangle.parsed();
+		let open = wall_thickness_impl(&vertices, &normals, &v_indices, 1).unwrap();
+		assert!(open.thickness().iter().all(|t| t.is_nan()));
+		assert!(open.min_thickness().is_nan());
+		assert!(open.min_location().is_empty());
+		assert!(wall_thickness_impl(&vertices, &normals, &v_indices, MAX_RAYS + 1).is_err());
+	}
+
+	#[test]
+	fn overlapping_shells() {
+		// A small cube poking into a unit cube: its face is hit first from
+		// the inside of the unit cube, facing the ray
+		let mut mesh = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 1.0, 2);
+		mesh.append(&Mesh::cube(Vec3::new(0.9, 0.25, 0.25), 0.5));
+		let (vertices, normals, v_indices, _) = mesh.parsed();
+		let positions = Mesh::from_buffers(&vertices, &v_indices).unwrap().positions;
+		let walls = wall_thickness_impl(&vertices, &normals, &v_indices, 1).unwrap();
+		let side = positions.iter().position(|p| *p == Vec3::new(0.0, 0.5, 0.5)).unwrap();
+		assert!(
+			(walls.thickness()[side] - 1.0).abs() < 1e-5,
+			"{}",
+			walls.thickness()[side]
+		);
+	}
+}
@@ -0,0 +1,188 @@
+use crate::predicates::orient2d;
+
+fn orient(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> f64 {
+	orient2d(a[0], a[1], b[0], b[1], c[0], c[1])
+}
+
+/// Triangulates a polygon with holes by ear clipping, after joining each hole
+/// to the outer boundary with a bridge edge (Eberly, "Triangulation by Ear
+/// Clipping"). `outer` is counter-clockwise and `holes` are clockwise, as
+/// indices into `points`. Returns counter-clockwise triangles that use every
+/// vertex of the input, so they match up with edges along the boundary.
+pub(crate) fn triangulate(points: &[[f64; 2]], outer: &[u32], holes: &[&[u32]]) -> Vec<[u32; 3]> {
+	let mut polygon = outer.to_vec();
+
+	// Rightmost holes first, so that each bridge only has to cross the
+	// boundary built so far
+	let rightmost = |hole: &[u32]| -> usize {
+		(0..hole.len())
+			.max_by(|&i, &j| points[hole[i] as usize][0].total_cmp(&points[hole[j] as usize][0]))
+			.unwrap()
+	};
+	let mut holes: Vec<(&[u32], usize)> = holes
+		.iter()
+		.filter(|h| h.len() >= 3)
+		.map(|h| (*h, rightmost(h)))
+		.collect();
+	holes.sort_by(|a, b| points[b.0[b.1] as usize][0].total_cmp(&points[a.0[a.1] as usize][0]));
+	for (hole, m) in holes {
+		if let Some(p) = bridge_target(points, &polygon, points[hole[m] as usize]) {
+			let mut spliced = Vec::with_capacity(polygon.len() + hole.len() + 2);
+			spliced.extend_from_slice(&polygon[..=p]);
+			spliced.extend(hole[m..].iter().chain(&hole[..=m]));
+			spliced.extend_from_slice(&polygon[p..]);
+			polygon = spliced;
+		}
+	}
+
+	clip_ears(points, &polygon)
+}
+
+/// Finds a vertex of `polygon` that can be joined to the hole vertex `m`,
+/// which is to the right of every other vertex of its hole, without crossing
+/// the polygon. Returns its position in `polygon`.
+fn bridge_target(points: &[[f64; 2]], polygon: &[u32], m: [f64; 2]) -> Option<usize> {
+	// Nearest edge hit by a ray from m towards +x
+	let n = polygon.len();
+	let mut best: Option<(f64, usize)> = None;
+	for i in 0..n {
+		let (a, b) = (points[polygon[i] as usize], points[polygon[(i + 1) % n] as usize]);
+		if (a[1] > m[1]) == (b[1] > m[1]) && a[1] != m[1] && b[1] != m[1] {
+			continue;
+		}
+		let x = if a[1] == b[1] {
+			a[0].min(b[0])
+		} else {
+			a[0] + (m[1] - a[1]) / (b[1] - a[1]) * (b[0] - a[0])
+		};
+		if x >= m[0] && best.map_or(true, |(bx, _)| x < bx) {
+			best = Some((x, i));
+		}
+	}
+	let (x, i) = best?;
+	let hit = [x, m[1]];
+	let j = (i + 1) % n;
+	let (a, b) = (points[polygon[i] as usize], points[polygon[j] as usize]);
+	if a == hit {
+		return Some(i);
+	}
+	if b == hit {
+		return Some(j);
+	}
+	let p = if a[0] > b[0] { i } else { j };
+	let pp = points[polygon[p] as usize];
+
+	// Vertices inside the triangle (m, hit, p) could block the bridge. If there
+	// are any, use the one closest in angle to the ray instead.
+	let (lo, hi) = if orient(m, hit, pp) > 0.0 { (hit, pp) } else { (pp, hit) };
+	let mut target = p;
+	let mut best_key = (f64::INFINITY, f64::INFINITY);
+	for (k, &v) in polygon.iter().enumerate() {
+		let r = points[v as usize];
+		if k == p || r == pp || r == m {
+			continue;
+		}
+		if orient(m, lo, r) >= 0.0 && orient(lo, hi, r) >= 0.0 && orient(hi, m, r) >= 0.0 {
+			let d = [r[0] - m[0], r[1] - m[1]];
+			let key = (d[1].abs() / d[0].max(f64::MIN_POSITIVE), d[0] * d[0] + d[1] * d[1]);
+			if key < best_key {
+				best_key = key;
+				target = k;
+			}
+		}
+	}
+	Some(target)
+}
+
+/// Ear clipping of a simple counter-clockwise polygon, which may touch itself
+/// at bridge vertices.
+fn clip_ears(points: &[[f64; 2]], polygon: &[u32]) -> Vec<[u32; 3]> {
+	let n = polygon.len();
+	let mut triangles = Vec::with_capacity(n.saturating_sub(2));
+	if n < 3 {
+		return triangles;
+	}
+	let pos = |k: usize| points[polygon[k] as usize];
+	let mut prev: Vec<usize> = (0..n).map(|k| (k + n - 1) % n).collect();
+	let mut next: Vec<usize> = (0..n).map(|k| (k + 1) % n).collect();
+	let mut remaining = n;
+	let mut k = 0;
+	// Vertices looked at since the last ear was clipped
+	let mut stalled = 0;
+	while remaining > 3 {
+		let (p, q) = (prev[k], next[k]);
+		let (a, b, c) = (pos(p), pos(k), pos(q));
+		let mut ear = orient(a, b, c) > 0.0;
+		if ear {
+			let mut r = next[q];
+			while r != p {
+				let x = pos(r);
+				if x != a && x != b && x != c && orient(a, b, x) >= 0.0 && orient(b, c, x) >= 0.0 && orient(c, a, x) >= 0.0
+				{
+					ear = false;
+					break;
+				}
+				r = next[r];
+			}
+		}
+		// Degenerate input can leave no proper ears; clip anyway rather than
+		// loop forever.
+		if ear || stalled > remaining {
+			triangles.push([polygon[p], polygon[k], polygon[q]]);
+			next[p] = q;
+			prev[q] = p;
+			remaining -= 1;
+			stalled = 0;
+			k = p;
+		} else {
+			stalled += 1;
+			k = q;
+		}
+	}
+	triangles.push([polygon[prev[k]], polygon[k], polygon[next[k]]]);
+	triangles
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn area(points: &[[f64; 2]], triangles: &[[u32; 3]]) -> f64 {
+		triangles
+			.iter()
+			.map(|t| orient(points[t[0] as usize], points[t[1] as usize], points[t[2] as usize]) / 2.0)
+			.sum()
+	}
+
+	#[test]
+	fn square_with_holes() {
+		let points = [
+			// Outer, with a collinear vertex on the bottom edge
+			[0.0, 0.0],
+			[2.0, 0.0],
+			[4.0, 0.0],
+			[4.0, 4.0],
+			[0.0, 4.0],
+			// Two holes at the same height
+			[1.0, 1.0],
+			[1.0, 2.0],
+			[2.0, 2.0],
+			[2.0, 1.0],
+			[2.5, 1.0],
+			[2.5, 2.0],
+			[3.5, 2.0],
+			[3.5, 1.0],
+		];
+		let triangles = triangulate(&points, &[0, 1, 2, 3, 4], &[&[5, 6, 7, 8], &[9, 10, 11, 12]]);
+		// n + 2h - 2 triangles for n vertices and h holes
+		assert_eq!(triangles.len(), 13 + 4 - 2);
+		assert!((area(&points, &triangles) - 14.0).abs() < 1e-12);
+		for t in &triangles {
+			assert!(orient(points[t[0] as usize], points[t[1] as usize], points[t[2] as usize]) >= 0.0);
+		}
+		let mut used: Vec<u32> = triangles.iter().flatten().copied().collect();
+		used.sort_unstable();
+		used.dedup();
+		assert_eq!(used.len(), 13);
+	}
+}
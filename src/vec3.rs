@@ -0,0 +1,149 @@
+use core::ops::{Add, AddAssign, Div, Index, Mul, Neg, Sub, SubAssign};
+
+/// Double precision 3-vector used by the mesh processing code. Buffers coming
+/// from JS are single precision, but accumulating in f64 keeps sums over
+/// millions of triangles stable.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Vec3 {
+	pub x: f64,
+	pub y: f64,
+	pub z: f64,
+}
+
+impl Vec3 {
+	pub const ZERO: Vec3 = Vec3 { x: 0.0, y: 0.0, z: 0.0 };
+
+	pub const fn new(x: f64, y: f64, z: f64) -> Vec3 {
+		Vec3 { x, y, z }
+	}
+
+	pub fn from_f32(buf: &[f32], idx: usize) -> Vec3 {
+		Vec3::new(
+			buf[idx * 3 + 0] as f64,
+			buf[idx * 3 + 1] as f64,
+			buf[idx * 3 + 2] as f64,
+		)
+	}
+
+	pub fn dot(self, o: Vec3) -> f64 {
+		self.x * o.x + self.y * o.y + self.z * o.z
+	}
+
+	pub fn cross(self, o: Vec3) -> Vec3 {
+		Vec3::new(
+			self.y * o.z - self.z * o.y,
+			self.z * o.x - self.x * o.z,
+			self.x * o.y - self.y * o.x,
+		)
+	}
+
+	pub fn length(self) -> f64 {
+		self.dot(self).sqrt()
+	}
+
+	/// Returns the unit vector in the same direction, or zero for a zero vector.
+	pub fn normalized(self) -> Vec3 {
+		let len = self.length();
+		if len > 0.0 {
+			self * (1.0 / len)
+		} else {
+			Vec3::ZERO
+		}
+	}
+
+	/// A unit vector perpendicular to this one, which must be nonzero.
+	pub fn any_perpendicular(self) -> Vec3 {
+		// Cross with the axis the vector is least aligned with
+		let (ax, ay, az) = (self.x.abs(), self.y.abs(), self.z.abs());
+		let axis = if ax <= ay && ax <= az {
+			Vec3::new(1.0, 0.0, 0.0)
+		} else if ay <= az {
+			Vec3::new(0.0, 1.0, 0.0)
+		} else {
+			Vec3::new(0.0, 0.0, 1.0)
+		};
+		self.cross(axis).normalized()
+	}
+
+	pub fn lerp(self, o: Vec3, t: f64) -> Vec3 {
+		self + (o - self) * t
+	}
+
+	pub fn min(self, o: Vec3) -> Vec3 {
+		Vec3::new(
+			if o.x < self.x { o.x } else { self.x },
+			if o.y < self.y { o.y } else { self.y },
+			if o.z < self.z { o.z } else { self.z },
+		)
+	}
+
+	pub fn max(self, o: Vec3) -> Vec3 {
+		Vec3::new(
+			if o.x > self.x { o.x } else { self.x },
+			if o.y > self.y { o.y } else { self.y },
+			if o.z > self.z { o.z } else { self.z },
+		)
+	}
+}
+
+impl Add for Vec3 {
+	type Output = Vec3;
+	fn add(self, o: Vec3) -> Vec3 {
+		Vec3::new(self.x + o.x, self.y + o.y, self.z + o.z)
+	}
+}
+
+impl AddAssign for Vec3 {
+	fn add_assign(&mut self, o: Vec3) {
+		self.x += o.x;
+		self.y += o.y;
+		self.z += o.z;
+	}
+}
+
+impl Sub for Vec3 {
+	type Output = Vec3;
+	fn sub(self, o: Vec3) -> Vec3 {
+		Vec3::new(self.x - o.x, self.y - o.y, self.z - o.z)
+	}
+}
+
+impl SubAssign for Vec3 {
+	fn sub_assign(&mut self, o: Vec3) {
+		self.x -= o.x;
+		self.y -= o.y;
+		self.z -= o.z;
+	}
+}
+
+impl Mul<f64> for Vec3 {
+	type Output = Vec3;
+	fn mul(self, s: f64) -> Vec3 {
+		Vec3::new(self.x * s, self.y * s, self.z * s)
+	}
+}
+
+impl Div<f64> for Vec3 {
+	type Output = Vec3;
+	fn div(self, s: f64) -> Vec3 {
+		Vec3::new(self.x / s, self.y / s, self.z / s)
+	}
+}
+
+impl Neg for Vec3 {
+	type Output = Vec3;
+	fn neg(self) -> Vec3 {
+		Vec3::new(-self.x, -self.y, -self.z)
+	}
+}
+
+impl Index<usize> for Vec3 {
+	type Output = f64;
+	fn index(&self, i: usize) -> &f64 {
+		match i {
+			0 => &self.x,
+			1 => &self.y,
+			_ => &self.z,
+		}
+	}
+}
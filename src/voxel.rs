@@ -0,0 +1,383 @@
+use wasm_bindgen::prelude::*;
+
+use crate::isosurface::{contour, ScalarGrid};
+use crate::mesh::{Mesh, MeshBuffers};
+use crate::predicates::orient2d;
+use crate::vec3::Vec3;
+
+/// Largest grid `voxelize` builds, to keep memory bounded.
+const MAX_VOXELS: u64 = 1 << 26;
+
+/// Which voxels of a mesh's grid are filled.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoxelFill {
+	/// Only voxels the surface passes through.
+	Surface = 0,
+	/// The surface plus voxels whose centers are inside by the parity of
+	/// crossings along rays in x, y and z, taking the majority of the three
+	/// (Nooruddin and Turk, "Simplification and Repair of Polygonal Models
+	/// Using Volumetric Techniques"). Tolerates small holes.
+	Parity = 1,
+	/// The surface plus voxels whose centers have a nonzero winding number
+	/// along rays in z. Fills overlapping shells of a closed mesh correctly.
+	Winding = 2,
+}
+
+/// A regular grid of voxels, each filled or empty.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct VoxelGrid {
+	dims: [u32; 3],
+	origin: Vec3,
+	size: f64,
+	bits: Vec<u8>,
+}
+
+impl VoxelGrid {
+	/// An empty grid covering `min`..`max` with a voxel to spare on every
+	/// side.
+	pub(crate) fn covering(min: Vec3, max: Vec3, size: f64) -> Result<VoxelGrid, String> {
+		if !size.is_finite() || size <= 0.0 {
+			return Err(format!("Voxel size must be positive, but is {}", size));
+		}
+		let extent = max - min;
+		let dims = [0, 1, 2].map(|i| (extent[i] / size).ceil().max(1.0) + 2.0);
+		let count = dims.iter().product::<f64>();
+		if count > MAX_VOXELS as f64 {
+			return Err(format!(
+				"A voxel size of {} needs {} voxels, more than the {} allowed",
+				size, count, MAX_VOXELS
+			));
+		}
+		Ok(VoxelGrid {
+			dims: dims.map(|d| d as u32),
+			origin: min - Vec3::new(size, size, size),
+			size,
+			bits: vec![0; (count as usize).div_ceil(8)],
+		})
+	}
+
+	pub(crate) fn dims(&self) -> [usize; 3] {
+		self.dims.map(|d| d as usize)
+	}
+
+	pub(crate) fn index(&self, [x, y, z]: [usize; 3]) -> usize {
+		let [nx, ny, _] = self.dims();
+		x + nx * (y + ny * z)
+	}
+
+	/// Center of a voxel.
+	pub(crate) fn center(&self, voxel: [usize; 3]) -> Vec3 {
+		self.origin + Vec3::new(voxel[0] as f64 + 0.5, voxel[1] as f64 + 0.5, voxel[2] as f64 + 0.5) * self.size
+	}
+
+	pub(crate) fn get(&self, voxel: [usize; 3]) -> bool {
+		let i = self.index(voxel);
+		self.bits[i >> 3] & (1 << (i & 7)) != 0
+	}
+
+	pub(crate) fn set(&mut self, voxel: [usize; 3]) {
+		let i = self.index(voxel);
+		self.bits[i >> 3] |= 1 << (i & 7);
+	}
+
+	fn count(&self) -> u64 {
+		self.bits.iter().map(|b| b.count_ones() as u64).sum()
+	}
+
+	/// -1 at the centers of filled voxels and 1 at empty ones, with an empty
+	/// layer around the grid so that the surface is closed.
+	fn samples(&self) -> ScalarGrid {
+		let [nx, ny, nz] = self.dims();
+		let mut samples = ScalarGrid {
+			dims: [nx + 2, ny + 2, nz + 2],
+			origin: self.center([0, 0, 0]) - Vec3::new(self.size, self.size, self.size),
+			spacing: self.size,
+			values: vec![1.0; (nx + 2) * (ny + 2) * (nz + 2)],
+		};
+		for z in 0..nz {
+			for y in 0..ny {
+				for x in 0..nx {
+					if self.get([x, y, z]) {
+						let i = samples.index([x + 1, y + 1, z + 1]);
+						samples.values[i] = -1.0;
+					}
+				}
+			}
+		}
+		samples
+	}
+}
+
+#[wasm_bindgen]
+impl VoxelGrid {
+	/// Number of voxels along x, y and z.
+	pub fn dimensions(&self) -> Box<[u32]> {
+		Box::new(self.dims)
+	}
+
+	/// The minimum corner of the grid.
+	pub fn origin(&self) -> Box<[f64]> {
+		Box::new([self.origin.x, self.origin.y, self.origin.z])
+	}
+
+	/// Edge length of a voxel.
+	#[wasm_bindgen(js_name = "voxelSize")]
+	pub fn voxel_size(&self) -> f64 {
+		self.size
+	}
+
+	/// One bit per voxel, least significant first, for the voxel at (x, y, z)
+	/// at index x + nx * (y + ny * z).
+	pub fn bits(&self) -> Box<[u8]> {
+		self.bits.clone().into_boxed_slice()
+	}
+
+	/// Whether the voxel at (x, y, z) is filled. False outside the grid.
+	#[wasm_bindgen(js_name = "isFilled")]
+	pub fn is_filled(&self, x: u32, y: u32, z: u32) -> bool {
+		x < self.dims[0] && y < self.dims[1] && z < self.dims[2] && self.get([x, y, z].map(|i| i as usize))
+	}
+
+	#[wasm_bindgen(js_name = "filledCount")]
+	pub fn filled_count(&self) -> f64 {
+		self.count() as f64
+	}
+
+	/// Total volume of the filled voxels.
+	pub fn volume(&self) -> f64 {
+		self.count() as f64 * self.size.powi(3)
+	}
+
+	/// A closed surface around the filled voxels, through the middle of the
+	/// faces between filled and empty ones, which bevels the voxels' edges.
+	/// In the layout `parseSTLMesh` produces.
+	#[wasm_bindgen(js_name = "toMesh")]
+	pub fn to_mesh(&self) -> MeshBuffers {
+		contour(&self.samples(), 0.0)
+	}
+}
+
+/// Separating axis test of a triangle against an axis-aligned box
+/// (Akenine-Möller, "Fast 3D Triangle-Box Overlap Testing").
+fn triangle_overlaps_box(center: Vec3, half: f64, tri: [Vec3; 3]) -> bool {
+	let v = tri.map(|p| p - center);
+	let edges = [v[1] - v[0], v[2] - v[1], v[0] - v[2]];
+	let separated_on = |axis: Vec3| {
+		let p = v.map(|p| p.dot(axis));
+		let r = half * (axis.x.abs() + axis.y.abs() + axis.z.abs());
+		p[0].min(p[1]).min(p[2]) > r || p[0].max(p[1]).max(p[2]) < -r
+	};
+	let units = [
+		Vec3::new(1.0, 0.0, 0.0),
+		Vec3::new(0.0, 1.0, 0.0),
+		Vec3::new(0.0, 0.0, 1.0),
+	];
+	for e in edges {
+		for u in units {
+			if separated_on(u.cross(e)) {
+				return false;
+			}
+		}
+	}
+	!units.iter().any(|&u| separated_on(u)) && !separated_on(edges[0].cross(edges[1]))
+}
+
+fn fill_surface(mesh: &Mesh, grid: &mut VoxelGrid) {
+	let dims = grid.dims();
+	let (origin, size) = (grid.origin, grid.size);
+	for t in 0..mesh.triangles.len() {
+		let tri = mesh.corners(t);
+		let lo = tri[0].min(tri[1]).min(tri[2]);
+		let hi = tri[0].max(tri[1]).max(tri[2]);
+		let range = |i: usize| {
+			let first = ((lo[i] - origin[i]) / size).floor().max(0.0) as usize;
+			let last = ((hi[i] - origin[i]) / size).floor() as usize;
+			first..=last.min(dims[i] - 1)
+		};
+		for z in range(2) {
+			for y in range(1) {
+				for x in range(0) {
+					if triangle_overlaps_box(grid.center([x, y, z]), size / 2.0, tri) {
+						grid.set([x, y, z]);
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Where the triangles cross each column of voxel centers along `axis`, with
+/// the sign of the crossing: +1 where the surface faces along the axis.
+///
+/// A column passing exactly through an edge or vertex is counted for one of
+/// the triangles sharing it, by the top-left rule used in rasterization, so
+/// crossings are neither lost nor doubled.
+fn column_crossings(mesh: &Mesh, grid: &VoxelGrid, axis: usize) -> Vec<Vec<(f64, i8)>> {
+	let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+	let dims = grid.dims();
+	let mut columns = vec![Vec::new(); dims[u] * dims[v]];
+	for t in 0..mesh.triangles.len() {
+		let mut p = mesh.corners(t);
+		let area = orient2d(p[0][u], p[0][v], p[1][u], p[1][v], p[2][u], p[2][v]);
+		if area == 0.0 {
+			continue;
+		}
+		let sign = if area > 0.0 { 1 } else { -1 };
+		if area < 0.0 {
+			p.swap(1, 2);
+		}
+		let column_range = |i: usize| {
+			let lo = p[0][i].min(p[1][i]).min(p[2][i]);
+			let hi = p[0][i].max(p[1][i]).max(p[2][i]);
+			let first = ((lo - grid.origin[i]) / grid.size - 0.5).ceil().max(0.0) as usize;
+			let last = ((hi - grid.origin[i]) / grid.size - 0.5).floor();
+			first..(last + 1.0).max(0.0).min(dims[i] as f64) as usize
+		};
+		// Whether an edge from a to b owns the points on it
+		let top_left = |a: Vec3, b: Vec3| b[v] < a[v] || (b[v] == a[v] && b[u] < a[u]);
+		for j in column_range(v) {
+			let cv = grid.origin[v] + (j as f64 + 0.5) * grid.size;
+			for i in column_range(u) {
+				let cu = grid.origin[u] + (i as f64 + 0.5) * grid.size;
+				let mut w = [0.0; 3];
+				let mut inside = true;
+				for k in 0..3 {
+					let (a, b) = (p[(k + 1) % 3], p[(k + 2) % 3]);
+					w[k] = orient2d(a[u], a[v], b[u], b[v], cu, cv);
+					if w[k] < 0.0 || (w[k] == 0.0 && !top_left(a, b)) {
+						inside = false;
+						break;
+					}
+				}
+				if inside {
+					let depth = (w[0] * p[0][axis] + w[1] * p[1][axis] + w[2] * p[2][axis]) / (w[0] + w[1] + w[2]);
+					columns[i + dims[u] * j].push((depth, sign));
+				}
+			}
+		}
+	}
+	columns
+}
+
+/// Calls `inside` for each voxel along `axis` whose center the column's
+/// crossings put inside: by parity, or with `winding` by a nonzero winding
+/// number.
+fn fill_columns(mesh: &Mesh, grid: &VoxelGrid, axis: usize, winding: bool, mut inside: impl FnMut([usize; 3])) {
+	let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+	let dims = grid.dims();
+	for (c, mut crossings) in column_crossings(mesh, grid, axis).into_iter().enumerate() {
+		if crossings.is_empty() {
+			continue;
+		}
+		crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+		let mut next = 0;
+		let mut count = 0i32;
+		for k in 0..dims[axis] {
+			let depth = grid.origin[axis] + (k as f64 + 0.5) * grid.size;
+			while next < crossings.len() && crossings[next].0 < depth {
+				// Entering through a surface facing against the axis
+				count -= crossings[next].1 as i32;
+				next += 1;
+			}
+			let filled = if winding { count != 0 } else { count % 2 != 0 };
+			if filled {
+				let mut voxel = [0; 3];
+				voxel[axis] = k;
+				voxel[u] = c % dims[u];
+				voxel[v] = c / dims[u];
+				inside(voxel);
+			}
+		}
+	}
+}
+
+pub(crate) fn voxelize(mesh: &Mesh, size: f64, fill: VoxelFill) -> Result<VoxelGrid, String> {
+	let (min, max) = mesh.bounds();
+	let mut grid = VoxelGrid::covering(min, max, size)?;
+	match fill {
+		VoxelFill::Surface => {}
+		VoxelFill::Parity => {
+			let mut votes = vec![0u8; grid.dims().iter().product()];
+			for axis in 0..3 {
+				fill_columns(mesh, &grid, axis, false, |voxel| votes[grid.index(voxel)] += 1);
+			}
+			for (i, &n) in votes.iter().enumerate() {
+				if n >= 2 {
+					grid.bits[i >> 3] |= 1 << (i & 7);
+				}
+			}
+		}
+		VoxelFill::Winding => {
+			let mut filled = Vec::new();
+			fill_columns(mesh, &grid, 2, true, |voxel| filled.push(voxel));
+			filled.into_iter().for_each(|voxel| grid.set(voxel));
+		}
+	}
+	fill_surface(mesh, &mut grid);
+	Ok(grid)
+}
+
+pub(crate) fn voxelize_impl(
+	vertices: &[f32],
+	v_indices: &[u32],
+	voxel_size: f64,
+	fill: VoxelFill,
+) -> Result<VoxelGrid, String> {
+	let mesh = Mesh::from_buffers(vertices, v_indices)?;
+	if mesh.triangles.is_empty() {
+		return Err(String::from("Mesh has no triangles"));
+	}
+	voxelize(&mesh, voxel_size, fill)
+}
+
+/// Convert a mesh from `parseSTLMesh` into cubes of edge `voxelSize`. The grid
+/// has an empty layer of voxels around the mesh.
+#[wasm_bindgen(js_name = "voxelize")]
+pub fn voxelize_export(
+	vertices: &[f32],
+	v_indices: &[u32],
+	voxel_size: f64,
+	fill: VoxelFill,
+) -> Result<VoxelGrid, JsValue> {
+	voxelize_impl(vertices, v_indices, voxel_size, fill).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn box_fills() {
+		// Faces of the box fall inside the voxels, not between them
+		let mesh = Mesh::tessellated_cube(Vec3::new(0.1, 0.1, 0.1), 1.8, 3);
+		let surface = voxelize(&mesh, 0.5, VoxelFill::Surface).unwrap();
+		assert_eq!(surface.dims, [6, 6, 6]);
+		assert_eq!(surface.count(), 64 - 8);
+		assert!(!surface.is_filled(0, 0, 0) && surface.is_filled(1, 1, 1) && !surface.is_filled(2, 2, 2));
+		assert!(!surface.is_filled(6, 0, 0));
+		for fill in [VoxelFill::Parity, VoxelFill::Winding] {
+			let solid = voxelize(&mesh, 0.5, fill).unwrap();
+			assert_eq!(solid.count(), 64, "{:?}", fill);
+			assert_eq!(solid.volume(), 8.0);
+		}
+
+		// Grid-aligned, so that columns run through edges and vertices
+		let mesh = Mesh::tessellated_cube(Vec3::new(0.0, 0.0, 0.0), 2.0, 4);
+		let solid = voxelize(&mesh, 0.25, VoxelFill::Winding).unwrap();
+		let inner = (0..8).flat_map(|z| (0..8).flat_map(move |y| (0..8).map(move |x| [x + 1, y + 1, z + 1])));
+		assert!(inner.clone().all(|v| solid.get(v)));
+		let parity = voxelize(&mesh, 0.25, VoxelFill::Parity).unwrap();
+		assert!(inner.clone().all(|v| parity.get(v)));
+
+		// Parity survives a missing triangle
+		let mut open = Mesh::tessellated_cube(Vec3::new(0.1, 0.1, 0.1), 1.8, 3);
+		open.triangles.remove(7);
+		let (vertices, _, v_indices, _) = open.parsed();
+		let solid = voxelize_impl(&vertices, &v_indices, 0.5, VoxelFill::Parity).unwrap();
+		assert_eq!(solid.filled_count(), 64.0);
+		assert!(voxelize_impl(&vertices, &v_indices, 0.0, VoxelFill::Parity).is_err());
+		assert!(voxelize_impl(&vertices, &v_indices, 1e-4, VoxelFill::Parity).is_err());
+	}
+}